@@ -0,0 +1,70 @@
+//! Products of the default network, 784 inputs into 800 hidden values, for a
+//! batch of 16. Run with `cargo bench --bench matrix`.
+
+use autoencoder::{random, Matrix};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rayon::prelude::*;
+
+const INPUTS: usize = 784;
+const HIDDEN: usize = 800;
+const BATCH: usize = 16;
+
+/// The product before the flat buffer, rows of nested vectors summed down
+/// the columns of `right`.
+fn nested_multiply(left: &[Vec<f64>], right: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    left.par_iter()
+        .map(|row| {
+            (0..right[0].len())
+                .map(|j| (0..row.len()).map(|k| row[k] * right[k][j]).sum())
+                .collect()
+        })
+        .collect()
+}
+
+fn multiply(c: &mut Criterion) {
+    let mut rng = random::rng(Some(1), random::Stream::Initialization);
+    let weights = Matrix::<f64>::random(HIDDEN, INPUTS, &mut rng);
+    let inputs = Matrix::<f64>::random(INPUTS, BATCH, &mut rng);
+    let deltas = Matrix::<f64>::random(HIDDEN, BATCH, &mut rng);
+
+    let mut group = c.benchmark_group("multiply");
+
+    // Forward pass, weights by inputs
+    let (nested_weights, nested_inputs) = (weights.to_rows(), inputs.to_rows());
+    group.bench_function("nested", |b| {
+        b.iter(|| nested_multiply(black_box(&nested_weights), black_box(&nested_inputs)))
+    });
+    group.bench_function("blocked", |b| {
+        b.iter(|| black_box(&weights).multiply(black_box(&inputs)))
+    });
+
+    // Backward pass, the transposed weights read through their strides
+    let transposed_weights = weights.transpose();
+    let (nested_transposed, nested_deltas) = (transposed_weights.to_rows(), deltas.to_rows());
+    group.bench_function("nested transposed", |b| {
+        b.iter(|| nested_multiply(black_box(&nested_transposed), black_box(&nested_deltas)))
+    });
+    group.bench_function("blocked transposed", |b| {
+        b.iter(|| black_box(&transposed_weights).multiply(black_box(&deltas)))
+    });
+
+    // Weight gradients, deltas by the inputs stored by columns
+    let transposed_inputs = inputs.transpose();
+    let nested_transposed_inputs = transposed_inputs.to_rows();
+    group.bench_function("nested gradients", |b| {
+        b.iter(|| {
+            nested_multiply(
+                black_box(&nested_deltas),
+                black_box(&nested_transposed_inputs),
+            )
+        })
+    });
+    group.bench_function("columns gradients", |b| {
+        b.iter(|| black_box(&deltas).multiply(black_box(&transposed_inputs)))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, multiply);
+criterion_main!(benches);
@@ -3,41 +3,196 @@
  *
  * https://github.com/mathletedev/rust-ml/
  **/
-use std::f64::consts::E;
+use std::f64::consts::{E, PI};
 
-#[derive(Clone)]
-pub struct Activation {
-    pub function: fn(f64) -> f64,
-    pub derivative: fn(f64) -> f64,
+use serde::{Deserialize, Serialize};
+
+use super::{float::Float, matrix::Matrix};
+
+/// Activation applied to the pre-activation values `z = W * x + b` of a layer.
+/// All derivatives are taken with respect to `z`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Activation {
+    Identity,
+    Sigmoid,
+    Relu,
+    LeakyRelu,
+    Tanh,
+    Elu,
+    Gelu,
+    Softmax,
 }
 
 // Static functions for the identity activation
-fn identity_function(x: f64) -> f64 {
+fn identity_function<T: Float>(x: T) -> T {
     x
 }
 
-fn identity_derivative(_: f64) -> f64 {
-    1.0
+fn identity_derivative<T: Float>(_: T) -> T {
+    T::one()
 }
 
 // Static functions for the sigmoid activation
-fn sigmoid_function(x: f64) -> f64 {
-    1.0 / (1.0 + E.powf(-x))
+fn sigmoid_function<T: Float>(x: T) -> T {
+    T::one() / (T::one() + T::of(E).powf(-x))
+}
+
+fn sigmoid_derivative<T: Float>(x: T) -> T {
+    let s = sigmoid_function(x);
+    s * (T::one() - s)
+}
+
+// Static functions for the ReLU activation
+fn relu_function<T: Float>(x: T) -> T {
+    x.max(T::zero())
+}
+
+fn relu_derivative<T: Float>(x: T) -> T {
+    if x > T::zero() {
+        T::one()
+    } else {
+        T::zero()
+    }
+}
+
+// Static functions for the leaky ReLU activation
+const LEAKY_RELU_SLOPE: f64 = 0.01;
+
+fn leaky_relu_function<T: Float>(x: T) -> T {
+    if x > T::zero() {
+        x
+    } else {
+        T::of(LEAKY_RELU_SLOPE) * x
+    }
+}
+
+fn leaky_relu_derivative<T: Float>(x: T) -> T {
+    if x > T::zero() {
+        T::one()
+    } else {
+        T::of(LEAKY_RELU_SLOPE)
+    }
+}
+
+// Static functions for the tanh activation
+fn tanh_function<T: Float>(x: T) -> T {
+    x.tanh()
+}
+
+fn tanh_derivative<T: Float>(x: T) -> T {
+    T::one() - x.tanh().powi(2)
+}
+
+// Static functions for the ELU activation with alpha = 1
+fn elu_function<T: Float>(x: T) -> T {
+    if x > T::zero() {
+        x
+    } else {
+        x.exp() - T::one()
+    }
+}
+
+fn elu_derivative<T: Float>(x: T) -> T {
+    if x > T::zero() {
+        T::one()
+    } else {
+        x.exp()
+    }
+}
+
+// Static functions for the GELU activation, using the tanh approximation
+fn gelu_inner<T: Float>(x: T) -> T {
+    T::of((2.0 / PI).sqrt()) * (x + T::of(0.044715) * x.powi(3))
+}
+
+fn gelu_function<T: Float>(x: T) -> T {
+    T::of(0.5) * x * (T::one() + gelu_inner(x).tanh())
+}
+
+fn gelu_derivative<T: Float>(x: T) -> T {
+    let t = gelu_inner(x).tanh();
+    let inner_derivative =
+        T::of((2.0 / PI).sqrt()) * (T::one() + T::of(3.0 * 0.044715) * x.powi(2));
+    let half = T::of(0.5);
+
+    half * (T::one() + t) + half * x * (T::one() - t * t) * inner_derivative
 }
 
-fn sigmoid_derivative(x: f64) -> f64 {
-    x * (1.0 - x)
+// Softmax works on whole columns, one column per sample
+fn softmax_column<T: Float>(z: &[T]) -> Vec<T> {
+    let max = z.iter().cloned().fold(T::neg_infinity(), T::max);
+    let exps: Vec<T> = z.iter().map(|&x| (x - max).exp()).collect();
+    let sum: T = exps.iter().copied().sum();
+
+    exps.iter().map(|&x| x / sum).collect()
 }
 
-pub const IDENTITY: Activation = Activation {
-    function: identity_function,
-    derivative: identity_derivative,
-};
+/// An element-wise activation function and its derivative.
+type ElementWise<T> = (fn(T) -> T, fn(T) -> T);
+
+pub const IDENTITY: Activation = Activation::Identity;
+pub const SIGMOID: Activation = Activation::Sigmoid;
+pub const RELU: Activation = Activation::Relu;
+pub const LEAKY_RELU: Activation = Activation::LeakyRelu;
+pub const TANH: Activation = Activation::Tanh;
+pub const ELU: Activation = Activation::Elu;
+pub const GELU: Activation = Activation::Gelu;
+pub const SOFTMAX: Activation = Activation::Softmax;
 
-pub const SIGMOID: Activation = Activation {
-    function: sigmoid_function,
-    derivative: sigmoid_derivative,
-};
+impl Activation {
+    /// The element-wise function and derivative, `None` for softmax which
+    /// depends on the whole column.
+    fn element_wise<T: Float>(&self) -> Option<ElementWise<T>> {
+        match self {
+            Activation::Identity => Some((identity_function, identity_derivative)),
+            Activation::Sigmoid => Some((sigmoid_function, sigmoid_derivative)),
+            Activation::Relu => Some((relu_function, relu_derivative)),
+            Activation::LeakyRelu => Some((leaky_relu_function, leaky_relu_derivative)),
+            Activation::Tanh => Some((tanh_function, tanh_derivative)),
+            Activation::Elu => Some((elu_function, elu_derivative)),
+            Activation::Gelu => Some((gelu_function, gelu_derivative)),
+            Activation::Softmax => None,
+        }
+    }
+
+    pub fn apply<T: Float>(&self, z: &Matrix<T>) -> Matrix<T> {
+        match self.element_wise() {
+            Some((function, _)) => z.map(function),
+            None => {
+                let columns: Vec<Vec<T>> = z.columns().iter().map(|c| softmax_column(c)).collect();
+                Matrix::from(columns).transpose()
+            }
+        }
+    }
+
+    /// Back propagates `gradients` with respect to the activated outputs `a`
+    /// to gradients with respect to the pre-activation values `z`.
+    pub fn backward<T: Float>(
+        &self,
+        z: &Matrix<T>,
+        a: &Matrix<T>,
+        gradients: &Matrix<T>,
+    ) -> Matrix<T> {
+        match self.element_wise() {
+            Some((_, derivative)) => z.map(derivative).dot_multiply(gradients),
+            None => {
+                let columns: Vec<Vec<T>> = a
+                    .columns()
+                    .iter()
+                    .zip(gradients.columns().iter())
+                    .map(|(a, g)| {
+                        let dot: T = a.iter().zip(g.iter()).map(|(&a, &g)| a * g).sum();
+                        a.iter()
+                            .zip(g.iter())
+                            .map(|(&a, &g)| a * (g - dot))
+                            .collect()
+                    })
+                    .collect();
+                Matrix::from(columns).transpose()
+            }
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -64,19 +219,81 @@ mod tests {
     fn test_sigmoid_function() {
         // Test sigmoid at a few points
         assert_eq!(sigmoid_function(0.0), 0.5);
-        assert!((sigmoid_function(1.0) - 0.7310585786300049).abs() < 1e-7);
-        assert!((sigmoid_function(-1.0) - 0.2689414213699951).abs() < 1e-7);
+        assert!((sigmoid_function(1.0f64) - 0.7310585786300049).abs() < 1e-7);
+        assert!((sigmoid_function(-1.0f64) - 0.2689414213699951).abs() < 1e-7);
     }
 
     #[test]
     fn test_sigmoid_derivative() {
-        // Test sigmoid derivative using the output of sigmoid function
-        let x = sigmoid_function(0.5);
-        let dx = sigmoid_derivative(x);
+        // Test sigmoid derivative using the pre-activation value
+        let dx = sigmoid_derivative(0.5f64);
         assert!((dx - 0.2350037122015945).abs() < 1e-7);
 
-        let y = sigmoid_function(2.0);
-        let dy = sigmoid_derivative(y);
+        let dy = sigmoid_derivative(2.0f64);
         assert!((dy - 0.10499358540350662).abs() < 1e-7);
     }
+
+    #[test]
+    fn test_relu_family() {
+        assert_eq!(relu_function(-2.0), 0.0);
+        assert_eq!(relu_function(3.0), 3.0);
+        assert_eq!(relu_derivative(-2.0), 0.0);
+        assert_eq!(relu_derivative(3.0), 1.0);
+
+        assert_eq!(leaky_relu_function(-2.0), -0.02);
+        assert_eq!(leaky_relu_derivative(-2.0), 0.01);
+
+        assert!((elu_function(-1.0) - (E.powf(-1.0) - 1.0)).abs() < 1e-12);
+        assert_eq!(elu_derivative(2.0), 1.0);
+    }
+
+    #[test]
+    fn test_derivatives_match_finite_differences() {
+        let h = 1e-6;
+        let activations = [IDENTITY, SIGMOID, RELU, LEAKY_RELU, TANH, ELU, GELU];
+
+        for activation in activations {
+            let (function, derivative) = activation.element_wise::<f64>().unwrap();
+            for x in [-1.5, -0.3, 0.4, 2.0] {
+                let numeric = (function(x + h) - function(x - h)) / (2.0 * h);
+                assert!(
+                    (numeric - derivative(x)).abs() < 1e-5,
+                    "{:?} at {}",
+                    activation,
+                    x
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_softmax_columns_sum_to_one() {
+        let z = Matrix::from(vec![
+            vec![1.0, 1000.0],
+            vec![2.0, 1000.0],
+            vec![3.0, 1000.0],
+        ]);
+        let a = SOFTMAX.apply(&z);
+
+        for column in a.columns() {
+            assert!((column.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        }
+        assert!(a[(2, 0)] > a[(1, 0)]);
+        assert!((a[(0, 1)] - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_softmax_backward() {
+        let z: Matrix = Matrix::from(vec![vec![0.5], vec![-0.2], vec![0.1]]);
+        let g = Matrix::from(vec![vec![1.0], vec![0.0], vec![0.0]]);
+        let a = SOFTMAX.apply(&z);
+        let backward = SOFTMAX.backward(&z, &a, &g);
+
+        // d a_0 / d z_j = a_0 * (delta_0j - a_j)
+        for j in 0..3 {
+            let delta = if j == 0 { 1.0 } else { 0.0 };
+            let expected = a[(0, 0)] * (delta - a[(j, 0)]);
+            assert!((backward[(j, 0)] - expected).abs() < 1e-12);
+        }
+    }
 }
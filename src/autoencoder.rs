@@ -0,0 +1,458 @@
+use std::path::Path;
+
+use autometrics::autometrics;
+use rand::prelude::SliceRandom;
+use rand::Rng;
+
+use super::{
+    activations::Activation,
+    error::{Error, Result},
+    float::Float,
+    layers::Mode,
+    losses::Loss,
+    matrix::Matrix,
+    model_file::{ModelData, ModelFile, ModelFileError, NetworkData, TrainingMetadata},
+    network::{self, Network},
+    optimizers::Optimizer,
+    progress::Progress,
+};
+
+pub struct Autoencoder<T: Float = f64> {
+    encoder: Network<T>,
+    decoder: Network<T>,
+    metadata: TrainingMetadata,
+}
+
+#[autometrics]
+impl Autoencoder {
+    /// Builds an encoder `input_size -> hidden_layers -> bottleneck` and the
+    /// mirrored decoder `bottleneck -> hidden_layers (reversed) -> input_size`.
+    /// Both halves take one activation per layer after their input layer.
+    pub fn new(
+        input_size: usize,
+        hidden_layers: Vec<usize>,
+        bottleneck: usize,
+        optimizer: Box<dyn Optimizer>,
+        encoder_activations: Vec<Activation>,
+        decoder_activations: Vec<Activation>,
+        loss: Box<dyn Loss>,
+    ) -> Autoencoder {
+        Autoencoder::with_rng(
+            input_size,
+            hidden_layers,
+            bottleneck,
+            optimizer,
+            encoder_activations,
+            decoder_activations,
+            loss,
+            &mut rand::thread_rng(),
+        )
+    }
+}
+
+#[autometrics]
+impl<T: Float> Autoencoder<T> {
+    /// Like `new`, drawing the initial weights and biases from `rng`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_rng(
+        input_size: usize,
+        hidden_layers: Vec<usize>,
+        bottleneck: usize,
+        optimizer: Box<dyn Optimizer<T>>,
+        encoder_activations: Vec<Activation>,
+        decoder_activations: Vec<Activation>,
+        loss: Box<dyn Loss<T>>,
+        rng: &mut impl Rng,
+    ) -> Autoencoder<T> {
+        let mut encoder_layers = vec![input_size];
+        encoder_layers.extend(hidden_layers.iter());
+        encoder_layers.push(bottleneck);
+
+        let decoder_layers: Vec<usize> = encoder_layers.iter().rev().cloned().collect();
+
+        Autoencoder {
+            encoder: Network::with_rng(
+                encoder_layers,
+                optimizer.state().into_optimizer(),
+                encoder_activations,
+                loss.config().build(),
+                rng,
+            ),
+            decoder: Network::with_rng(decoder_layers, optimizer, decoder_activations, loss, rng),
+            metadata: TrainingMetadata::default(),
+        }
+    }
+
+    /// Pairs any encoder with a decoder taking its outputs, e.g.
+    /// convolutions with transposed convolutions. Panics if the sizes differ.
+    pub fn from_networks(encoder: Network<T>, decoder: Network<T>) -> Autoencoder<T> {
+        if encoder.output_size() != decoder.input_size()
+            || decoder.output_size() != encoder.input_size()
+        {
+            panic!("Decoder does not mirror the encoder");
+        }
+
+        Autoencoder {
+            encoder,
+            decoder,
+            metadata: TrainingMetadata::default(),
+        }
+    }
+
+    pub fn encode(&self, inputs: &[f64]) -> Result<Vec<f64>> {
+        self.encoder.try_predict(inputs)
+    }
+
+    pub fn decode(&self, code: &[f64]) -> Result<Vec<f64>> {
+        self.decoder.try_predict(code)
+    }
+
+    pub fn reconstruct(&self, inputs: &[f64]) -> Result<Vec<f64>> {
+        self.decode(&self.encode(inputs)?)
+    }
+
+    pub fn back_propogate(&mut self, inputs: Vec<f64>) -> Result<f64> {
+        self.back_propogate_batch(network::batch(&[&inputs]))
+    }
+
+    /// Trains on a block of inputs, one sample per column, using the inputs
+    /// themselves as targets. Returns the loss of the batch.
+    pub fn back_propogate_batch(&mut self, inputs: Matrix<T>) -> Result<f64> {
+        let code = self.encoder.try_feed_forward_batch(inputs.clone())?;
+        let outputs = self.decoder.try_feed_forward_batch(code)?;
+
+        // The penalty of the encoder weights that produced the code
+        let encoder_penalty = self.encoder.penalty();
+        let (loss, code_errors) = self.decoder.propagate_loss(&outputs, &inputs)?;
+        self.encoder.propagate_errors(code_errors)?;
+
+        Ok(loss + encoder_penalty)
+    }
+
+    /// Trains one pass over `inputs` and returns the mean loss of the batches.
+    pub fn train(&mut self, inputs: &[&Vec<f64>], batch_size: usize) -> Result<f64> {
+        self.train_from(inputs, batch_size, 0, |_, _| Ok(()))
+    }
+
+    /// Trains on the batches from `first_batch` on, see `Network::train_from`.
+    pub fn train_from(
+        &mut self,
+        inputs: &[&Vec<f64>],
+        batch_size: usize,
+        first_batch: usize,
+        mut on_batch: impl FnMut(&Autoencoder<T>, usize) -> Result<()>,
+    ) -> Result<f64> {
+        let batch_size = batch_size.max(1);
+        let mut progress = Progress::new(inputs.len().saturating_sub(first_batch * batch_size));
+        let mut total_loss = 0.0;
+        let mut batches = 0;
+
+        self.set_mode(Mode::Training);
+        let trained = (|| -> Result<()> {
+            for (index, batch) in inputs.chunks(batch_size).enumerate().skip(first_batch) {
+                let loss = self.back_propogate_batch(network::batch(batch))?;
+
+                log::debug!("Batch {} loss: {}", index, loss);
+                total_loss += loss;
+                batches += 1;
+
+                progress.advance(batch.len(), loss);
+                on_batch(self, index + 1)?;
+            }
+            Ok(())
+        })();
+        // Also on errors, see `Network::train_from`
+        self.set_mode(Mode::Evaluation);
+        trained?;
+        progress.finish();
+
+        let mean_loss = total_loss / batches.max(1) as f64;
+        log::info!("Completed training, loss: {:?}", mean_loss);
+
+        Ok(mean_loss)
+    }
+
+    /// Mean squared reconstruction error over `inputs`.
+    pub fn validate(&self, inputs: &[Vec<f64>]) -> Result<f64> {
+        let mut total_error = 0.0;
+        for input in inputs {
+            total_error += reconstruction_error(input, &self.reconstruct(input)?);
+        }
+
+        let mean_error = total_error / inputs.len().max(1) as f64;
+
+        log::info!("Mean reconstruction error: {:?}", mean_error);
+
+        Ok(mean_error)
+    }
+
+    pub fn model(&self) -> String {
+        format!("ae-{}-{}", self.encoder.model(), self.decoder.model())
+    }
+
+    pub fn encoder(&self) -> &Network<T> {
+        &self.encoder
+    }
+
+    pub fn decoder(&self) -> &Network<T> {
+        &self.decoder
+    }
+
+    /// The halves, e.g. to set their regularization and clipping. The
+    /// clipping norm applies to each half separately.
+    pub fn networks_mut(&mut self) -> [&mut Network<T>; 2] {
+        [&mut self.encoder, &mut self.decoder]
+    }
+
+    pub fn learning_rate(&self) -> f64 {
+        self.decoder.optimizer().learning_rate()
+    }
+
+    /// Sets the learning rate of the optimizers of both halves.
+    pub fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.encoder
+            .optimizer_mut()
+            .set_learning_rate(learning_rate);
+        self.decoder
+            .optimizer_mut()
+            .set_learning_rate(learning_rate);
+    }
+
+    /// Sets the mode of both halves, see `Network::set_mode`.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.encoder.set_mode(mode);
+        self.decoder.set_mode(mode);
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.encoder.input_size()
+    }
+
+    pub fn metadata(&self) -> &TrainingMetadata {
+        &self.metadata
+    }
+
+    pub fn metadata_mut(&mut self) -> &mut TrainingMetadata {
+        &mut self.metadata
+    }
+
+    /// Rebuilds an autoencoder from the saved data of both halves.
+    pub fn from_data(
+        encoder: NetworkData,
+        decoder: NetworkData,
+    ) -> std::result::Result<Autoencoder<T>, ModelFileError> {
+        Ok(Autoencoder {
+            encoder: Network::from_data(encoder)?,
+            decoder: Network::from_data(decoder)?,
+            metadata: TrainingMetadata::default(),
+        })
+    }
+
+    pub fn from_file(file: impl AsRef<Path>) -> Result<Autoencoder<T>> {
+        let model_file = ModelFile::read(file)?;
+
+        match model_file.model {
+            ModelData::Autoencoder { encoder, decoder } => {
+                let mut autoencoder = Autoencoder::from_data(encoder, decoder)?;
+                autoencoder.metadata = model_file.metadata;
+                Ok(autoencoder)
+            }
+            model => Err(Error::ModelFile(ModelFileError::Incompatible {
+                expected: String::from("autoencoder"),
+                found: String::from(model.kind()),
+            })),
+        }
+    }
+
+    /// Both halves for saving, with their values converted to `f64`.
+    pub fn to_data(&self) -> ModelData {
+        ModelData::Autoencoder {
+            encoder: self.encoder.to_data(),
+            decoder: self.decoder.to_data(),
+        }
+    }
+
+    pub fn save(&self, file: impl AsRef<Path>) -> Result<()> {
+        ModelFile::new(self.to_data(), self.metadata.clone()).write(file)?;
+
+        Ok(())
+    }
+
+    /// Restores the layers and optimizer state of both halves from snapshots
+    /// taken with `Network::to_data`, keeping the metadata.
+    pub fn restore(&mut self, encoder: NetworkData, decoder: NetworkData) -> Result<()> {
+        self.encoder.restore(encoder)?;
+        self.decoder.restore(decoder)
+    }
+
+    /// Loads the weights and optimizer state of a saved autoencoder whose
+    /// halves are compatible with these, see `Network::load`.
+    pub fn load(&mut self, file: impl AsRef<Path>) -> Result<()> {
+        let saved = Autoencoder::<T>::from_file(file)?;
+
+        // Neither half changes unless both fit
+        self.encoder.check_compatible(&saved.encoder)?;
+        self.decoder.check_compatible(&saved.decoder)?;
+
+        self.encoder.replace(saved.encoder)?;
+        self.decoder.replace(saved.decoder)?;
+        self.metadata = saved.metadata;
+
+        Ok(())
+    }
+
+    pub fn run_training_epoch(
+        &mut self,
+        train_inputs: &[Vec<f64>],
+        val_inputs: &[Vec<f64>],
+        batch_size: usize,
+        rng: &mut impl Rng,
+    ) -> Result<f64> {
+        self.resume_training_epoch(train_inputs, val_inputs, batch_size, rng, 0, |_, _| Ok(()))
+    }
+
+    /// Continues an epoch from `first_batch`, see
+    /// `Network::resume_training_epoch`.
+    pub fn resume_training_epoch(
+        &mut self,
+        train_inputs: &[Vec<f64>],
+        val_inputs: &[Vec<f64>],
+        batch_size: usize,
+        rng: &mut impl Rng,
+        first_batch: usize,
+        on_batch: impl FnMut(&Autoencoder<T>, usize) -> Result<()>,
+    ) -> Result<f64> {
+        let mut inputs_shuffled: Vec<&Vec<f64>> = train_inputs.iter().collect();
+        inputs_shuffled.shuffle(rng);
+
+        let loss = self.train_from(&inputs_shuffled, batch_size, first_batch, on_batch)?;
+
+        log::info!(
+            "Autoencoder trained with training data, epoch loss: {:?}",
+            loss
+        );
+
+        let error = self.validate(val_inputs)?;
+
+        self.metadata.epochs += 1;
+        self.metadata.loss = Some(loss);
+        self.metadata.reconstruction_error = Some(error);
+
+        Ok(error)
+    }
+}
+
+#[autometrics]
+pub fn reconstruction_error(inputs: &[f64], outputs: &[f64]) -> f64 {
+    inputs
+        .iter()
+        .zip(outputs.iter())
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f64>()
+        / inputs.len().max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::activations::{RELU, SIGMOID};
+    use crate::losses::{BinaryCrossEntropy, MeanSquaredError};
+    use crate::optimizers::Sgd;
+
+    use super::*;
+
+    #[test]
+    fn test_autoencoder_shapes() {
+        let autoencoder = Autoencoder::new(
+            6,
+            vec![4],
+            2,
+            Box::new(Sgd::new(0.1)),
+            vec![RELU, SIGMOID],
+            vec![RELU, SIGMOID],
+            Box::new(BinaryCrossEntropy),
+        );
+
+        let inputs = vec![0.1, 0.2, 0.3, 0.4, 0.5, 0.6];
+        let code = autoencoder.encode(&inputs).unwrap();
+        assert_eq!(code.len(), 2);
+
+        let decoded = autoencoder.decode(&code).unwrap();
+        assert_eq!(decoded.len(), 6);
+
+        assert_eq!(autoencoder.reconstruct(&inputs).unwrap(), decoded);
+        assert!(matches!(
+            autoencoder.encode(&[0.1, 0.2]),
+            Err(Error::InvalidLength { expected: 6, .. })
+        ));
+        assert_eq!(autoencoder.model(), "ae-6-4-2-2-4-6");
+    }
+
+    #[test]
+    fn test_stopped_training_returns_to_evaluation() {
+        let layers = |sizes: &[usize], dropout: &[f64]| {
+            network::dense_layers::<f64>(
+                sizes,
+                &[SIGMOID],
+                dropout,
+                None,
+                &mut crate::random::rng(Some(1), crate::random::Stream::Initialization),
+            )
+        };
+        let mut autoencoder = Autoencoder::from_networks(
+            Network::from_layers(
+                layers(&[4, 2], &[0.5]),
+                Box::new(Sgd::new(0.1)),
+                Box::new(MeanSquaredError),
+            ),
+            Network::from_layers(
+                layers(&[2, 4], &[]),
+                Box::new(Sgd::new(0.1)),
+                Box::new(MeanSquaredError),
+            ),
+        );
+        let inputs = vec![1.0, 0.0, 1.0, 0.0];
+
+        let stopped = autoencoder.train_from(&[&inputs], 1, 0, |_, _| Err(Error::EmptyResult));
+        assert!(stopped.is_err());
+        assert!(autoencoder.train(&[&vec![1.0]], 1).is_err());
+
+        for half in [autoencoder.encoder(), autoencoder.decoder()] {
+            assert_eq!(half.mode(), Mode::Evaluation);
+        }
+    }
+
+    #[test]
+    fn test_reconstruction_error() {
+        assert_eq!(reconstruction_error(&[1.0, 0.0], &[1.0, 0.0]), 0.0);
+        assert_eq!(reconstruction_error(&[1.0, 0.0], &[0.0, 1.0]), 1.0);
+        assert_eq!(reconstruction_error(&[0.5, 0.5], &[0.0, 0.5]), 0.125);
+    }
+
+    #[test]
+    fn test_training_reduces_reconstruction_error() {
+        let mut autoencoder = Autoencoder::new(
+            4,
+            vec![],
+            2,
+            Box::new(Sgd::new(2.0)),
+            vec![SIGMOID],
+            vec![SIGMOID],
+            Box::new(MeanSquaredError),
+        );
+
+        let inputs = vec![
+            vec![1.0, 0.0, 1.0, 0.0],
+            vec![0.0, 1.0, 0.0, 1.0],
+            vec![1.0, 1.0, 0.0, 0.0],
+        ];
+
+        let initial_error = autoencoder.validate(&inputs).unwrap();
+        for _ in 0..200 {
+            for input in inputs.iter() {
+                autoencoder.back_propogate(input.clone()).unwrap();
+            }
+        }
+
+        assert!(autoencoder.validate(&inputs).unwrap() < initial_error);
+    }
+}
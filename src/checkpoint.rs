@@ -0,0 +1,204 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    early_stopping::BestState,
+    model_file::{self, ModelFile, ModelFileError},
+    model_format::{self, ModelFormat},
+    random::RngState,
+    schedules::LearningRateSchedule,
+};
+
+/// Subdirectory of the output directory the checkpoints are written to, so
+/// they are not mistaken for trained models.
+pub const CHECKPOINT_DIRECTORY: &str = "checkpoints";
+
+/// How often a run writes a checkpoint, never when both are `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Checkpointing {
+    pub epochs: Option<usize>,
+    pub batches: Option<usize>,
+}
+
+impl Checkpointing {
+    pub fn after_epoch(&self, epochs: usize) -> bool {
+        self.epochs.is_some_and(|every| epochs % every == 0)
+    }
+
+    pub fn after_batch(&self, batches: usize) -> bool {
+        self.batches.is_some_and(|every| batches % every == 0)
+    }
+}
+
+/// Where a run is: the completed epochs and the completed batches of the
+/// next one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    pub epoch: usize,
+    pub batch: usize,
+}
+
+impl Position {
+    /// The start of the epoch after `epoch`, counted from 0.
+    pub fn after_epoch(epoch: usize) -> Position {
+        Position {
+            epoch: epoch + 1,
+            batch: 0,
+        }
+    }
+}
+
+/// Everything needed to continue an interrupted run: the model with its
+/// optimizer state and the generators of its dropout layers, the position in
+/// the run, the shuffling generator as it was at the start of the current
+/// epoch, the learning rate schedule and the best epoch of early stopping.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub model: ModelFile,
+    pub position: Position,
+    pub rng: RngState,
+    pub schedule: LearningRateSchedule,
+    /// `None` without early stopping or before the first epoch ended.
+    #[serde(default)]
+    pub best: Option<BestState<ModelFile>>,
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint in the format given by the extension of `path`,
+    /// replacing any previous one only once it is completely written.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), ModelFileError> {
+        let path = path.as_ref();
+        let format = ModelFormat::from_path(path)?;
+
+        let value = serde_json::to_value(self)
+            .map_err(|error| ModelFileError::Invalid(error.to_string()))?;
+        let bytes = format.encode(value, path)?;
+
+        model_file::write_atomically(path, &bytes)
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Checkpoint, ModelFileError> {
+        let path = path.as_ref();
+
+        let bytes = fs::read(path).map_err(|source| ModelFileError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let value = model_format::decode(bytes, path)?;
+        let checkpoint: Checkpoint =
+            serde_json::from_value(value).map_err(|source| ModelFileError::Corrupt {
+                path: path.to_path_buf(),
+                source,
+            })?;
+
+        checkpoint.model.validate()?;
+        if let Some(best) = &checkpoint.best {
+            best.snapshot.validate()?;
+        }
+
+        Ok(checkpoint)
+    }
+}
+
+/// The most recently written checkpoint of `model` in `directory`, if any.
+pub fn latest(directory: impl AsRef<Path>, model: &str) -> Result<Option<PathBuf>, ModelFileError> {
+    let directory = directory.as_ref();
+    if !directory.is_dir() {
+        return Ok(None);
+    }
+    let io_error = |source| ModelFileError::Io {
+        path: directory.to_path_buf(),
+        source,
+    };
+
+    let prefix = format!("{}.", model);
+    let mut latest: Option<(SystemTime, PathBuf)> = None;
+    for entry in fs::read_dir(directory).map_err(io_error)? {
+        let entry = entry.map_err(io_error)?;
+        let path = entry.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        if !name.starts_with(&prefix) || ModelFormat::from_path(&path).is_err() {
+            continue;
+        }
+
+        let modified = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .map_err(io_error)?;
+        if latest.as_ref().map_or(true, |(time, _)| modified > *time) {
+            latest = Some((modified, path));
+        }
+    }
+
+    Ok(latest.map(|(_, path)| path))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use crate::{
+        activations::SIGMOID,
+        losses::MeanSquaredError,
+        model_file::{ModelData, TrainingMetadata},
+        network::Network,
+        optimizers::Sgd,
+        random::{self, RngState, Stream},
+        schedules::Schedule,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let directory = std::env::temp_dir().join("autoencoder-checkpoint");
+        let network = Network::<f64>::new(
+            vec![2, 3, 2],
+            Box::new(Sgd::new(0.1)),
+            vec![SIGMOID; 2],
+            Box::new(MeanSquaredError),
+        );
+        let mut rng = random::rng(Some(3), Stream::Shuffling);
+        rng.gen::<u64>();
+
+        let model = ModelFile::new(
+            ModelData::Network(network.to_data()),
+            TrainingMetadata::default(),
+        );
+        let checkpoint = Checkpoint {
+            model: model.clone(),
+            position: Position { epoch: 2, batch: 5 },
+            rng: RngState::from(&rng),
+            schedule: LearningRateSchedule::new(Schedule::Exponential { gamma: 0.5 }, 0.1, 1),
+            best: Some(BestState {
+                value: 0.25,
+                snapshot: model,
+                stale_epochs: 1,
+            }),
+        };
+
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(format!("{}.json", network.model()));
+        checkpoint.write(&path).unwrap();
+
+        let read = Checkpoint::read(&path).unwrap();
+        assert_eq!(read.position, checkpoint.position);
+        assert_eq!(read.schedule, checkpoint.schedule);
+        let best = read.best.unwrap();
+        assert_eq!((best.value, best.stale_epochs), (0.25, 1));
+        // The generator continues with the same numbers
+        assert_eq!(read.rng.rng().gen::<u64>(), rng.gen::<u64>());
+
+        assert_eq!(latest(&directory, &network.model()).unwrap(), Some(path));
+        assert_eq!(latest(&directory, "1-2").unwrap(), None);
+    }
+}
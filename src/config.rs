@@ -0,0 +1,776 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ndarray::{Array2, Array3};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{
+    activations::{Activation, RELU, SIGMOID, SOFTMAX},
+    autoencoder::Autoencoder,
+    checkpoint::Checkpointing,
+    data_set::{mnist_data_set, mnist_test_set, DataSet},
+    early_stopping::{EarlyStopping, Metric},
+    error,
+    float::Float,
+    losses::LossConfig,
+    model_format::{ModelFormat, Precision},
+    network::{dense_layers, Network},
+    normalization::Normalization,
+    optimizers::OptimizerConfig,
+    random::{self, Stream},
+    regularization::{GradientClipping, Regularization},
+    schedules::Schedule,
+    session::SessionConfig,
+    trainer::Trainer,
+};
+
+/// Pixels of an MNIST image.
+pub const IMAGE_SIZE: usize = 784;
+/// Digits an MNIST classifier tells apart.
+pub const CLASSES: usize = 10;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("unable to access config file {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("config file {path} is invalid: {message}")]
+    Parse { path: PathBuf, message: String },
+    #[error("unknown config file format for {path}, expected .toml or .json")]
+    UnknownFormat { path: PathBuf },
+    #[error("invalid config: {0}")]
+    Invalid(String),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelKind {
+    #[default]
+    Classifier,
+    Autoencoder,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DataConfig {
+    /// Directory of the MNIST IDX files, e.g. `train-images-idx3-ubyte`,
+    /// each optionally gzipped.
+    pub directory: PathBuf,
+    pub training_set_size: u32,
+    pub val_set_size: u32,
+    pub test_set_size: u32,
+}
+
+impl Default for DataConfig {
+    fn default() -> DataConfig {
+        DataConfig {
+            directory: PathBuf::from("data"),
+            training_set_size: 50_000,
+            val_set_size: 10_000,
+            test_set_size: 10_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchitectureConfig {
+    /// Layer sizes, for an autoencoder from the input down to the bottleneck.
+    /// The decoder mirrors them.
+    pub layers: Option<Vec<usize>>,
+    /// One per layer after the input, for an autoencoder those of the encoder.
+    pub activations: Option<Vec<Activation>>,
+    /// Activations of the decoder, the same as the encoder when not given.
+    pub decoder_activations: Option<Vec<Activation>>,
+    /// One dropout rate per layer but the output layer, applied to its
+    /// outputs while training. For an autoencoder only the encoder drops.
+    pub dropout: Option<Vec<f64>>,
+    /// Normalization between the hidden layers and their activations, in
+    /// both halves of an autoencoder.
+    pub normalization: Option<Normalization>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrainingConfig {
+    pub epochs: usize,
+    pub batch_size: usize,
+    /// Learning rate schedule relative to the rate of the optimizer.
+    pub schedule: Schedule,
+    /// Epochs rising linearly up to the scheduled learning rate.
+    pub warmup_epochs: usize,
+    pub early_stopping: Option<EarlyStopping>,
+    /// How often to write a checkpoint to `<output directory>/checkpoints`.
+    pub checkpoint: Checkpointing,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> TrainingConfig {
+        TrainingConfig {
+            epochs: 10,
+            batch_size: 16,
+            schedule: Schedule::Constant,
+            warmup_epochs: 0,
+            early_stopping: None,
+            checkpoint: Checkpointing::default(),
+        }
+    }
+}
+
+/// Weight penalties and gradient clipping, none unless given.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegularizationConfig {
+    /// Penalties of every layer, unless `layers` gives them per layer.
+    pub l1: f64,
+    pub l2: f64,
+    /// One per layer after the input, for an autoencoder those of the
+    /// encoder. The decoder mirrors them.
+    pub layers: Option<Vec<Regularization>>,
+    pub clip_value: Option<f64>,
+    pub clip_norm: Option<f64>,
+}
+
+impl RegularizationConfig {
+    fn clipping(&self) -> GradientClipping {
+        GradientClipping {
+            value: self.clip_value,
+            norm: self.clip_norm,
+        }
+    }
+
+    /// Sets the penalties of the layers with weights in order, and the
+    /// clipping.
+    fn apply<T: Float>(&self, network: &mut Network<T>, layers: Option<Vec<Regularization>>) {
+        let weighted: Vec<usize> = (0..network.layers().len())
+            .filter(|&i| {
+                let layer = &network.layers()[i];
+                (0..layer.parameters().len()).any(|index| layer.is_weight(index))
+            })
+            .collect();
+
+        for (k, layer) in weighted.into_iter().enumerate() {
+            let regularization = match &layers {
+                Some(layers) => layers[k],
+                None => Regularization::new(self.l1, self.l2),
+            };
+            network.set_layer_regularization(layer, regularization);
+        }
+        network.set_clipping(self.clipping());
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub directory: PathBuf,
+    /// Extension, and so format, of the saved models, e.g. `bin.zst` or `json`.
+    pub format: String,
+}
+
+impl Default for OutputConfig {
+    fn default() -> OutputConfig {
+        OutputConfig {
+            directory: PathBuf::from("./data/networks"),
+            format: String::from("bin.zst"),
+        }
+    }
+}
+
+/// Everything describing a training run, loaded from a TOML or JSON file.
+/// Settings left out fall back to the defaults of the model kind, see
+/// `resolve`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExperimentConfig {
+    pub model: ModelKind,
+    /// Seeds the initialization and the shuffling of the training data,
+    /// random when not given.
+    pub seed: Option<u64>,
+    /// Element type of the network, `f64` unless `f32` is asked for.
+    pub precision: Precision,
+    pub loss: Option<LossConfig>,
+    pub optimizer: Option<OptimizerConfig>,
+    pub data: DataConfig,
+    pub architecture: ArchitectureConfig,
+    pub training: TrainingConfig,
+    pub regularization: RegularizationConfig,
+    pub output: OutputConfig,
+    pub session: SessionConfig,
+}
+
+enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    fn from_path(path: &Path) -> Result<ConfigFormat, ConfigError> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("json") => Ok(ConfigFormat::Json),
+            _ => Err(ConfigError::UnknownFormat {
+                path: path.to_path_buf(),
+            }),
+        }
+    }
+}
+
+impl ExperimentConfig {
+    /// Reads, resolves and validates the config in `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<ExperimentConfig, ConfigError> {
+        let path = path.as_ref();
+        let format = ConfigFormat::from_path(path)?;
+
+        let contents = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let parse_error = |message: String| ConfigError::Parse {
+            path: path.to_path_buf(),
+            message,
+        };
+
+        let config: ExperimentConfig = match format {
+            ConfigFormat::Toml => {
+                toml::from_str(&contents).map_err(|error| parse_error(error.to_string()))?
+            }
+            ConfigFormat::Json => {
+                serde_json::from_str(&contents).map_err(|error| parse_error(error.to_string()))?
+            }
+        };
+
+        let config = config.resolve();
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), ConfigError> {
+        let path = path.as_ref();
+
+        let contents = match ConfigFormat::from_path(path)? {
+            ConfigFormat::Toml => {
+                toml::to_string(self).map_err(|error| ConfigError::Invalid(error.to_string()))?
+            }
+            ConfigFormat::Json => serde_json::to_string_pretty(self)
+                .map_err(|error| ConfigError::Invalid(error.to_string()))?,
+        };
+
+        fs::write(path, contents).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Fills in the architecture, loss and optimizer of the model kind where
+    /// they are not given.
+    pub fn resolve(mut self) -> ExperimentConfig {
+        let architecture = &mut self.architecture;
+
+        let layers = architecture.layers.get_or_insert_with(|| match self.model {
+            ModelKind::Classifier => vec![IMAGE_SIZE, 800, 800, CLASSES],
+            ModelKind::Autoencoder => vec![IMAGE_SIZE, 256, 32],
+        });
+        let connections = layers.len().saturating_sub(1);
+
+        let (hidden, output) = match self.model {
+            ModelKind::Classifier => (SIGMOID, SOFTMAX),
+            ModelKind::Autoencoder => (RELU, SIGMOID),
+        };
+        let activations = architecture.activations.get_or_insert_with(|| {
+            let mut activations = vec![hidden; connections.saturating_sub(1)];
+            activations.push(output);
+            activations
+        });
+        if self.model == ModelKind::Autoencoder && architecture.decoder_activations.is_none() {
+            architecture.decoder_activations = Some(activations.clone());
+        }
+
+        self.loss.get_or_insert(match self.model {
+            ModelKind::Classifier => LossConfig::CrossEntropy,
+            ModelKind::Autoencoder => LossConfig::BinaryCrossEntropy,
+        });
+        self.optimizer.get_or_insert(match self.model {
+            ModelKind::Classifier => OptimizerConfig::Sgd { learning_rate: 0.1 },
+            ModelKind::Autoencoder => OptimizerConfig::Adam {
+                learning_rate: 0.001,
+            },
+        });
+
+        self
+    }
+
+    /// The config of another model kind, keeping the data, training,
+    /// session and output settings. The architecture, loss, optimizer and
+    /// per-layer penalties belong to the kind, so they are cleared for
+    /// `resolve` to fill in again.
+    pub fn with_model(mut self, model: ModelKind) -> ExperimentConfig {
+        if model != self.model {
+            self.model = model;
+            self.architecture = ArchitectureConfig::default();
+            self.loss = None;
+            self.optimizer = None;
+            self.regularization.layers = None;
+        }
+
+        self
+    }
+
+    /// Checks a resolved config, so a run fails at startup rather than after
+    /// loading the data.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: String| Err(ConfigError::Invalid(message));
+
+        let (Some(layers), Some(activations), Some(optimizer)) = (
+            &self.architecture.layers,
+            &self.architecture.activations,
+            &self.optimizer,
+        ) else {
+            return invalid(String::from("config is not resolved"));
+        };
+
+        if layers.len() < 2 || layers.contains(&0) {
+            return invalid(format!(
+                "architecture.layers needs at least two non-zero sizes, found {:?}",
+                layers
+            ));
+        }
+        if layers[0] != IMAGE_SIZE {
+            return invalid(format!(
+                "architecture.layers should start with the {} pixels of an image, found {}",
+                IMAGE_SIZE, layers[0]
+            ));
+        }
+        if self.model == ModelKind::Classifier && layers[layers.len() - 1] != CLASSES {
+            return invalid(format!(
+                "a classifier should end with {} classes, found {}",
+                CLASSES,
+                layers[layers.len() - 1]
+            ));
+        }
+
+        let connections = layers.len() - 1;
+        let decoder_activations = match self.model {
+            ModelKind::Classifier => None,
+            ModelKind::Autoencoder => self.architecture.decoder_activations.as_ref(),
+        };
+        for (name, activations) in [
+            ("activations", Some(activations)),
+            ("decoder_activations", decoder_activations),
+        ] {
+            if let Some(activations) = activations {
+                if activations.len() != connections {
+                    return invalid(format!(
+                        "architecture.{} needs {} activations for {} layers, found {}",
+                        name,
+                        connections,
+                        layers.len(),
+                        activations.len()
+                    ));
+                }
+            }
+        }
+
+        if let Some(dropout) = &self.architecture.dropout {
+            if dropout.len() != connections {
+                return invalid(format!(
+                    "architecture.dropout needs {} rates for {} layers, found {}",
+                    connections,
+                    layers.len(),
+                    dropout.len()
+                ));
+            }
+            if let Some(rate) = dropout.iter().find(|rate| !(0.0..1.0).contains(*rate)) {
+                return invalid(format!(
+                    "dropout rates should be at least 0 and below 1, found {}",
+                    rate
+                ));
+            }
+        }
+
+        let regularization = &self.regularization;
+        let mut penalties = vec![Regularization::new(regularization.l1, regularization.l2)];
+        if let Some(per_layer) = &regularization.layers {
+            if per_layer.len() != connections {
+                return invalid(format!(
+                    "regularization.layers needs {} entries for {} layers, found {}",
+                    connections,
+                    layers.len(),
+                    per_layer.len()
+                ));
+            }
+            penalties.extend(per_layer);
+        }
+        let valid = |value: f64| value.is_finite() && value >= 0.0;
+        if !penalties
+            .iter()
+            .all(|penalty| valid(penalty.l1) && valid(penalty.l2))
+        {
+            return invalid(String::from(
+                "regularization penalties should be finite and not negative",
+            ));
+        }
+        for (name, clip) in [
+            ("clip_value", regularization.clip_value),
+            ("clip_norm", regularization.clip_norm),
+        ] {
+            if let Some(clip) = clip.filter(|clip| !(clip.is_finite() && *clip > 0.0)) {
+                return invalid(format!(
+                    "regularization.{} should be positive, found {}",
+                    name, clip
+                ));
+            }
+        }
+
+        let learning_rate = optimizer.learning_rate();
+        if !learning_rate.is_finite() || learning_rate <= 0.0 {
+            return invalid(format!(
+                "the learning rate should be positive, found {}",
+                learning_rate
+            ));
+        }
+
+        let data = &self.data;
+        if data.training_set_size == 0 || data.val_set_size == 0 || data.test_set_size == 0 {
+            return invalid(String::from("data set sizes should be positive"));
+        }
+        if self.training.epochs == 0 || self.training.batch_size == 0 {
+            return invalid(String::from(
+                "training.epochs and training.batch_size should be positive",
+            ));
+        }
+
+        if let Err(message) = self.training.schedule.validate() {
+            return invalid(format!("training.schedule: {}", message));
+        }
+        let checkpoint = &self.training.checkpoint;
+        if checkpoint.epochs == Some(0) || checkpoint.batches == Some(0) {
+            return invalid(String::from(
+                "training.checkpoint.epochs and training.checkpoint.batches should be positive",
+            ));
+        }
+        if let Some(early_stopping) = &self.training.early_stopping {
+            if !early_stopping.min_delta.is_finite() || early_stopping.min_delta < 0.0 {
+                return invalid(String::from(
+                    "training.early_stopping.min_delta should not be negative",
+                ));
+            }
+            let unavailable = match self.model {
+                ModelKind::Classifier => Metric::ReconstructionError,
+                ModelKind::Autoencoder => Metric::Accuracy,
+            };
+            if early_stopping.metric == Some(unavailable) {
+                return invalid(format!(
+                    "training.early_stopping.metric {:?} is not available for the {:?}",
+                    unavailable, self.model
+                ));
+            }
+        }
+
+        let session = &self.session;
+        if session.rounds == Some(0) || session.retention.is_some_and(|keep| keep.count() == 0) {
+            return invalid(String::from(
+                "session.rounds and the count of session.retention should be positive",
+            ));
+        }
+        if let Some(minutes) = session
+            .max_minutes
+            .filter(|minutes| !(minutes.is_finite() && *minutes > 0.0))
+        {
+            return invalid(format!(
+                "session.max_minutes {} should be positive",
+                minutes
+            ));
+        }
+
+        let extension = format!("model.{}", self.output.format);
+        if ModelFormat::from_path(&extension).is_err() {
+            return invalid(format!(
+                "unknown output.format {}, expected e.g. json, bin or bin.zst",
+                self.output.format
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn layers(&self) -> Vec<usize> {
+        self.architecture.layers.clone().unwrap_or_default()
+    }
+
+    fn activations(&self) -> Vec<Activation> {
+        self.architecture.activations.clone().unwrap_or_default()
+    }
+
+    fn dropout(&self) -> Vec<f64> {
+        self.architecture.dropout.clone().unwrap_or_default()
+    }
+
+    fn loss(&self) -> LossConfig {
+        self.loss.unwrap_or(LossConfig::MeanSquaredError)
+    }
+
+    fn optimizer(&self) -> OptimizerConfig {
+        self.optimizer
+            .unwrap_or(OptimizerConfig::Sgd { learning_rate: 0.1 })
+    }
+
+    pub fn data_set(&self) -> error::Result<DataSet> {
+        mnist_data_set(
+            &self.data.directory,
+            self.data.training_set_size,
+            self.data.val_set_size,
+            self.data.test_set_size,
+        )
+    }
+
+    /// The test images and labels only, for evaluating a saved model.
+    pub fn test_set(&self) -> error::Result<(Array3<f64>, Array2<f64>)> {
+        mnist_test_set(&self.data.directory, self.data.test_set_size)
+    }
+
+    /// Initialized from `seed`, so a seeded config always builds the same
+    /// network.
+    pub fn build_network<T: Float>(&self) -> Network<T> {
+        let mut network = Network::from_layers(
+            dense_layers(
+                &self.layers(),
+                &self.activations(),
+                &self.dropout(),
+                self.architecture.normalization,
+                &mut random::rng(self.seed, Stream::Initialization),
+            ),
+            self.optimizer().build(),
+            self.loss().build(),
+        );
+        self.regularization
+            .apply(&mut network, self.regularization.layers.clone());
+
+        network
+    }
+
+    pub fn build_autoencoder<T: Float>(&self) -> Autoencoder<T> {
+        let layers = self.layers();
+        let decoder_layers: Vec<usize> = layers.iter().rev().cloned().collect();
+        let decoder_activations = self
+            .architecture
+            .decoder_activations
+            .clone()
+            .unwrap_or_else(|| self.activations());
+        let mut rng = random::rng(self.seed, Stream::Initialization);
+
+        let normalization = self.architecture.normalization;
+        let encoder = dense_layers(
+            &layers,
+            &self.activations(),
+            &self.dropout(),
+            normalization,
+            &mut rng,
+        );
+        let decoder = dense_layers(
+            &decoder_layers,
+            &decoder_activations,
+            &[],
+            normalization,
+            &mut rng,
+        );
+
+        let mut autoencoder = Autoencoder::from_networks(
+            Network::from_layers(encoder, self.optimizer().build(), self.loss().build()),
+            Network::from_layers(decoder, self.optimizer().build(), self.loss().build()),
+        );
+        let regularization = &self.regularization;
+        let [encoder, decoder] = autoencoder.networks_mut();
+        regularization.apply(encoder, regularization.layers.clone());
+        regularization.apply(
+            decoder,
+            regularization
+                .layers
+                .as_ref()
+                .map(|layers| layers.iter().rev().cloned().collect()),
+        );
+
+        autoencoder
+    }
+
+    /// A trainer for this run, which saves the config next to every model.
+    pub fn trainer(&self) -> Trainer {
+        let mut trainer = Trainer::new(self.training.epochs, self.training.batch_size);
+        trainer.output_directory = self.output.directory.clone();
+        trainer.model_extension = self.output.format.clone();
+        trainer.seed = self.seed;
+        trainer.schedule = self.training.schedule;
+        trainer.warmup_epochs = self.training.warmup_epochs;
+        trainer.early_stopping = self.training.early_stopping;
+        trainer.checkpointing = self.training.checkpoint;
+        trainer.learning_rate = Some(self.optimizer().learning_rate());
+        trainer.config = Some(self.clone());
+        trainer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use crate::session::Retention;
+
+    use super::*;
+
+    #[test]
+    fn test_example_configs() {
+        for path in ["configs/classifier.toml", "configs/autoencoder.toml"] {
+            let config = ExperimentConfig::load(path).unwrap();
+            assert_eq!(config.clone().resolve(), config, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_resolve_defaults() {
+        let config: ExperimentConfig = toml::from_str("model = \"autoencoder\"").unwrap();
+        let config = config.resolve();
+
+        assert_eq!(config.architecture.layers, Some(vec![784, 256, 32]));
+        assert_eq!(
+            config.architecture.decoder_activations,
+            Some(vec![RELU, SIGMOID])
+        );
+        assert_eq!(config.loss, Some(LossConfig::BinaryCrossEntropy));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_written_config_loads_identically() {
+        let mut config = ExperimentConfig::default().resolve();
+        config.seed = Some(7);
+        config.architecture.normalization = Some(Normalization::LayerNorm);
+        config.training.schedule = Schedule::CosineWarmRestarts {
+            period: 2,
+            multiplier: 2,
+            minimum: 0.001,
+        };
+        config.training.warmup_epochs = 1;
+        config.training.checkpoint.epochs = Some(2);
+        config.session = SessionConfig {
+            rounds: Some(3),
+            max_minutes: Some(90.0),
+            retention: Some(Retention::Best(2)),
+            ..SessionConfig::default()
+        };
+        config.training.early_stopping = Some(EarlyStopping {
+            metric: Some(Metric::Loss),
+            ..EarlyStopping::default()
+        });
+        config.regularization = RegularizationConfig {
+            l2: 0.001,
+            layers: Some(vec![Regularization::new(0.0, 0.01); 3]),
+            clip_norm: Some(5.0),
+            ..RegularizationConfig::default()
+        };
+        config.loss = Some(LossConfig::Huber { delta: 0.5 });
+        config.optimizer = Some(OptimizerConfig::AdamW {
+            learning_rate: 0.01,
+            weight_decay: 0.1,
+        });
+
+        for name in ["written.toml", "written.json"] {
+            let path = temp_dir().join(format!("autoencoder-config-{}", name));
+            config.write(&path).unwrap();
+
+            assert_eq!(ExperimentConfig::load(&path).unwrap(), config);
+        }
+    }
+
+    #[test]
+    fn test_with_model_keeps_run_settings() {
+        let mut config = ExperimentConfig::load("configs/classifier.toml").unwrap();
+        config.seed = Some(3);
+        config.data.directory = PathBuf::from("mnist");
+        config.output.directory = PathBuf::from("models");
+        config.training.epochs = 4;
+        config.session.rounds = Some(2);
+        config.regularization.l2 = 0.01;
+        config.regularization.layers = Some(vec![Regularization::new(0.0, 0.1); 3]);
+
+        let switched = config.clone().with_model(ModelKind::Autoencoder).resolve();
+        assert!(switched.validate().is_ok());
+        assert_eq!(switched.architecture.layers, Some(vec![784, 256, 32]));
+        assert_eq!(switched.loss, Some(LossConfig::BinaryCrossEntropy));
+        assert_eq!(switched.regularization.layers, None);
+        assert_eq!(switched.regularization.l2, 0.01);
+        assert_eq!(switched.seed, config.seed);
+        assert_eq!(switched.data, config.data);
+        assert_eq!(switched.training, config.training);
+        assert_eq!(switched.output, config.output);
+        assert_eq!(switched.session, config.session);
+
+        assert_eq!(config.clone().with_model(ModelKind::Classifier), config);
+    }
+
+    #[test]
+    fn test_dropout_layers() {
+        let config: ExperimentConfig =
+            toml::from_str("[architecture]\nlayers = [784, 32, 10]\ndropout = [0.2, 0.5]").unwrap();
+        let config = config.resolve();
+        assert!(config.validate().is_ok());
+
+        let kinds: Vec<&str> = config
+            .build_network::<f64>()
+            .to_data()
+            .layers
+            .iter()
+            .map(|layer| layer.kind())
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "dropout",
+                "dense",
+                "activation",
+                "dropout",
+                "dense",
+                "activation"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_configs() {
+        let invalid = [
+            "[architecture]\nlayers = [784, 10]\nactivations = [\"Relu\", \"Softmax\"]",
+            "[architecture]\nlayers = [700, 10]",
+            "[architecture]\nlayers = [784, 12]",
+            "[training]\nbatch_size = 0",
+            "[optimizer.Adam]\nlearning_rate = -1.0",
+            "[output]\nformat = \"txt\"",
+            "[architecture]\nlayers = [784, 10]\ndropout = [0.5, 0.5]",
+            "[architecture]\nlayers = [784, 10]\ndropout = [1.0]",
+            "[regularization]\nl2 = -0.1",
+            "[training.schedule.Step]\nstep_size = 0\ngamma = 0.5",
+            "[training.early_stopping]\nmetric = \"ReconstructionError\"",
+            "[training.early_stopping]\nmin_delta = -1.0",
+            "[training.checkpoint]\nbatches = 0",
+            "[session]\nretention = { Best = 0 }",
+            "[session]\nmax_minutes = -5.0",
+            "[regularization]\nclip_norm = 0.0",
+            "[regularization]\nlayers = [{ l2 = 0.1 }, { l2 = 0.1 }]",
+        ];
+
+        for contents in invalid {
+            let config: ExperimentConfig = toml::from_str(contents).unwrap();
+            assert!(
+                matches!(config.resolve().validate(), Err(ConfigError::Invalid(_))),
+                "{}",
+                contents
+            );
+        }
+
+        assert!(toml::from_str::<ExperimentConfig>("epochs = 3").is_err());
+    }
+}
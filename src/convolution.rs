@@ -0,0 +1,1025 @@
+use autometrics::autometrics;
+use rand::Rng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    error::{Error, Result},
+    float::Float,
+    layers::{check_gradients, check_inputs, Layer, LayerData},
+    matrix::Matrix,
+};
+
+/// Channels, height and width of the images a layer takes or returns. A
+/// batch holds one image per column with its values in channel, row, column
+/// order, so a flattened MNIST image is already a `Shape::new(1, 28, 28)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shape {
+    pub channels: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl Shape {
+    pub fn new(channels: usize, height: usize, width: usize) -> Shape {
+        Shape {
+            channels,
+            height,
+            width,
+        }
+    }
+
+    /// Number of values of one image.
+    pub fn size(&self) -> usize {
+        self.channels * self.height * self.width
+    }
+
+    fn positions(&self) -> usize {
+        self.height * self.width
+    }
+}
+
+/// Square window of a convolution or pooling layer, moved `stride` values at
+/// a time over the input padded with `padding` zeros on every side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Window {
+    pub size: usize,
+    pub stride: usize,
+    pub padding: usize,
+}
+
+impl Window {
+    pub fn new(size: usize, stride: usize, padding: usize) -> Window {
+        Window {
+            size,
+            stride,
+            padding,
+        }
+    }
+
+    /// Shape of the result with `channels` channels when the window slides
+    /// over `input`, `None` when it does not fit.
+    pub fn output(&self, input: Shape, channels: usize) -> Option<Shape> {
+        let (height, width) = (
+            input.height + 2 * self.padding,
+            input.width + 2 * self.padding,
+        );
+
+        if self.size == 0 || self.stride == 0 || self.size > height || self.size > width {
+            return None;
+        }
+
+        Some(Shape::new(
+            channels,
+            (height - self.size) / self.stride + 1,
+            (width - self.size) / self.stride + 1,
+        ))
+    }
+
+    /// Like `output` for pooling, where every window has to cover some of the
+    /// input rather than only padding.
+    pub fn pool_output(&self, input: Shape) -> Option<Shape> {
+        if self.padding >= self.size {
+            return None;
+        }
+
+        self.output(input, input.channels)
+    }
+
+    /// Shape of the result of a transposed convolution with `channels`
+    /// channels, the input of a convolution with `input` as its output.
+    pub fn transposed_output(&self, input: Shape, channels: usize) -> Option<Shape> {
+        let size = |input: usize| (input.max(1) - 1) * self.stride + self.size;
+        let (height, width) = (size(input.height), size(input.width));
+
+        if self.size == 0 || self.stride == 0 || channels == 0 {
+            return None;
+        }
+
+        Some(Shape::new(
+            channels,
+            height.checked_sub(2 * self.padding).filter(|&h| h > 0)?,
+            width.checked_sub(2 * self.padding).filter(|&w| w > 0)?,
+        ))
+    }
+
+    /// Input coordinate at `offset` into the window at output coordinate
+    /// `position`, `None` in the padding.
+    fn input(&self, position: usize, offset: usize, size: usize) -> Option<usize> {
+        (position * self.stride + offset)
+            .checked_sub(self.padding)
+            .filter(|&coordinate| coordinate < size)
+    }
+}
+
+/// Unfolds a batch of images into a matrix with a row for every value of the
+/// window over every channel and a column for every window position of every
+/// image, so a convolution becomes a single `multiply`.
+#[autometrics]
+pub fn im2col<T: Float>(inputs: &Matrix<T>, shape: Shape, window: Window) -> Matrix<T> {
+    let output = window
+        .output(shape, 1)
+        .expect("Attempted to unfold images smaller than the window");
+    if inputs.rows != shape.size() {
+        panic!("Attempted to unfold images of incorrect dimensions");
+    }
+
+    let batch = inputs.cols;
+    let columns = batch * output.positions();
+    let values = inputs.to_vec();
+    let k = window.size;
+    let mut data = vec![T::zero(); shape.channels * k * k * columns];
+
+    data.par_chunks_mut(columns.max(1))
+        .enumerate()
+        .for_each(|(row, unfolded)| {
+            let (channel, dy, dx) = (row / (k * k), row / k % k, row % k);
+            for b in 0..batch {
+                for y in 0..output.height {
+                    let Some(iy) = window.input(y, dy, shape.height) else {
+                        continue;
+                    };
+                    for x in 0..output.width {
+                        let Some(ix) = window.input(x, dx, shape.width) else {
+                            continue;
+                        };
+                        let input = channel * shape.positions() + iy * shape.width + ix;
+                        unfolded[(b * output.height + y) * output.width + x] =
+                            values[input * batch + b];
+                    }
+                }
+            }
+        });
+
+    Matrix::from_vec(shape.channels * k * k, columns, data)
+}
+
+/// Folds a matrix laid out as by `im2col` back into a batch of images, adding
+/// up the values of overlapping windows.
+#[autometrics]
+pub fn col2im<T: Float>(columns: &Matrix<T>, shape: Shape, window: Window) -> Matrix<T> {
+    let output = window
+        .output(shape, 1)
+        .expect("Attempted to fold images smaller than the window");
+    let k = window.size;
+    if columns.rows != shape.channels * k * k || columns.cols % output.positions() != 0 {
+        panic!("Attempted to fold columns of incorrect dimensions");
+    }
+
+    let batch = columns.cols / output.positions();
+    let values = columns.to_vec();
+    let mut data = vec![T::zero(); shape.size() * batch];
+
+    data.par_chunks_mut((shape.positions() * batch).max(1))
+        .enumerate()
+        .for_each(|(channel, folded)| {
+            for dy in 0..k {
+                for dx in 0..k {
+                    let row = (channel * k + dy) * k + dx;
+                    for y in 0..output.height {
+                        let Some(iy) = window.input(y, dy, shape.height) else {
+                            continue;
+                        };
+                        for x in 0..output.width {
+                            let Some(ix) = window.input(x, dx, shape.width) else {
+                                continue;
+                            };
+                            for b in 0..batch {
+                                let column = (b * output.height + y) * output.width + x;
+                                folded[(iy * shape.width + ix) * batch + b] +=
+                                    values[row * columns.cols + column];
+                            }
+                        }
+                    }
+                }
+            }
+        });
+
+    Matrix::from_vec(shape.size(), batch, data)
+}
+
+/// Regroups a batch of images into one row per channel, with the positions of
+/// every image side by side as in the columns of `im2col`.
+fn to_channels<T: Float>(samples: &Matrix<T>, channels: usize) -> Matrix<T> {
+    let (batch, positions) = (samples.cols, samples.rows / channels);
+    let values = samples.to_vec();
+    let mut data = vec![T::zero(); values.len()];
+
+    data.par_chunks_mut((batch * positions).max(1))
+        .enumerate()
+        .for_each(|(channel, row)| {
+            for b in 0..batch {
+                for p in 0..positions {
+                    row[b * positions + p] = values[(channel * positions + p) * batch + b];
+                }
+            }
+        });
+
+    Matrix::from_vec(channels, batch * positions, data)
+}
+
+/// Inverse of `to_channels`.
+fn to_samples<T: Float>(channels: &Matrix<T>, batch: usize) -> Matrix<T> {
+    let positions = channels.cols / batch;
+    let values = channels.to_vec();
+    let mut data = vec![T::zero(); values.len()];
+
+    data.par_chunks_mut(batch.max(1))
+        .enumerate()
+        .for_each(|(row, sample)| {
+            let (channel, p) = (row / positions, row % positions);
+            for (b, value) in sample.iter_mut().enumerate() {
+                *value = values[channel * channels.cols + b * positions + p];
+            }
+        });
+
+    Matrix::from_vec(channels.rows * positions, batch, data)
+}
+
+/// Uniform values in `[-1, 1)` scaled down by the square root of `fan_in`,
+/// so wide kernels do not saturate the activations.
+fn initial<T: Float>(rows: usize, cols: usize, fan_in: usize, rng: &mut impl Rng) -> Matrix<T> {
+    let mut matrix = Matrix::random(rows, cols, rng);
+    matrix.scale_assign(T::of(1.0 / (fan_in as f64).sqrt()));
+    matrix
+}
+
+/// 2D convolution with `filters` learned kernels, one `multiply` of the
+/// kernels with the `im2col` unfolding of the batch.
+pub struct Conv2D<T: Float = f64> {
+    input: Shape,
+    output: Shape,
+    window: Window,
+    /// One row per filter, one column per value of the window over every
+    /// input channel.
+    weights: Matrix<T>,
+    biases: Matrix<T>,
+    weight_gradients: Matrix<T>,
+    bias_gradients: Matrix<T>,
+    /// The unfolded inputs of the last `forward`.
+    columns: Option<Matrix<T>>,
+}
+
+#[autometrics]
+impl<T: Float> Conv2D<T> {
+    pub fn new(input: Shape, filters: usize, window: Window) -> Conv2D<T> {
+        Conv2D::with_rng(input, filters, window, &mut rand::thread_rng())
+    }
+
+    /// Like `new`, drawing the initial kernels and biases from `rng`.
+    pub fn with_rng(input: Shape, filters: usize, window: Window, rng: &mut impl Rng) -> Conv2D<T> {
+        let fan_in = input.channels * window.size * window.size;
+
+        Conv2D::from_parameters(
+            input,
+            window,
+            initial(filters, fan_in, fan_in, rng),
+            initial(filters, 1, fan_in, rng),
+        )
+    }
+
+    /// Builds the layer from its kernels, one row per filter, and biases.
+    pub fn from_parameters(
+        input: Shape,
+        window: Window,
+        weights: Matrix<T>,
+        biases: Matrix<T>,
+    ) -> Conv2D<T> {
+        let output = window
+            .output(input, weights.rows)
+            .expect("Convolution window does not fit the input");
+        if weights.cols != input.channels * window.size * window.size
+            || biases.shape() != (weights.rows, 1)
+        {
+            panic!("Convolution parameters do not match the window");
+        }
+
+        Conv2D {
+            input,
+            output,
+            window,
+            weight_gradients: Matrix::zeros(weights.rows, weights.cols),
+            bias_gradients: Matrix::zeros(biases.rows, 1),
+            weights,
+            biases,
+            columns: None,
+        }
+    }
+
+    pub fn input_shape(&self) -> Shape {
+        self.input
+    }
+
+    pub fn output_shape(&self) -> Shape {
+        self.output
+    }
+
+    fn convolve(&self, inputs: &Matrix<T>) -> Result<(Matrix<T>, Matrix<T>)> {
+        check_inputs(inputs, self.input.size())?;
+
+        let columns = im2col(inputs, self.input, self.window);
+        let outputs = self.weights.multiply(&columns).add_column(&self.biases);
+
+        Ok((to_samples(&outputs, inputs.cols), columns))
+    }
+}
+
+impl<T: Float> Layer<T> for Conv2D<T> {
+    /// Convolves a batch of images and keeps the unfolded inputs for
+    /// `backward`.
+    fn forward(&mut self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        let (outputs, columns) = self.convolve(inputs)?;
+        self.columns = Some(columns);
+
+        Ok(outputs)
+    }
+
+    fn predict(&self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        Ok(self.convolve(inputs)?.0)
+    }
+
+    fn backward(&mut self, gradients: &Matrix<T>) -> Result<Matrix<T>> {
+        let columns = self.columns.as_ref().ok_or(Error::MissingForwardPass)?;
+        let batch = columns.cols / self.output.positions();
+        check_gradients(gradients, self.output.size(), batch)?;
+
+        let gradients = to_channels(gradients, self.output.channels);
+        let batch_scale = T::of(1.0 / batch as f64);
+
+        self.weight_gradients = gradients.multiply(&columns.transpose());
+        self.weight_gradients.scale_assign(batch_scale);
+        self.bias_gradients = gradients.sum_columns();
+        self.bias_gradients.scale_assign(batch_scale);
+
+        let input_columns = self.weights.transpose().multiply(&gradients);
+
+        Ok(col2im(&input_columns, self.input, self.window))
+    }
+
+    fn parameters(&self) -> Vec<&Matrix<T>> {
+        vec![&self.weights, &self.biases]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        vec![&mut self.weights, &mut self.biases]
+    }
+
+    fn gradients(&self) -> Vec<&Matrix<T>> {
+        vec![&self.weight_gradients, &self.bias_gradients]
+    }
+
+    fn is_weight(&self, index: usize) -> bool {
+        index == 0
+    }
+
+    fn input_size(&self) -> usize {
+        self.input.size()
+    }
+
+    fn output_size(&self) -> usize {
+        self.output.size()
+    }
+
+    fn to_data(&self) -> LayerData {
+        LayerData::Conv2D {
+            input: self.input,
+            window: self.window,
+            weights: self.weights.cast(),
+            biases: self.biases.cast(),
+        }
+    }
+}
+
+/// Transposed 2D convolution, the gradient of a `Conv2D` with respect to its
+/// inputs. Upsamples in decoders, e.g. 7x7 to 14x14 with a window of size 2
+/// and stride 2.
+pub struct ConvTranspose2D<T: Float = f64> {
+    input: Shape,
+    output: Shape,
+    window: Window,
+    /// One row per input channel, one column per value of the window over
+    /// every filter.
+    weights: Matrix<T>,
+    biases: Matrix<T>,
+    weight_gradients: Matrix<T>,
+    bias_gradients: Matrix<T>,
+    /// The inputs of the last `forward`, grouped by channel.
+    channels: Option<Matrix<T>>,
+}
+
+#[autometrics]
+impl<T: Float> ConvTranspose2D<T> {
+    pub fn new(input: Shape, filters: usize, window: Window) -> ConvTranspose2D<T> {
+        ConvTranspose2D::with_rng(input, filters, window, &mut rand::thread_rng())
+    }
+
+    /// Like `new`, drawing the initial kernels and biases from `rng`.
+    pub fn with_rng(
+        input: Shape,
+        filters: usize,
+        window: Window,
+        rng: &mut impl Rng,
+    ) -> ConvTranspose2D<T> {
+        let fan_in = input.channels * window.size * window.size;
+        let kernels = filters * window.size * window.size;
+
+        ConvTranspose2D::from_parameters(
+            input,
+            window,
+            initial(input.channels, kernels, fan_in, rng),
+            initial(filters, 1, fan_in, rng),
+        )
+    }
+
+    /// Builds the layer from its kernels, one row per input channel, and
+    /// biases, one per filter.
+    pub fn from_parameters(
+        input: Shape,
+        window: Window,
+        weights: Matrix<T>,
+        biases: Matrix<T>,
+    ) -> ConvTranspose2D<T> {
+        let output = window
+            .transposed_output(input, biases.rows)
+            .expect("Transposed convolution padding is larger than the output");
+        if weights.shape() != (input.channels, biases.rows * window.size * window.size)
+            || biases.cols != 1
+        {
+            panic!("Transposed convolution parameters do not match the window");
+        }
+
+        ConvTranspose2D {
+            input,
+            output,
+            window,
+            weight_gradients: Matrix::zeros(weights.rows, weights.cols),
+            bias_gradients: Matrix::zeros(biases.rows, 1),
+            weights,
+            biases,
+            channels: None,
+        }
+    }
+
+    pub fn input_shape(&self) -> Shape {
+        self.input
+    }
+
+    pub fn output_shape(&self) -> Shape {
+        self.output
+    }
+
+    fn convolve(&self, inputs: &Matrix<T>) -> Result<(Matrix<T>, Matrix<T>)> {
+        check_inputs(inputs, self.input.size())?;
+
+        let channels = to_channels(inputs, self.input.channels);
+        let columns = self.weights.transpose().multiply(&channels);
+        let outputs = to_channels(
+            &col2im(&columns, self.output, self.window),
+            self.output.channels,
+        )
+        .add_column(&self.biases);
+
+        Ok((to_samples(&outputs, inputs.cols), channels))
+    }
+}
+
+impl<T: Float> Layer<T> for ConvTranspose2D<T> {
+    /// Upsamples a batch of images and keeps the inputs for `backward`.
+    fn forward(&mut self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        let (outputs, channels) = self.convolve(inputs)?;
+        self.channels = Some(channels);
+
+        Ok(outputs)
+    }
+
+    fn predict(&self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        Ok(self.convolve(inputs)?.0)
+    }
+
+    fn backward(&mut self, gradients: &Matrix<T>) -> Result<Matrix<T>> {
+        let channels = self.channels.as_ref().ok_or(Error::MissingForwardPass)?;
+        let batch = channels.cols / self.input.positions();
+        check_gradients(gradients, self.output.size(), batch)?;
+
+        let columns = im2col(gradients, self.output, self.window);
+        let batch_scale = T::of(1.0 / batch as f64);
+
+        self.weight_gradients = channels.multiply(&columns.transpose());
+        self.weight_gradients.scale_assign(batch_scale);
+        self.bias_gradients = to_channels(gradients, self.output.channels).sum_columns();
+        self.bias_gradients.scale_assign(batch_scale);
+
+        Ok(to_samples(&self.weights.multiply(&columns), batch))
+    }
+
+    fn parameters(&self) -> Vec<&Matrix<T>> {
+        vec![&self.weights, &self.biases]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        vec![&mut self.weights, &mut self.biases]
+    }
+
+    fn gradients(&self) -> Vec<&Matrix<T>> {
+        vec![&self.weight_gradients, &self.bias_gradients]
+    }
+
+    fn is_weight(&self, index: usize) -> bool {
+        index == 0
+    }
+
+    fn input_size(&self) -> usize {
+        self.input.size()
+    }
+
+    fn output_size(&self) -> usize {
+        self.output.size()
+    }
+
+    fn to_data(&self) -> LayerData {
+        LayerData::ConvTranspose2D {
+            input: self.input,
+            window: self.window,
+            weights: self.weights.cast(),
+            biases: self.biases.cast(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Pooling {
+    Max,
+    /// Mean over the window, counting the padding as zeros.
+    Average,
+}
+
+/// Max or average pooling of every channel separately.
+pub struct Pool2D {
+    pooling: Pooling,
+    input: Shape,
+    output: Shape,
+    window: Window,
+    /// Batch size of the last `forward` and, for max pooling, the input row
+    /// of the maximum of every output value.
+    batch: Option<usize>,
+    sources: Vec<usize>,
+}
+
+#[autometrics]
+impl Pool2D {
+    pub fn new(pooling: Pooling, input: Shape, window: Window) -> Pool2D {
+        let output = window
+            .pool_output(input)
+            .expect("Pooling window does not fit the input");
+
+        Pool2D {
+            pooling,
+            input,
+            output,
+            window,
+            batch: None,
+            sources: vec![],
+        }
+    }
+
+    pub fn max(input: Shape, window: Window) -> Pool2D {
+        Pool2D::new(Pooling::Max, input, window)
+    }
+
+    pub fn average(input: Shape, window: Window) -> Pool2D {
+        Pool2D::new(Pooling::Average, input, window)
+    }
+
+    pub fn input_shape(&self) -> Shape {
+        self.input
+    }
+
+    pub fn output_shape(&self) -> Shape {
+        self.output
+    }
+
+    /// Input rows in the window of output row `row`, without the padding.
+    fn window_rows(&self, row: usize) -> impl Iterator<Item = usize> + '_ {
+        let (channel, position) = (row / self.output.positions(), row % self.output.positions());
+        let (y, x) = (position / self.output.width, position % self.output.width);
+
+        (0..self.window.size).flat_map(move |dy| {
+            (0..self.window.size).filter_map(move |dx| {
+                let iy = self.window.input(y, dy, self.input.height)?;
+                let ix = self.window.input(x, dx, self.input.width)?;
+                Some(channel * self.input.positions() + iy * self.input.width + ix)
+            })
+        })
+    }
+
+    fn pool<T: Float>(&self, inputs: &Matrix<T>) -> Result<(Matrix<T>, Vec<usize>)> {
+        check_inputs(inputs, self.input.size())?;
+
+        let batch = inputs.cols;
+        let values = inputs.to_vec();
+        let area = T::of((self.window.size * self.window.size) as f64);
+        let mut data = vec![T::zero(); self.output.size() * batch];
+        let mut sources = match self.pooling {
+            Pooling::Max => vec![0; data.len()],
+            Pooling::Average => vec![],
+        };
+
+        for row in 0..self.output.size() {
+            for b in 0..batch {
+                let index = row * batch + b;
+                match self.pooling {
+                    Pooling::Max => {
+                        let source = self
+                            .window_rows(row)
+                            .max_by(|&i, &j| {
+                                values[i * batch + b]
+                                    .partial_cmp(&values[j * batch + b])
+                                    .unwrap_or(std::cmp::Ordering::Equal)
+                            })
+                            .expect("Pooling window covers the input");
+                        data[index] = values[source * batch + b];
+                        sources[index] = source;
+                    }
+                    Pooling::Average => {
+                        let sum: T = self.window_rows(row).map(|i| values[i * batch + b]).sum();
+                        data[index] = sum / area;
+                    }
+                }
+            }
+        }
+
+        Ok((Matrix::from_vec(self.output.size(), batch, data), sources))
+    }
+}
+
+impl<T: Float> Layer<T> for Pool2D {
+    /// Pools a batch of images and keeps where the maxima came from.
+    fn forward(&mut self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        let (outputs, sources) = self.pool(inputs)?;
+        self.batch = Some(inputs.cols);
+        self.sources = sources;
+
+        Ok(outputs)
+    }
+
+    fn predict(&self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        Ok(self.pool(inputs)?.0)
+    }
+
+    /// Routes the gradients back to the maxima, or spreads them evenly over
+    /// the window.
+    fn backward(&mut self, gradients: &Matrix<T>) -> Result<Matrix<T>> {
+        let batch = self.batch.ok_or(Error::MissingForwardPass)?;
+        check_gradients(gradients, self.output.size(), batch)?;
+
+        let values = gradients.to_vec();
+        let area = T::of((self.window.size * self.window.size) as f64);
+        let mut data = vec![T::zero(); self.input.size() * batch];
+
+        for row in 0..self.output.size() {
+            for b in 0..batch {
+                let index = row * batch + b;
+                match self.pooling {
+                    Pooling::Max => data[self.sources[index] * batch + b] += values[index],
+                    Pooling::Average => {
+                        for source in self.window_rows(row) {
+                            data[source * batch + b] += values[index] / area;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(Matrix::from_vec(self.input.size(), batch, data))
+    }
+
+    fn input_size(&self) -> usize {
+        self.input.size()
+    }
+
+    fn output_size(&self) -> usize {
+        self.output.size()
+    }
+
+    fn to_data(&self) -> LayerData {
+        LayerData::Pool2D {
+            pooling: self.pooling,
+            input: self.input,
+            window: self.window,
+        }
+    }
+}
+
+/// Hands images to dense layers. A batch already holds every image as one
+/// column, so only the shape is checked.
+pub struct Flatten {
+    input: Shape,
+}
+
+impl Flatten {
+    pub fn new(input: Shape) -> Flatten {
+        Flatten { input }
+    }
+
+    pub fn input_shape(&self) -> Shape {
+        self.input
+    }
+}
+
+impl<T: Float> Layer<T> for Flatten {
+    fn forward(&mut self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        self.predict(inputs)
+    }
+
+    fn predict(&self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        check_inputs(inputs, self.input.size())?;
+
+        Ok(inputs.clone())
+    }
+
+    fn backward(&mut self, gradients: &Matrix<T>) -> Result<Matrix<T>> {
+        check_gradients(gradients, self.input.size(), gradients.cols)?;
+
+        Ok(gradients.clone())
+    }
+
+    fn input_size(&self) -> usize {
+        self.input.size()
+    }
+
+    fn output_size(&self) -> usize {
+        self.input.size()
+    }
+
+    fn to_data(&self) -> LayerData {
+        LayerData::Flatten { input: self.input }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    fn rng() -> ChaCha8Rng {
+        ChaCha8Rng::seed_from_u64(5)
+    }
+
+    /// Half the sum of the squared outputs, whose gradient is the outputs.
+    fn half_square(outputs: &Matrix) -> f64 {
+        outputs.to_vec().iter().map(|x| x * x / 2.0).sum()
+    }
+
+    fn assert_input_gradients<L>(
+        layer: &mut L,
+        inputs: &Matrix,
+        forward: fn(&mut L, &Matrix) -> Result<Matrix>,
+        backward: fn(&mut L, &Matrix) -> Result<Matrix>,
+    ) {
+        let h = 1e-6;
+        let outputs = forward(layer, inputs).unwrap();
+        let gradients = backward(layer, &outputs).unwrap();
+        assert_eq!(gradients.shape(), inputs.shape());
+
+        for i in 0..inputs.rows {
+            for b in 0..inputs.cols {
+                let mut plus = inputs.clone();
+                let mut minus = inputs.clone();
+                plus[(i, b)] += h;
+                minus[(i, b)] -= h;
+
+                let numeric = (half_square(&forward(layer, &plus).unwrap())
+                    - half_square(&forward(layer, &minus).unwrap()))
+                    / (2.0 * h);
+                assert!((numeric - gradients[(i, b)]).abs() < 1e-5);
+            }
+        }
+    }
+
+    /// Parameter gradients are averaged over the batch, the finite
+    /// differences of the summed loss are not.
+    fn assert_parameter_gradients<L>(
+        layer: &mut L,
+        inputs: &Matrix,
+        forward: fn(&mut L, &Matrix) -> Result<Matrix>,
+        backward: fn(&mut L, &Matrix) -> Result<Matrix>,
+        parameter: fn(&mut L) -> &mut Matrix,
+        gradient: fn(&L) -> &Matrix,
+    ) {
+        let h = 1e-6;
+        let outputs = forward(layer, inputs).unwrap();
+        backward(layer, &outputs).unwrap();
+        let gradients = gradient(layer).scale(inputs.cols as f64);
+
+        for i in 0..gradients.rows {
+            for j in 0..gradients.cols {
+                parameter(layer)[(i, j)] += h;
+                let plus = half_square(&forward(layer, inputs).unwrap());
+                parameter(layer)[(i, j)] -= 2.0 * h;
+                let minus = half_square(&forward(layer, inputs).unwrap());
+                parameter(layer)[(i, j)] += h;
+
+                assert!(((plus - minus) / (2.0 * h) - gradients[(i, j)]).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_im2col_and_col2im() {
+        let shape = Shape::new(1, 3, 3);
+        let inputs = Matrix::from_vec(9, 1, (1..=9).map(f64::from).collect());
+        let window = Window::new(2, 1, 0);
+
+        let columns = im2col(&inputs, shape, window);
+        assert_eq!(
+            columns.to_rows(),
+            vec![
+                vec![1.0, 2.0, 4.0, 5.0],
+                vec![2.0, 3.0, 5.0, 6.0],
+                vec![4.0, 5.0, 7.0, 8.0],
+                vec![5.0, 6.0, 8.0, 9.0],
+            ]
+        );
+
+        // Every input is counted once for each window it is in
+        let ones = Matrix::from_vec(4, 4, vec![1.0; 16]);
+        assert_eq!(
+            col2im(&ones, shape, window).to_vec(),
+            vec![1.0, 2.0, 1.0, 2.0, 4.0, 2.0, 1.0, 2.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn test_conv_forward() {
+        let mut conv = Conv2D::new(Shape::new(1, 3, 3), 1, Window::new(2, 1, 1));
+        conv.weights = Matrix::from_vec(1, 4, vec![1.0; 4]);
+        conv.biases = Matrix::from_vec(1, 1, vec![0.5]);
+
+        let inputs = Matrix::from_vec(9, 1, (1..=9).map(f64::from).collect());
+        let outputs = conv.forward(&inputs).unwrap();
+
+        assert_eq!(conv.output_shape(), Shape::new(1, 4, 4));
+        assert_eq!(
+            outputs.to_vec(),
+            vec![
+                1.5, 3.5, 5.5, 3.5, 5.5, 12.5, 16.5, 9.5, 11.5, 24.5, 28.5, 15.5, 7.5, 15.5, 17.5,
+                9.5
+            ]
+        );
+        assert_eq!(conv.predict(&inputs).unwrap().to_vec(), outputs.to_vec());
+    }
+
+    #[test]
+    fn test_conv_gradients_match_finite_differences() {
+        let input = Shape::new(2, 5, 4);
+        let inputs = Matrix::random(input.size(), 3, &mut rng());
+        let mut conv = Conv2D::with_rng(input, 3, Window::new(3, 2, 1), &mut rng());
+
+        assert_input_gradients(&mut conv, &inputs, Conv2D::forward, Conv2D::backward);
+        assert_parameter_gradients(
+            &mut conv,
+            &inputs,
+            Conv2D::forward,
+            Conv2D::backward,
+            |conv| &mut conv.weights,
+            |conv| &conv.weight_gradients,
+        );
+        assert_parameter_gradients(
+            &mut conv,
+            &inputs,
+            Conv2D::forward,
+            Conv2D::backward,
+            |conv| &mut conv.biases,
+            |conv| &conv.bias_gradients,
+        );
+    }
+
+    #[test]
+    fn test_conv_transpose_gradients_match_finite_differences() {
+        let input = Shape::new(2, 3, 3);
+        let inputs = Matrix::random(input.size(), 2, &mut rng());
+        let mut conv = ConvTranspose2D::with_rng(input, 2, Window::new(3, 2, 1), &mut rng());
+        assert_eq!(conv.output_shape(), Shape::new(2, 5, 5));
+
+        assert_input_gradients(
+            &mut conv,
+            &inputs,
+            ConvTranspose2D::forward,
+            ConvTranspose2D::backward,
+        );
+        assert_parameter_gradients(
+            &mut conv,
+            &inputs,
+            ConvTranspose2D::forward,
+            ConvTranspose2D::backward,
+            |conv| &mut conv.weights,
+            |conv| &conv.weight_gradients,
+        );
+        assert_parameter_gradients(
+            &mut conv,
+            &inputs,
+            ConvTranspose2D::forward,
+            ConvTranspose2D::backward,
+            |conv| &mut conv.biases,
+            |conv| &conv.bias_gradients,
+        );
+    }
+
+    #[test]
+    fn test_pooling() {
+        let input = Shape::new(1, 4, 4);
+        let inputs = Matrix::from_vec(16, 1, (1..=16).map(f64::from).collect());
+
+        let mut max = Pool2D::max(input, Window::new(2, 2, 0));
+        assert_eq!(
+            max.forward(&inputs).unwrap().to_vec(),
+            vec![6.0, 8.0, 14.0, 16.0]
+        );
+        let mut average = Pool2D::average(input, Window::new(2, 2, 0));
+        assert_eq!(
+            average.forward(&inputs).unwrap().to_vec(),
+            vec![3.5, 5.5, 11.5, 13.5]
+        );
+
+        let input = Shape::new(2, 5, 5);
+        let inputs = Matrix::random(input.size(), 2, &mut rng());
+        for pooling in [Pooling::Max, Pooling::Average] {
+            let mut pool = Pool2D::new(pooling, input, Window::new(3, 2, 1));
+            assert_input_gradients(&mut pool, &inputs, Pool2D::forward, Pool2D::backward);
+        }
+    }
+
+    #[test]
+    fn test_mnist_shapes() {
+        let image = Shape::new(1, 28, 28);
+        let same = Window::new(3, 1, 1);
+        let halve = Window::new(2, 2, 0);
+        let inputs: Matrix = Matrix::zeros(image.size(), 2);
+
+        // Classifier features: conv, pool, conv, pool, flatten
+        let mut conv1 = Conv2D::new(image, 4, same);
+        let mut pool1 = Pool2D::max(conv1.output_shape(), halve);
+        let mut conv2 = Conv2D::new(pool1.output_shape(), 8, same);
+        let mut pool2 = Pool2D::average(conv2.output_shape(), halve);
+        let mut flatten = Flatten::new(pool2.output_shape());
+
+        let features = conv1.forward(&inputs).unwrap();
+        let features = pool1.forward(&features).unwrap();
+        let features = conv2.forward(&features).unwrap();
+        let features = pool2.forward(&features).unwrap();
+        let features = flatten.forward(&features).unwrap();
+        assert_eq!(features.shape(), (8 * 7 * 7, 2));
+
+        // Decoder: back up from 7x7 to 28x28
+        let mut up1 = ConvTranspose2D::new(pool2.output_shape(), 4, halve);
+        let mut up2 = ConvTranspose2D::new(up1.output_shape(), 1, halve);
+        assert_eq!(up2.output_shape(), image);
+
+        let decoded = up2.forward(&up1.forward(&features).unwrap()).unwrap();
+        assert_eq!(decoded.shape(), inputs.shape());
+
+        let errors = up1.backward(&up2.backward(&decoded).unwrap()).unwrap();
+        assert_eq!(errors.shape(), features.shape());
+    }
+
+    #[test]
+    fn test_invalid_shapes_return_errors() {
+        let mut conv: Conv2D = Conv2D::new(Shape::new(1, 4, 4), 2, Window::new(3, 1, 0));
+
+        assert!(matches!(
+            conv.backward(&Matrix::zeros(8, 1)),
+            Err(Error::MissingForwardPass)
+        ));
+        assert!(matches!(
+            conv.forward(&Matrix::zeros(15, 1)),
+            Err(Error::InvalidLength {
+                expected: 16,
+                found: 15,
+                ..
+            })
+        ));
+
+        conv.forward(&Matrix::zeros(16, 2)).unwrap();
+        assert!(matches!(
+            conv.backward(&Matrix::zeros(8, 1)),
+            Err(Error::ShapeMismatch { .. })
+        ));
+        assert_eq!(Window::new(5, 1, 0).output(Shape::new(1, 4, 4), 1), None);
+
+        // Corner windows of only padding have nothing to pool
+        let padded = Window::new(2, 2, 2);
+        assert!(padded.output(Shape::new(1, 4, 4), 1).is_some());
+        assert_eq!(padded.pool_output(Shape::new(1, 4, 4)), None);
+        assert!(Window::new(3, 2, 2)
+            .pool_output(Shape::new(1, 4, 4))
+            .is_some());
+        let data = crate::layers::LayerData::Pool2D {
+            pooling: Pooling::Max,
+            input: Shape::new(1, 4, 4),
+            window: padded,
+        };
+        assert!(data.validate().is_err());
+    }
+}
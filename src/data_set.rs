@@ -1,7 +1,11 @@
+use std::path::Path;
+
 use autometrics::autometrics;
-use mnist::*;
+use image::{imageops::FilterType, GrayImage};
 use ndarray::{s, Array2, Array3, ArrayBase, Dim};
 
+use crate::error::{Error, Result};
+use crate::idx::{self, IdxArray, IdxError};
 use crate::utils::convert_number_to_target_vec;
 
 pub struct DataSet {
@@ -13,61 +17,78 @@ pub struct DataSet {
     pub test_labels: Array2<f64>,
 }
 
+/// Names of the MNIST files, as published, each also read when gzipped.
+pub const TRAIN_IMAGES: &str = "train-images-idx3-ubyte";
+pub const TRAIN_LABELS: &str = "train-labels-idx1-ubyte";
+pub const TEST_IMAGES: &str = "t10k-images-idx3-ubyte";
+pub const TEST_LABELS: &str = "t10k-labels-idx1-ubyte";
+
+/// The first `count` items of the IDX file `name` in `directory`, which
+/// should have `dimensions` dimensions.
+fn read_idx(directory: &Path, name: &str, count: usize, dimensions: usize) -> Result<IdxArray> {
+    let path = idx::find(directory, name)?;
+    let array = IdxArray::read(&path)?;
+
+    if array.shape.len() != dimensions {
+        return Err(Error::Idx(IdxError::Malformed {
+            path,
+            reason: format!(
+                "expected {} dimensions, found {}",
+                dimensions,
+                array.shape.len()
+            ),
+        }));
+    }
+
+    Ok(array.take(count, &path)?)
+}
+
+/// Images of `array` from `start` on, scaled to `[0, 1)`.
+fn images(array: &IdxArray, start: usize, count: usize) -> Array3<f64> {
+    let (height, width) = (array.shape[1], array.shape[2]);
+    let pixels = &array.data[start * height * width..(start + count) * height * width];
+
+    Array3::from_shape_vec((count, height, width), pixels.to_vec())
+        .expect("IDX images match their shape")
+        .map(|x| *x as f64 / 256.0)
+}
+
+fn labels(array: &IdxArray, start: usize, count: usize) -> Array2<f64> {
+    Array2::from_shape_vec((count, 1), array.data[start..start + count].to_vec())
+        .expect("IDX labels match their shape")
+        .map(|x| *x as f64)
+}
+
+/// Loads MNIST from the IDX files in `directory`. The validation set follows
+/// the training set in the training files, the test set comes from the
+/// `t10k` files.
 #[autometrics]
-pub fn mnist_data_set(training_set_size: u32, val_set_size: u32, test_set_size: u32) -> DataSet {
-    // Deconstruct the returned Mnist struct.
-    let Mnist {
-        trn_img,
-        trn_lbl,
-        val_img,
-        val_lbl,
-        tst_img,
-        tst_lbl,
-    } = MnistBuilder::new()
-        .label_format_digit()
-        .training_set_length(training_set_size)
-        .validation_set_length(val_set_size)
-        .test_set_length(test_set_size)
-        .finalize();
-
-    let train_data = Array3::from_shape_vec((training_set_size as usize, 28, 28), trn_img)
-        .expect("Error converting images to Array3 struct")
-        .map(|x| *x as f64 / 256.0);
-
-    let train_labels: Array2<f64> =
-        Array2::from_shape_vec((training_set_size as usize, 1), trn_lbl)
-            .expect("Error converting training labels to Array2 struct")
-            .map(|x| *x as f64);
-
-    let val_data: ArrayBase<ndarray::OwnedRepr<f64>, Dim<[usize; 3]>> =
-        Array3::from_shape_vec((val_set_size as usize, 28, 28), val_img)
-            .expect("Error converting images to Array3 struct")
-            .map(|x| *x as f64 / 256.0);
-
-    let val_labels: Array2<f64> = Array2::from_shape_vec((val_set_size as usize, 1), val_lbl)
-        .expect("Error converting validation labels to Array2 struct")
-        .map(|x| *x as f64);
-
-    let test_data: ArrayBase<ndarray::OwnedRepr<f64>, Dim<[usize; 3]>> =
-        Array3::from_shape_vec((test_set_size as usize, 28, 28), tst_img)
-            .expect("Error converting images to Array3 struct")
-            .map(|x| *x as f64 / 256.0);
-
-    let test_labels: Array2<f64> = Array2::from_shape_vec((test_set_size as usize, 1), tst_lbl)
-        .expect("Error converting training labels to Array2 struct")
-        .map(|x| *x as f64);
+pub fn mnist_data_set(
+    directory: &Path,
+    training_set_size: u32,
+    val_set_size: u32,
+    test_set_size: u32,
+) -> Result<DataSet> {
+    let (training_set_size, val_set_size, test_set_size) = (
+        training_set_size as usize,
+        val_set_size as usize,
+        test_set_size as usize,
+    );
+    let train_count = training_set_size + val_set_size;
+
+    let train_images = read_idx(directory, TRAIN_IMAGES, train_count, 3)?;
+    let train_label_bytes = read_idx(directory, TRAIN_LABELS, train_count, 1)?;
+    let (test_data, test_labels) = mnist_test_set(directory, test_set_size as u32)?;
+
+    let train_data = images(&train_images, 0, training_set_size);
+    let train_labels = labels(&train_label_bytes, 0, training_set_size);
+    let val_data = images(&train_images, training_set_size, val_set_size);
+    let val_labels = labels(&train_label_bytes, training_set_size, val_set_size);
 
     let mut train_inputs: Vec<Vec<f64>> = Vec::new();
     let mut train_targets: Vec<Vec<f64>> = Vec::new();
 
-    for i in 0..(training_set_size as usize) {
-        let image = train_data
-            .slice(s![i, .., ..])
-            .to_owned()
-            .into_shape((784,))
-            .unwrap()
-            .to_vec();
-
+    for (i, image) in flatten_images(&train_data).into_iter().enumerate() {
         let label =
             convert_number_to_target_vec(train_labels.slice(s![i, ..]).to_vec()[0] as usize);
 
@@ -75,12 +96,151 @@ pub fn mnist_data_set(training_set_size: u32, val_set_size: u32, test_set_size:
         train_targets.push(label);
     }
 
-    DataSet {
+    Ok(DataSet {
         val_data,
         val_labels,
         train_inputs,
         train_targets,
         test_data,
         test_labels,
+    })
+}
+
+/// Loads only the first `test_set_size` images and labels of the MNIST test
+/// set, from the `t10k` files in `directory`.
+#[autometrics]
+pub fn mnist_test_set(directory: &Path, test_set_size: u32) -> Result<(Array3<f64>, Array2<f64>)> {
+    let test_set_size = test_set_size as usize;
+    let test_images = read_idx(directory, TEST_IMAGES, test_set_size, 3)?;
+    let test_labels = read_idx(directory, TEST_LABELS, test_set_size, 1)?;
+
+    Ok((
+        images(&test_images, 0, test_set_size),
+        labels(&test_labels, 0, test_set_size),
+    ))
+}
+
+#[autometrics]
+pub fn flatten_images(data: &Array3<f64>) -> Vec<Vec<f64>> {
+    let (count, height, width) = data.dim();
+
+    (0..count)
+        .map(|i| {
+            data.slice(s![i, .., ..])
+                .to_owned()
+                .into_shape((height * width,))
+                .unwrap()
+                .to_vec()
+        })
+        .collect()
+}
+
+/// Loads an image file as grayscale, resized to `width` x `height` and scaled
+/// like the MNIST images, row by row.
+#[autometrics]
+pub fn load_image(path: &Path, width: u32, height: u32) -> Result<Vec<f64>> {
+    let image = image::open(path).map_err(|source| Error::Image {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    Ok(image
+        .resize_exact(width, height, FilterType::Triangle)
+        .to_luma8()
+        .pixels()
+        .map(|pixel| pixel[0] as f64 / 256.0)
+        .collect())
+}
+
+/// Saves `pixels`, row by row and scaled like the MNIST images, as a grayscale
+/// image in the format given by the extension of `path`.
+#[autometrics]
+pub fn save_image(path: &Path, pixels: &[f64], width: u32, height: u32) -> Result<()> {
+    let bytes = pixels
+        .iter()
+        .map(|x| (x * 256.0).clamp(0.0, 255.0) as u8)
+        .collect();
+
+    let image = GrayImage::from_raw(width, height, bytes).ok_or(Error::InvalidLength {
+        name: "image",
+        expected: (width * height) as usize,
+        found: pixels.len(),
+    })?;
+
+    image.save(path).map_err(|source| Error::Image {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_mnist_data_set_from_idx_files() {
+        let directory = std::env::temp_dir().join("autoencoder-mnist");
+        fs::create_dir_all(&directory).unwrap();
+        let _ = fs::remove_file(directory.join(TEST_LABELS));
+
+        // Three 2x2 training images labelled 0 to 2, one gzipped test image
+        IdxArray::new(vec![3, 2, 2], (0..12).collect())
+            .write(directory.join(TRAIN_IMAGES))
+            .unwrap();
+        IdxArray::new(vec![3], vec![0, 1, 2])
+            .write(directory.join(TRAIN_LABELS))
+            .unwrap();
+        IdxArray::new(vec![1, 2, 2], vec![128; 4])
+            .write(directory.join(format!("{}.gz", TEST_IMAGES)))
+            .unwrap();
+
+        assert!(matches!(
+            mnist_data_set(&directory, 2, 1, 1),
+            Err(Error::Idx(IdxError::Missing { .. }))
+        ));
+
+        IdxArray::new(vec![1], vec![7])
+            .write(directory.join(TEST_LABELS))
+            .unwrap();
+        let data_set = mnist_data_set(&directory, 2, 1, 1).unwrap();
+
+        assert_eq!(
+            data_set.train_inputs[1],
+            vec![4.0 / 256.0, 5.0 / 256.0, 6.0 / 256.0, 7.0 / 256.0]
+        );
+        assert_eq!(data_set.train_targets[1][1], 1.0);
+        assert_eq!(data_set.val_data[[0, 1, 1]], 11.0 / 256.0);
+        assert_eq!(data_set.val_labels[[0, 0]], 2.0);
+        assert_eq!(data_set.test_data[[0, 0, 0]], 0.5);
+        assert_eq!(data_set.test_labels[[0, 0]], 7.0);
+
+        assert!(matches!(
+            mnist_data_set(&directory, 3, 1, 1),
+            Err(Error::Idx(IdxError::TooFewItems { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_mnist_test_set_needs_only_test_files() {
+        let directory = std::env::temp_dir().join("autoencoder-mnist-test-set");
+        fs::create_dir_all(&directory).unwrap();
+
+        IdxArray::new(vec![2, 2, 2], vec![0, 64, 128, 192, 0, 0, 0, 0])
+            .write(directory.join(TEST_IMAGES))
+            .unwrap();
+        IdxArray::new(vec![2], vec![3, 4])
+            .write(directory.join(format!("{}.gz", TEST_LABELS)))
+            .unwrap();
+
+        let (test_data, test_labels) = mnist_test_set(&directory, 1).unwrap();
+        assert_eq!(test_data.dim(), (1, 2, 2));
+        assert_eq!(test_data[[0, 1, 0]], 0.5);
+        assert_eq!(test_labels.into_raw_vec(), vec![3.0]);
+        assert!(matches!(
+            mnist_data_set(&directory, 1, 1, 1),
+            Err(Error::Idx(IdxError::Missing { .. }))
+        ));
     }
 }
@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+use super::model_file::TrainingMetadata;
+
+/// Validation metric an early stopping run watches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Metric {
+    /// Validation accuracy of a classifier.
+    Accuracy,
+    /// Validation reconstruction error of an autoencoder.
+    ReconstructionError,
+    /// Training loss of the epoch.
+    Loss,
+}
+
+impl Metric {
+    pub fn higher_is_better(&self) -> bool {
+        *self == Metric::Accuracy
+    }
+
+    /// The value of the metric in the metadata of the last epoch.
+    pub fn value(&self, metadata: &TrainingMetadata) -> Option<f64> {
+        match self {
+            Metric::Accuracy => metadata.accuracy,
+            Metric::ReconstructionError => metadata.reconstruction_error,
+            Metric::Loss => metadata.loss,
+        }
+    }
+}
+
+/// Stops training once `metric` has not improved by more than `min_delta` for
+/// `patience` epochs, then restores the weights of the best epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EarlyStopping {
+    /// Accuracy for a classifier and reconstruction error for an autoencoder
+    /// when `None`.
+    pub metric: Option<Metric>,
+    pub patience: usize,
+    pub min_delta: f64,
+    pub restore_best: bool,
+}
+
+impl Default for EarlyStopping {
+    fn default() -> EarlyStopping {
+        EarlyStopping {
+            metric: None,
+            patience: 5,
+            min_delta: 0.0,
+            restore_best: true,
+        }
+    }
+}
+
+/// The best value of a run so far, its snapshot and the epochs since, saved
+/// in checkpoints to continue early stopping from.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BestState<S> {
+    pub value: f64,
+    pub snapshot: S,
+    pub stale_epochs: usize,
+}
+
+/// The best epoch of a run so far, with a snapshot of the model taken then.
+pub struct BestEpoch<S> {
+    pub metric: Metric,
+    early_stopping: EarlyStopping,
+    best: Option<(f64, S)>,
+    stale_epochs: usize,
+}
+
+impl<S> BestEpoch<S> {
+    pub fn new(early_stopping: EarlyStopping, default_metric: Metric) -> BestEpoch<S> {
+        BestEpoch {
+            metric: early_stopping.metric.unwrap_or(default_metric),
+            early_stopping,
+            best: None,
+            stale_epochs: 0,
+        }
+    }
+
+    /// Records the metric of an epoch, taking a `snapshot` when it is the best
+    /// so far. Returns true when training should stop.
+    pub fn observe(&mut self, value: f64, snapshot: impl FnOnce() -> S) -> bool {
+        let improvement = match &self.best {
+            None => f64::INFINITY,
+            Some((best, _)) if self.metric.higher_is_better() => value - best,
+            Some((best, _)) => best - value,
+        };
+
+        if improvement > self.early_stopping.min_delta {
+            self.best = Some((value, snapshot()));
+            self.stale_epochs = 0;
+            return false;
+        }
+
+        self.stale_epochs += 1;
+        self.stale_epochs > self.early_stopping.patience
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.best.as_ref().map(|(value, _)| *value)
+    }
+
+    /// Continues from the state of an earlier run.
+    pub fn restore(&mut self, state: BestState<S>) {
+        self.best = Some((state.value, state.snapshot));
+        self.stale_epochs = state.stale_epochs;
+    }
+
+    /// The snapshot of the best epoch, if it should be restored.
+    pub fn into_snapshot(self) -> Option<S> {
+        match self.best {
+            Some((_, snapshot)) if self.early_stopping.restore_best => Some(snapshot),
+            _ => None,
+        }
+    }
+}
+
+impl<S: Clone> BestEpoch<S> {
+    /// `None` until an epoch was observed.
+    pub fn state(&self) -> Option<BestState<S>> {
+        self.best.as_ref().map(|(value, snapshot)| BestState {
+            value: *value,
+            snapshot: snapshot.clone(),
+            stale_epochs: self.stale_epochs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stops_after_patience() {
+        let early_stopping = EarlyStopping {
+            metric: None,
+            patience: 1,
+            min_delta: 0.5,
+            restore_best: true,
+        };
+        let mut best = BestEpoch::new(early_stopping, Metric::Accuracy);
+
+        assert!(!best.observe(90.0, || 1));
+        assert!(!best.observe(91.0, || 2));
+        assert!(!best.observe(92.0, || 3));
+        // Neither is more than the minimum delta above 92.0
+        assert!(!best.observe(92.4, || 4));
+        assert!(best.observe(92.3, || 5));
+
+        assert_eq!(best.value(), Some(92.0));
+        assert_eq!(best.into_snapshot(), Some(3));
+    }
+
+    #[test]
+    fn test_lower_is_better_for_errors() {
+        let mut best = BestEpoch::new(EarlyStopping::default(), Metric::ReconstructionError);
+
+        best.observe(0.5, || "first");
+        best.observe(0.7, || "second");
+        best.observe(0.2, || "third");
+
+        assert_eq!(best.into_snapshot(), Some("third"));
+    }
+
+    #[test]
+    fn test_restored_state_keeps_patience() {
+        let early_stopping = EarlyStopping {
+            patience: 1,
+            ..EarlyStopping::default()
+        };
+        let mut best = BestEpoch::new(early_stopping, Metric::Accuracy);
+        assert!(best.state().is_none());
+        best.observe(90.0, || 1);
+        best.observe(80.0, || 2);
+
+        let mut restored = BestEpoch::new(early_stopping, Metric::Accuracy);
+        restored.restore(best.state().unwrap());
+
+        assert!(restored.observe(85.0, || 3));
+        assert_eq!(restored.into_snapshot(), Some(1));
+    }
+}
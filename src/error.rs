@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use super::{config::ConfigError, idx::IdxError, model_file::ModelFileError};
+
+/// Errors of the matrix, network and model file APIs. Shapes are given as
+/// `(rows, cols)`.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(
+        "cannot {operation} a {}x{} matrix and a {}x{} matrix",
+        .left.0, .left.1, .right.0, .right.1
+    )]
+    ShapeMismatch {
+        operation: &'static str,
+        left: (usize, usize),
+        right: (usize, usize),
+    },
+    #[error("invalid {name} length: expected {expected}, found {found}")]
+    InvalidLength {
+        name: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    #[error("back propagation needs a forward pass of the same batch first")]
+    MissingForwardPass,
+    #[error("cannot convert an empty result vector to a number")]
+    EmptyResult,
+    #[error("unable to access {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("unable to read or write image {path}: {source}")]
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    #[error("unable to preload {path}: {source}")]
+    Preload { path: PathBuf, source: Box<Error> },
+    #[error("unable to load the data set: {source}")]
+    DataSet { source: Box<Error> },
+    #[error("training interrupted, continue it with --resume from {checkpoint}")]
+    Interrupted { checkpoint: PathBuf },
+    #[error(transparent)]
+    ModelFile(#[from] ModelFileError),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Idx(#[from] IdxError),
+}
+
+impl Error {
+    /// Whether a later round of a session would fail the same way, as it
+    /// loads the same config, data and model.
+    pub fn repeats(&self) -> bool {
+        matches!(
+            self,
+            Error::Preload { .. }
+                | Error::DataSet { .. }
+                | Error::Config(_)
+                | Error::ModelFile(_)
+                | Error::Idx(_)
+        )
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,46 @@
+use std::{fmt::Debug, iter::Sum};
+
+use num_traits::{Float as NumFloat, NumAssign};
+use rand::distributions::uniform::SampleUniform;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Element type of matrices and networks, `f32` or `f64`. Hyperparameters,
+/// losses and data sets stay `f64` and are converted at the boundary.
+pub trait Float:
+    NumFloat
+    + NumAssign
+    + Sum
+    + SampleUniform
+    + Default
+    + Debug
+    + Send
+    + Sync
+    + Serialize
+    + DeserializeOwned
+    + 'static
+{
+    /// Converts a constant or a value of a data set, rounding for `f32`.
+    fn of(value: f64) -> Self;
+
+    fn as_f64(self) -> f64;
+}
+
+impl Float for f32 {
+    fn of(value: f64) -> f32 {
+        value as f32
+    }
+
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Float for f64 {
+    fn of(value: f64) -> f64 {
+        value
+    }
+
+    fn as_f64(self) -> f64 {
+        self
+    }
+}
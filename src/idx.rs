@@ -0,0 +1,252 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder};
+use thiserror::Error;
+
+/// Type code of unsigned bytes, the only element type of the MNIST files.
+const UNSIGNED_BYTE: u8 = 0x08;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Debug, Error)]
+pub enum IdxError {
+    #[error("{name} not found in {directory}, expected it uncompressed or as {name}.gz")]
+    Missing { directory: PathBuf, name: String },
+    #[error("unable to access IDX file {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("IDX file {path} is malformed: {reason}")]
+    Malformed { path: PathBuf, reason: String },
+    #[error("IDX file {path} is truncated: expected {expected} bytes of data, found {found}")]
+    Truncated {
+        path: PathBuf,
+        expected: usize,
+        found: usize,
+    },
+    #[error("IDX file {path} holds {available} items, {requested} were asked for")]
+    TooFewItems {
+        path: PathBuf,
+        requested: usize,
+        available: usize,
+    },
+}
+
+/// An IDX array of unsigned bytes, stored row-major.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdxArray {
+    pub shape: Vec<usize>,
+    pub data: Vec<u8>,
+}
+
+impl IdxArray {
+    pub fn new(shape: Vec<usize>, data: Vec<u8>) -> IdxArray {
+        assert_eq!(
+            shape.iter().product::<usize>(),
+            data.len(),
+            "IDX data does not match its shape"
+        );
+
+        IdxArray { shape, data }
+    }
+
+    /// Items along the first dimension, e.g. images.
+    pub fn len(&self) -> usize {
+        self.shape.first().copied().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes of an item, e.g. the pixels of an image.
+    pub fn item_size(&self) -> usize {
+        self.shape.iter().skip(1).product()
+    }
+
+    /// Reads an IDX file, gzipped or not, detected from its contents.
+    pub fn read(path: impl AsRef<Path>) -> Result<IdxArray, IdxError> {
+        let path = path.as_ref();
+        let io_error = |source| IdxError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        let malformed = |reason: &str| IdxError::Malformed {
+            path: path.to_path_buf(),
+            reason: String::from(reason),
+        };
+
+        let mut bytes = fs::read(path).map_err(io_error)?;
+        if bytes.starts_with(&GZIP_MAGIC) {
+            let mut decompressed = vec![];
+            GzDecoder::new(bytes.as_slice())
+                .read_to_end(&mut decompressed)
+                .map_err(io_error)?;
+            bytes = decompressed;
+        }
+
+        if bytes.len() < 4 {
+            return Err(malformed("missing the magic number"));
+        }
+        if bytes[0] != 0 || bytes[1] != 0 {
+            return Err(malformed(
+                "the magic number should start with two zero bytes",
+            ));
+        }
+        if bytes[2] != UNSIGNED_BYTE {
+            return Err(malformed(&format!(
+                "element type 0x{:02x} is not supported, expected unsigned bytes",
+                bytes[2]
+            )));
+        }
+
+        let dimensions = bytes[3] as usize;
+        let header = 4 + 4 * dimensions;
+        if bytes.len() < header {
+            return Err(malformed("the dimensions are cut off"));
+        }
+        let shape: Vec<usize> = bytes[4..header]
+            .chunks(4)
+            .map(|size| u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize)
+            .collect();
+
+        let expected = shape.iter().product::<usize>();
+        let found = bytes.len() - header;
+        if found < expected {
+            return Err(IdxError::Truncated {
+                path: path.to_path_buf(),
+                expected,
+                found,
+            });
+        }
+
+        bytes.truncate(header + expected);
+        bytes.drain(..header);
+
+        Ok(IdxArray { shape, data: bytes })
+    }
+
+    /// Writes the array as an IDX file, gzipped when `path` ends in `.gz`.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), IdxError> {
+        let path = path.as_ref();
+        let io_error = |source| IdxError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        let mut bytes = vec![0, 0, UNSIGNED_BYTE, self.shape.len() as u8];
+        for &size in self.shape.iter() {
+            bytes.extend_from_slice(&(size as u32).to_be_bytes());
+        }
+        bytes.extend_from_slice(&self.data);
+
+        if path.extension().is_some_and(|extension| extension == "gz") {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&bytes).map_err(io_error)?;
+            bytes = encoder.finish().map_err(io_error)?;
+        }
+
+        fs::write(path, bytes).map_err(io_error)
+    }
+
+    /// The first `count` items, failing when there are fewer.
+    pub fn take(mut self, count: usize, path: &Path) -> Result<IdxArray, IdxError> {
+        if count > self.len() {
+            return Err(IdxError::TooFewItems {
+                path: path.to_path_buf(),
+                requested: count,
+                available: self.len(),
+            });
+        }
+
+        self.data.truncate(count * self.item_size());
+        self.shape[0] = count;
+
+        Ok(self)
+    }
+}
+
+/// The file `name` in `directory`, uncompressed or gzipped.
+pub fn find(directory: impl AsRef<Path>, name: &str) -> Result<PathBuf, IdxError> {
+    let directory = directory.as_ref();
+
+    [name.to_string(), format!("{}.gz", name)]
+        .into_iter()
+        .map(|name| directory.join(name))
+        .find(|path| path.is_file())
+        .ok_or_else(|| IdxError::Missing {
+            directory: directory.to_path_buf(),
+            name: name.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(name);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn test_round_trip_plain_and_gzipped() {
+        let directory = directory("autoencoder-idx");
+        let array = IdxArray::new(vec![2, 2, 3], (0..12).collect());
+
+        for name in ["images-idx3-ubyte", "images-gz-idx3-ubyte.gz"] {
+            array.write(directory.join(name)).unwrap();
+            assert_eq!(IdxArray::read(directory.join(name)).unwrap(), array);
+        }
+
+        assert_eq!(
+            find(&directory, "images-gz-idx3-ubyte").unwrap(),
+            directory.join("images-gz-idx3-ubyte.gz")
+        );
+        assert!(matches!(
+            find(&directory, "labels-idx1-ubyte"),
+            Err(IdxError::Missing { .. })
+        ));
+
+        let path = directory.join("images-idx3-ubyte");
+        let first = IdxArray::read(&path).unwrap().take(1, &path).unwrap();
+        assert_eq!(first, IdxArray::new(vec![1, 2, 3], (0..6).collect()));
+        assert!(matches!(
+            IdxArray::read(&path).unwrap().take(3, &path),
+            Err(IdxError::TooFewItems { .. })
+        ));
+    }
+
+    #[test]
+    fn test_truncated_and_malformed_files() {
+        let directory = directory("autoencoder-idx-invalid");
+        let path = directory.join("labels-idx1-ubyte");
+
+        IdxArray::new(vec![4], vec![1, 2, 3, 4])
+            .write(&path)
+            .unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.pop();
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            IdxArray::read(&path),
+            Err(IdxError::Truncated {
+                expected: 4,
+                found: 3,
+                ..
+            })
+        ));
+
+        bytes[2] = 0x0d;
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            IdxArray::read(&path),
+            Err(IdxError::Malformed { .. })
+        ));
+    }
+}
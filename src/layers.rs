@@ -0,0 +1,915 @@
+use autometrics::autometrics;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    activations::Activation,
+    convolution::{Conv2D, ConvTranspose2D, Flatten, Pool2D, Pooling, Shape, Window},
+    error::{Error, Result},
+    float::Float,
+    matrix::Matrix,
+    model_file::ModelFileError,
+    normalization::{BatchNorm, LayerNorm},
+    random::{RngState, RunRng},
+};
+
+/// Whether layers such as `Dropout` behave as during training or as during
+/// validation and inference.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    Training,
+    #[default]
+    Evaluation,
+}
+
+/// A step of a `Network`. Batches hold one sample per column.
+pub trait Layer<T: Float = f64>: Send {
+    /// Outputs for a batch, keeping what `backward` needs.
+    fn forward(&mut self, inputs: &Matrix<T>) -> Result<Matrix<T>>;
+
+    /// Outputs for a batch without keeping anything, for inference.
+    fn predict(&self, inputs: &Matrix<T>) -> Result<Matrix<T>>;
+
+    /// Takes the gradients with respect to the outputs of the last `forward`,
+    /// keeps the gradients of the parameters averaged over the batch and
+    /// returns the gradients with respect to the inputs.
+    fn backward(&mut self, gradients: &Matrix<T>) -> Result<Matrix<T>>;
+
+    /// Learned values, none for layers without any.
+    fn parameters(&self) -> Vec<&Matrix<T>> {
+        vec![]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        vec![]
+    }
+
+    /// Gradients of the last `backward`, in the order of `parameters`.
+    fn gradients(&self) -> Vec<&Matrix<T>> {
+        vec![]
+    }
+
+    /// Whether parameter `index` holds weights, which regularization applies
+    /// to, rather than biases or normalization parameters.
+    fn is_weight(&self, _index: usize) -> bool {
+        false
+    }
+
+    fn input_size(&self) -> usize;
+
+    fn output_size(&self) -> usize;
+
+    /// Switches between training and evaluation, for the layers that differ.
+    fn set_mode(&mut self, _mode: Mode) {}
+
+    /// The activation applied by the layer, which a loss may fuse with its own
+    /// gradient.
+    fn activation(&self) -> Option<Activation> {
+        None
+    }
+
+    /// The layer for saving, with its values converted to `f64`.
+    fn to_data(&self) -> LayerData;
+}
+
+/// Saved form of every kind of layer.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum LayerData {
+    Dense {
+        weights: Matrix,
+        biases: Matrix,
+    },
+    Activation {
+        activation: Activation,
+        size: usize,
+    },
+    Conv2D {
+        input: Shape,
+        window: Window,
+        weights: Matrix,
+        biases: Matrix,
+    },
+    ConvTranspose2D {
+        input: Shape,
+        window: Window,
+        weights: Matrix,
+        biases: Matrix,
+    },
+    Pool2D {
+        pooling: Pooling,
+        input: Shape,
+        window: Window,
+    },
+    Flatten {
+        input: Shape,
+    },
+    Dropout {
+        rate: f64,
+        size: usize,
+        /// Generator of the next masks, a fresh one when missing, as in files
+        /// written before it was saved.
+        #[serde(default)]
+        rng: Option<RngState>,
+    },
+    BatchNorm {
+        channels: usize,
+        positions: usize,
+        momentum: f64,
+        epsilon: f64,
+        scale: Matrix,
+        shift: Matrix,
+        running_mean: Matrix,
+        running_variance: Matrix,
+    },
+    LayerNorm {
+        epsilon: f64,
+        scale: Matrix,
+        shift: Matrix,
+    },
+}
+
+pub(crate) fn check_inputs<T: Float>(inputs: &Matrix<T>, size: usize) -> Result<()> {
+    if inputs.rows != size {
+        return Err(Error::InvalidLength {
+            name: "inputs",
+            expected: size,
+            found: inputs.rows,
+        });
+    }
+
+    Ok(())
+}
+
+/// Gradients must match the outputs of the last forward pass.
+pub(crate) fn check_gradients<T: Float>(
+    gradients: &Matrix<T>,
+    size: usize,
+    batch: usize,
+) -> Result<()> {
+    if gradients.shape() != (size, batch) {
+        return Err(Error::ShapeMismatch {
+            operation: "back propagate",
+            left: (size, batch),
+            right: gradients.shape(),
+        });
+    }
+
+    Ok(())
+}
+
+fn invalid(reason: String) -> ModelFileError {
+    ModelFileError::Invalid(reason)
+}
+
+fn check_matrix(
+    name: &str,
+    matrix: &Matrix,
+    rows: usize,
+    cols: usize,
+) -> std::result::Result<(), ModelFileError> {
+    if matrix.rows != rows || matrix.cols != cols {
+        return Err(invalid(format!(
+            "{} should be {}x{}, found {}x{}",
+            name, rows, cols, matrix.rows, matrix.cols
+        )));
+    }
+
+    Ok(())
+}
+
+impl LayerData {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LayerData::Dense { .. } => "dense",
+            LayerData::Activation { .. } => "activation",
+            LayerData::Conv2D { .. } => "conv2d",
+            LayerData::ConvTranspose2D { .. } => "conv_transpose2d",
+            LayerData::Pool2D { .. } => "pool2d",
+            LayerData::Flatten { .. } => "flatten",
+            LayerData::Dropout { .. } => "dropout",
+            LayerData::BatchNorm { .. } => "batch_norm",
+            LayerData::LayerNorm { .. } => "layer_norm",
+        }
+    }
+
+    fn output_shape(&self) -> Option<Shape> {
+        match self {
+            LayerData::Conv2D {
+                input,
+                window,
+                weights,
+                ..
+            } => window.output(*input, weights.rows),
+            LayerData::ConvTranspose2D {
+                input,
+                window,
+                weights,
+                ..
+            } => {
+                window.transposed_output(*input, weights.cols / (window.size * window.size).max(1))
+            }
+            LayerData::Pool2D { input, window, .. } => window.pool_output(*input),
+            _ => None,
+        }
+    }
+
+    pub fn input_size(&self) -> usize {
+        match self {
+            LayerData::Dense { weights, .. } => weights.cols,
+            LayerData::Activation { size, .. } | LayerData::Dropout { size, .. } => *size,
+            LayerData::BatchNorm {
+                channels,
+                positions,
+                ..
+            } => channels * positions,
+            LayerData::LayerNorm { scale, .. } => scale.rows,
+            LayerData::Conv2D { input, .. }
+            | LayerData::ConvTranspose2D { input, .. }
+            | LayerData::Pool2D { input, .. }
+            | LayerData::Flatten { input } => input.size(),
+        }
+    }
+
+    pub fn output_size(&self) -> usize {
+        match self {
+            LayerData::Dense { weights, .. } => weights.rows,
+            LayerData::Activation { size, .. } | LayerData::Dropout { size, .. } => *size,
+            LayerData::Flatten { input } => input.size(),
+            LayerData::BatchNorm { .. } | LayerData::LayerNorm { .. } => self.input_size(),
+            _ => self.output_shape().map_or(0, |shape| shape.size()),
+        }
+    }
+
+    /// Part of the model name, e.g. the size of a dense layer. Layers that
+    /// keep the shape of their inputs have none.
+    pub fn name(&self) -> Option<String> {
+        match self {
+            LayerData::Dense { weights, .. } => Some(weights.rows.to_string()),
+            LayerData::Conv2D { weights, .. } => Some(format!("conv{}", weights.rows)),
+            LayerData::ConvTranspose2D { .. } => self
+                .output_shape()
+                .map(|shape| format!("deconv{}", shape.channels)),
+            LayerData::Pool2D {
+                pooling: Pooling::Max,
+                ..
+            } => Some(String::from("maxpool")),
+            LayerData::Pool2D {
+                pooling: Pooling::Average,
+                ..
+            } => Some(String::from("avgpool")),
+            LayerData::Activation { .. }
+            | LayerData::Flatten { .. }
+            | LayerData::Dropout { .. }
+            | LayerData::BatchNorm { .. }
+            | LayerData::LayerNorm { .. } => None,
+        }
+    }
+
+    /// The kind, shapes and settings of the layer without its learned values,
+    /// equal for layers that can take each other's place.
+    pub fn description(&self) -> String {
+        match self {
+            LayerData::Dense { weights, .. } => {
+                format!("dense {} to {}", weights.cols, weights.rows)
+            }
+            LayerData::Activation { activation, size } => {
+                format!("activation {:?} of {}", activation, size)
+            }
+            LayerData::Conv2D {
+                input,
+                window,
+                weights,
+                ..
+            } => format!(
+                "conv2d of {:?} with {:?} to {}",
+                input, window, weights.rows
+            ),
+            LayerData::ConvTranspose2D { input, window, .. } => format!(
+                "conv_transpose2d of {:?} with {:?} to {:?}",
+                input,
+                window,
+                self.output_shape()
+            ),
+            LayerData::Pool2D {
+                pooling,
+                input,
+                window,
+            } => format!("pool2d {:?} of {:?} with {:?}", pooling, input, window),
+            LayerData::Flatten { input } => format!("flatten of {:?}", input),
+            LayerData::Dropout { rate, size, .. } => format!("dropout {} of {}", rate, size),
+            LayerData::BatchNorm {
+                channels,
+                positions,
+                momentum,
+                epsilon,
+                ..
+            } => format!(
+                "batch_norm of {}x{} with momentum {} and epsilon {}",
+                channels, positions, momentum, epsilon
+            ),
+            LayerData::LayerNorm { epsilon, scale, .. } => {
+                format!("layer_norm of {} with epsilon {}", scale.rows, epsilon)
+            }
+        }
+    }
+
+    pub fn activation(&self) -> Option<Activation> {
+        match self {
+            LayerData::Activation { activation, .. } => Some(*activation),
+            _ => None,
+        }
+    }
+
+    pub fn parameters(&self) -> Vec<&Matrix> {
+        match self {
+            LayerData::Dense { weights, biases }
+            | LayerData::Conv2D {
+                weights, biases, ..
+            }
+            | LayerData::ConvTranspose2D {
+                weights, biases, ..
+            } => vec![weights, biases],
+            LayerData::BatchNorm { scale, shift, .. }
+            | LayerData::LayerNorm { scale, shift, .. } => {
+                vec![scale, shift]
+            }
+            _ => vec![],
+        }
+    }
+
+    pub fn validate(&self) -> std::result::Result<(), ModelFileError> {
+        match self {
+            LayerData::Dense { weights, biases } => {
+                if weights.rows == 0 || weights.cols == 0 {
+                    return Err(invalid(String::from("dense weights are empty")));
+                }
+                check_matrix("dense biases", biases, weights.rows, 1)
+            }
+            LayerData::Activation { size, .. } if *size == 0 => {
+                Err(invalid(String::from("activation layer has no values")))
+            }
+            LayerData::Conv2D {
+                input,
+                window,
+                weights,
+                biases,
+            } => {
+                if window.output(*input, 1).is_none() || weights.rows == 0 {
+                    return Err(invalid(format!(
+                        "convolution window {:?} does not fit the input {:?}",
+                        window, input
+                    )));
+                }
+                let kernel = input.channels * window.size * window.size;
+                check_matrix("convolution kernels", weights, weights.rows, kernel)?;
+                check_matrix("convolution biases", biases, weights.rows, 1)
+            }
+            LayerData::ConvTranspose2D {
+                input,
+                window,
+                weights,
+                biases,
+            } => {
+                let area = window.size * window.size;
+                let filters = weights.cols / area.max(1);
+                if filters == 0 || self.output_shape().is_none() {
+                    return Err(invalid(format!(
+                        "transposed convolution window {:?} does not fit the input {:?}",
+                        window, input
+                    )));
+                }
+                check_matrix(
+                    "transposed convolution kernels",
+                    weights,
+                    input.channels,
+                    filters * area,
+                )?;
+                check_matrix("transposed convolution biases", biases, filters, 1)
+            }
+            LayerData::Pool2D { input, window, .. } if window.pool_output(*input).is_none() => {
+                Err(invalid(format!(
+                    "pooling window {:?} does not fit the input {:?}",
+                    window, input
+                )))
+            }
+            LayerData::Flatten { input } if input.size() == 0 => {
+                Err(invalid(String::from("flatten layer has no values")))
+            }
+            LayerData::Dropout { rate, size, .. } => {
+                if !(0.0..1.0).contains(rate) {
+                    return Err(invalid(format!(
+                        "dropout rate should be at least 0 and below 1, found {}",
+                        rate
+                    )));
+                }
+                if *size == 0 {
+                    return Err(invalid(String::from("dropout layer has no values")));
+                }
+                Ok(())
+            }
+            LayerData::BatchNorm {
+                channels,
+                positions,
+                momentum,
+                epsilon,
+                scale,
+                shift,
+                running_mean,
+                running_variance,
+            } => {
+                if channels * positions == 0 || !(0.0..=1.0).contains(momentum) || *epsilon <= 0.0 {
+                    return Err(invalid(String::from(
+                        "batch normalization needs values, a momentum between 0 and 1 and a positive epsilon",
+                    )));
+                }
+                check_matrix("batch normalization scale", scale, *channels, 1)?;
+                check_matrix("batch normalization shift", shift, *channels, 1)?;
+                check_matrix("batch normalization mean", running_mean, *channels, 1)?;
+                check_matrix(
+                    "batch normalization variance",
+                    running_variance,
+                    *channels,
+                    1,
+                )
+            }
+            LayerData::LayerNorm {
+                epsilon,
+                scale,
+                shift,
+            } => {
+                if scale.rows == 0 || *epsilon <= 0.0 {
+                    return Err(invalid(String::from(
+                        "layer normalization needs values and a positive epsilon",
+                    )));
+                }
+                check_matrix("layer normalization scale", scale, scale.rows, 1)?;
+                check_matrix("layer normalization shift", shift, scale.rows, 1)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Rebuilds the layer in precision `T`, panics unless `validate` passed.
+    pub fn build<T: Float>(&self) -> Box<dyn Layer<T>> {
+        match self {
+            LayerData::Dense { weights, biases } => {
+                Box::new(Dense::from_parameters(weights.cast(), biases.cast()))
+            }
+            LayerData::Activation { activation, size } => {
+                Box::new(ActivationLayer::new(*activation, *size))
+            }
+            LayerData::Conv2D {
+                input,
+                window,
+                weights,
+                biases,
+            } => Box::new(Conv2D::from_parameters(
+                *input,
+                *window,
+                weights.cast(),
+                biases.cast(),
+            )),
+            LayerData::ConvTranspose2D {
+                input,
+                window,
+                weights,
+                biases,
+            } => Box::new(ConvTranspose2D::from_parameters(
+                *input,
+                *window,
+                weights.cast(),
+                biases.cast(),
+            )),
+            LayerData::Pool2D {
+                pooling,
+                input,
+                window,
+            } => Box::new(Pool2D::new(*pooling, *input, *window)),
+            LayerData::Flatten { input } => Box::new(Flatten::new(*input)),
+            LayerData::Dropout { rate, size, rng } => Box::new(match rng {
+                Some(rng) => Dropout::from_rng(*rate, *size, rng.rng()),
+                None => Dropout::new(*rate, *size),
+            }),
+            LayerData::BatchNorm {
+                channels,
+                positions,
+                momentum,
+                epsilon,
+                scale,
+                shift,
+                running_mean,
+                running_variance,
+            } => Box::new(BatchNorm::from_parameters(
+                *channels,
+                *positions,
+                *momentum,
+                *epsilon,
+                [
+                    scale.cast(),
+                    shift.cast(),
+                    running_mean.cast(),
+                    running_variance.cast(),
+                ],
+            )),
+            LayerData::LayerNorm {
+                epsilon,
+                scale,
+                shift,
+            } => Box::new(LayerNorm::from_parameters(
+                *epsilon,
+                scale.cast(),
+                shift.cast(),
+            )),
+        }
+    }
+}
+
+/// Fully connected layer computing `weights * inputs + biases`.
+pub struct Dense<T: Float = f64> {
+    weights: Matrix<T>,
+    biases: Matrix<T>,
+    weight_gradients: Matrix<T>,
+    bias_gradients: Matrix<T>,
+    /// The inputs of the last `forward`.
+    inputs: Option<Matrix<T>>,
+}
+
+#[autometrics]
+impl<T: Float> Dense<T> {
+    pub fn new(inputs: usize, outputs: usize) -> Dense<T> {
+        Dense::with_rng(inputs, outputs, &mut rand::thread_rng())
+    }
+
+    /// Like `new`, drawing the initial weights and biases from `rng`.
+    pub fn with_rng(inputs: usize, outputs: usize, rng: &mut impl Rng) -> Dense<T> {
+        let weights = Matrix::random(outputs, inputs, rng);
+        let biases = Matrix::random(outputs, 1, rng);
+
+        Dense::from_parameters(weights, biases)
+    }
+
+    pub fn from_parameters(weights: Matrix<T>, biases: Matrix<T>) -> Dense<T> {
+        if biases.shape() != (weights.rows, 1) {
+            panic!("Biases do not match the weights");
+        }
+
+        Dense {
+            weight_gradients: Matrix::zeros(weights.rows, weights.cols),
+            bias_gradients: Matrix::zeros(biases.rows, 1),
+            weights,
+            biases,
+            inputs: None,
+        }
+    }
+}
+
+impl<T: Float> Layer<T> for Dense<T> {
+    fn forward(&mut self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        let outputs = self.predict(inputs)?;
+        self.inputs = Some(inputs.clone());
+
+        Ok(outputs)
+    }
+
+    fn predict(&self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        check_inputs(inputs, self.weights.cols)?;
+
+        Ok(self.weights.multiply(inputs).add_column(&self.biases))
+    }
+
+    fn backward(&mut self, gradients: &Matrix<T>) -> Result<Matrix<T>> {
+        let inputs = self.inputs.as_ref().ok_or(Error::MissingForwardPass)?;
+        check_gradients(gradients, self.weights.rows, inputs.cols)?;
+
+        let batch_scale = T::of(1.0 / inputs.cols as f64);
+
+        self.weight_gradients = gradients.multiply(&inputs.transpose());
+        self.weight_gradients.scale_assign(batch_scale);
+        self.bias_gradients = gradients.sum_columns();
+        self.bias_gradients.scale_assign(batch_scale);
+
+        Ok(self.weights.transpose().multiply(gradients))
+    }
+
+    fn parameters(&self) -> Vec<&Matrix<T>> {
+        vec![&self.weights, &self.biases]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        vec![&mut self.weights, &mut self.biases]
+    }
+
+    fn gradients(&self) -> Vec<&Matrix<T>> {
+        vec![&self.weight_gradients, &self.bias_gradients]
+    }
+
+    fn is_weight(&self, index: usize) -> bool {
+        index == 0
+    }
+
+    fn input_size(&self) -> usize {
+        self.weights.cols
+    }
+
+    fn output_size(&self) -> usize {
+        self.weights.rows
+    }
+
+    fn to_data(&self) -> LayerData {
+        LayerData::Dense {
+            weights: self.weights.cast(),
+            biases: self.biases.cast(),
+        }
+    }
+}
+
+/// Applies an `Activation` to every value, or every column for softmax.
+pub struct ActivationLayer<T: Float = f64> {
+    activation: Activation,
+    size: usize,
+    /// The inputs and outputs of the last `forward`.
+    cache: Option<(Matrix<T>, Matrix<T>)>,
+}
+
+impl<T: Float> ActivationLayer<T> {
+    pub fn new(activation: Activation, size: usize) -> ActivationLayer<T> {
+        ActivationLayer {
+            activation,
+            size,
+            cache: None,
+        }
+    }
+}
+
+impl<T: Float> Layer<T> for ActivationLayer<T> {
+    fn forward(&mut self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        let outputs = self.predict(inputs)?;
+        self.cache = Some((inputs.clone(), outputs.clone()));
+
+        Ok(outputs)
+    }
+
+    fn predict(&self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        check_inputs(inputs, self.size)?;
+
+        Ok(self.activation.apply(inputs))
+    }
+
+    fn backward(&mut self, gradients: &Matrix<T>) -> Result<Matrix<T>> {
+        let (inputs, outputs) = self.cache.as_ref().ok_or(Error::MissingForwardPass)?;
+        check_gradients(gradients, self.size, inputs.cols)?;
+
+        Ok(self.activation.backward(inputs, outputs, gradients))
+    }
+
+    fn input_size(&self) -> usize {
+        self.size
+    }
+
+    fn output_size(&self) -> usize {
+        self.size
+    }
+
+    fn activation(&self) -> Option<Activation> {
+        Some(self.activation)
+    }
+
+    fn to_data(&self) -> LayerData {
+        LayerData::Activation {
+            activation: self.activation,
+            size: self.size,
+        }
+    }
+}
+
+/// Zeroes each value with probability `rate` during training and scales the
+/// others by `1 / (1 - rate)`, so evaluation uses the values as they are.
+pub struct Dropout<T: Float = f64> {
+    rate: f64,
+    size: usize,
+    mode: Mode,
+    rng: RunRng,
+    /// Batch size of the last `forward` and the mask it applied, if any.
+    batch: Option<usize>,
+    mask: Option<Matrix<T>>,
+}
+
+#[autometrics]
+impl<T: Float> Dropout<T> {
+    pub fn new(rate: f64, size: usize) -> Dropout<T> {
+        Dropout::with_rng(rate, size, &mut rand::thread_rng())
+    }
+
+    /// Like `new`, seeding the masks from `rng`.
+    pub fn with_rng(rate: f64, size: usize, rng: &mut impl Rng) -> Dropout<T> {
+        Dropout::from_rng(rate, size, RunRng::seed_from_u64(rng.gen()))
+    }
+
+    /// Like `new`, drawing the masks from `rng` itself, e.g. one restored
+    /// from saved data.
+    pub fn from_rng(rate: f64, size: usize, rng: RunRng) -> Dropout<T> {
+        if !(0.0..1.0).contains(&rate) {
+            panic!("Dropout rate should be at least 0 and below 1");
+        }
+
+        Dropout {
+            rate,
+            size,
+            mode: Mode::default(),
+            rng,
+            batch: None,
+            mask: None,
+        }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+}
+
+impl<T: Float> Layer<T> for Dropout<T> {
+    fn forward(&mut self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        check_inputs(inputs, self.size)?;
+        self.batch = Some(inputs.cols);
+
+        if self.mode == Mode::Evaluation || self.rate == 0.0 {
+            self.mask = None;
+            return Ok(inputs.clone());
+        }
+
+        let keep = T::of(1.0 / (1.0 - self.rate));
+        let data = (0..inputs.rows * inputs.cols)
+            .map(|_| {
+                if self.rng.gen::<f64>() < self.rate {
+                    T::zero()
+                } else {
+                    keep
+                }
+            })
+            .collect();
+        let mask = Matrix::from_vec(inputs.rows, inputs.cols, data);
+        let outputs = inputs.dot_multiply(&mask);
+        self.mask = Some(mask);
+
+        Ok(outputs)
+    }
+
+    fn predict(&self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        check_inputs(inputs, self.size)?;
+
+        Ok(inputs.clone())
+    }
+
+    fn backward(&mut self, gradients: &Matrix<T>) -> Result<Matrix<T>> {
+        let batch = self.batch.ok_or(Error::MissingForwardPass)?;
+        check_gradients(gradients, self.size, batch)?;
+
+        Ok(match &self.mask {
+            Some(mask) => gradients.dot_multiply(mask),
+            None => gradients.clone(),
+        })
+    }
+
+    fn input_size(&self) -> usize {
+        self.size
+    }
+
+    fn output_size(&self) -> usize {
+        self.size
+    }
+
+    fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    fn to_data(&self) -> LayerData {
+        LayerData::Dropout {
+            rate: self.rate,
+            size: self.size,
+            rng: Some(RngState::from(&self.rng)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::activations::{SIGMOID, TANH};
+
+    use super::*;
+
+    #[test]
+    fn test_dense_gradients_match_finite_differences() {
+        let mut dense = Dense::with_rng(
+            3,
+            2,
+            &mut crate::random::rng(Some(1), crate::random::Stream::Initialization),
+        );
+        let inputs = Matrix::from(vec![vec![0.5, -0.3], vec![0.1, 0.8], vec![-0.7, 0.2]]);
+
+        // Half the sum of the squared outputs, whose gradient is the outputs
+        let loss = |dense: &Dense| -> f64 {
+            dense
+                .predict(&inputs)
+                .unwrap()
+                .to_vec()
+                .iter()
+                .map(|x| x * x / 2.0)
+                .sum()
+        };
+
+        let outputs = dense.forward(&inputs).unwrap();
+        let errors = dense.backward(&outputs).unwrap();
+        let h = 1e-6;
+
+        for i in 0..2 {
+            for j in 0..3 {
+                let gradient = dense.weight_gradients[(i, j)] * 2.0;
+                dense.weights[(i, j)] += h;
+                let plus = loss(&dense);
+                dense.weights[(i, j)] -= 2.0 * h;
+                let minus = loss(&dense);
+                dense.weights[(i, j)] += h;
+
+                assert!(((plus - minus) / (2.0 * h) - gradient).abs() < 1e-6);
+            }
+        }
+        assert_eq!(errors.shape(), inputs.shape());
+    }
+
+    #[test]
+    fn test_layer_data_round_trip() {
+        let mut dense = Dense::<f32>::new(4, 3);
+        let mut activation = ActivationLayer::<f32>::new(TANH, 3);
+        let inputs = Matrix::from(vec![vec![0.5], vec![-0.25], vec![1.0], vec![0.0]]);
+        let outputs = activation
+            .forward(&dense.forward(&inputs).unwrap())
+            .unwrap();
+
+        let data = vec![dense.to_data(), activation.to_data()];
+        let json = serde_json::to_string(&data).unwrap();
+        let restored: Vec<LayerData> = serde_json::from_str(&json).unwrap();
+
+        assert!(restored.iter().all(|layer| layer.validate().is_ok()));
+        assert_eq!(restored[0].name(), Some(String::from("3")));
+        assert_eq!(restored[1].activation(), Some(TANH));
+
+        let layers: Vec<Box<dyn Layer<f32>>> = restored.iter().map(LayerData::build).collect();
+        let predicted = layers[1]
+            .predict(&layers[0].predict(&inputs).unwrap())
+            .unwrap();
+        assert_eq!(predicted.to_vec(), outputs.to_vec());
+    }
+
+    #[test]
+    fn test_dropout_only_in_training() {
+        let mut dropout = Dropout::with_rng(
+            0.5,
+            1000,
+            &mut crate::random::rng(Some(2), crate::random::Stream::Initialization),
+        );
+        let inputs = Matrix::from_vec(1000, 1, vec![1.0; 1000]);
+
+        assert_eq!(dropout.forward(&inputs).unwrap().to_vec(), inputs.to_vec());
+
+        dropout.set_mode(Mode::Training);
+        let outputs = dropout.forward(&inputs).unwrap();
+        let dropped = outputs.to_vec().iter().filter(|&&x| x == 0.0).count();
+        assert!((400..600).contains(&dropped));
+        assert!(outputs.to_vec().iter().all(|&x| x == 0.0 || x == 2.0));
+
+        // Gradients only flow through the kept values
+        let errors = dropout.backward(&inputs).unwrap();
+        assert_eq!(errors.to_vec(), outputs.to_vec());
+        assert_eq!(dropout.predict(&inputs).unwrap().to_vec(), inputs.to_vec());
+    }
+
+    #[test]
+    fn test_invalid_layer_data() {
+        let dense = LayerData::Dense {
+            weights: Matrix::zeros(2, 3),
+            biases: Matrix::zeros(3, 1),
+        };
+        assert!(matches!(dense.validate(), Err(ModelFileError::Invalid(_))));
+
+        let conv = LayerData::Conv2D {
+            input: Shape::new(1, 4, 4),
+            window: Window::new(5, 1, 0),
+            weights: Matrix::zeros(2, 25),
+            biases: Matrix::zeros(2, 1),
+        };
+        assert!(matches!(conv.validate(), Err(ModelFileError::Invalid(_))));
+
+        let activation = LayerData::Activation {
+            activation: SIGMOID,
+            size: 0,
+        };
+        assert!(activation.validate().is_err());
+
+        let dropout = LayerData::Dropout {
+            rate: 1.0,
+            size: 4,
+            rng: None,
+        };
+        assert!(dropout.validate().is_err());
+    }
+}
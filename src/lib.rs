@@ -0,0 +1,41 @@
+//! Feed forward networks and autoencoders trained on MNIST, with the model
+//! file formats, data loading and metrics used by the training service.
+
+pub mod activations;
+pub mod autoencoder;
+pub mod checkpoint;
+pub mod config;
+pub mod convolution;
+pub mod data_set;
+pub mod early_stopping;
+pub mod error;
+pub mod float;
+pub mod idx;
+pub mod layers;
+pub mod logger;
+pub mod losses;
+pub mod matrix;
+pub mod metrics_logger;
+pub mod model;
+pub mod model_file;
+pub mod model_format;
+pub mod network;
+pub mod normalization;
+pub mod optimizers;
+pub mod progress;
+pub mod random;
+pub mod regularization;
+pub mod schedules;
+pub mod session;
+pub mod shutdown;
+pub mod trainer;
+pub mod utils;
+
+pub use autoencoder::Autoencoder;
+pub use config::ExperimentConfig;
+pub use data_set::{mnist_data_set, DataSet};
+pub use error::{Error, Result};
+pub use matrix::Matrix;
+pub use model::Model;
+pub use network::Network;
+pub use trainer::Trainer;
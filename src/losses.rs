@@ -0,0 +1,319 @@
+use serde::{Deserialize, Serialize};
+
+use super::{activations::Activation, float::Float, matrix::Matrix};
+
+const EPSILON: f64 = 1e-12;
+
+/// Smallest output the logarithms see, larger for `f32`, where `1 - EPSILON`
+/// rounds to one.
+fn epsilon<T: Float>() -> T {
+    T::of(EPSILON).max(T::epsilon())
+}
+
+/// Loss between network outputs and targets, one sample per column. The loss
+/// is averaged over the samples of the batch, the gradient is returned per
+/// sample and averaged by the network.
+pub trait Loss<T: Float = f64>: Send {
+    fn loss(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> f64;
+
+    /// Gradient of the loss with respect to the outputs.
+    fn gradient(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> Matrix<T>;
+
+    /// Gradient with respect to the pre-activation values of the output layer
+    /// when the loss and the output `activation` have a simpler and more
+    /// stable combined form.
+    fn fused_gradient(
+        &self,
+        _activation: Activation,
+        _outputs: &Matrix<T>,
+        _targets: &Matrix<T>,
+    ) -> Option<Matrix<T>> {
+        None
+    }
+
+    fn config(&self) -> LossConfig;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LossConfig {
+    MeanSquaredError,
+    CrossEntropy,
+    BinaryCrossEntropy,
+    L1,
+    Huber { delta: f64 },
+}
+
+impl LossConfig {
+    pub fn build<T: Float>(self) -> Box<dyn Loss<T>> {
+        match self {
+            LossConfig::MeanSquaredError => Box::new(MeanSquaredError),
+            LossConfig::CrossEntropy => Box::new(CrossEntropy),
+            LossConfig::BinaryCrossEntropy => Box::new(BinaryCrossEntropy),
+            LossConfig::L1 => Box::new(L1),
+            LossConfig::Huber { delta } => Box::new(Huber { delta }),
+        }
+    }
+}
+
+/// Mean over all elements of `(outputs - targets)^2`.
+pub struct MeanSquaredError;
+
+impl<T: Float> Loss<T> for MeanSquaredError {
+    fn loss(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> f64 {
+        let count = (outputs.rows * outputs.cols) as f64;
+
+        outputs
+            .zip_map(targets, |o, t| (o - t) * (o - t))
+            .sum()
+            .as_f64()
+            / count
+    }
+
+    fn gradient(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> Matrix<T> {
+        let scale = T::of(2.0 / outputs.rows as f64);
+
+        outputs.zip_map(targets, |o, t| scale * (o - t))
+    }
+
+    fn config(&self) -> LossConfig {
+        LossConfig::MeanSquaredError
+    }
+}
+
+/// Categorical cross-entropy on one-hot targets, summed over the classes of a
+/// sample. Fused with a softmax output layer.
+pub struct CrossEntropy;
+
+impl<T: Float> Loss<T> for CrossEntropy {
+    fn loss(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> f64 {
+        let epsilon = epsilon::<T>();
+        let total = outputs
+            .zip_map(targets, |o, t| -t * o.max(epsilon).ln())
+            .sum();
+
+        total.as_f64() / outputs.cols as f64
+    }
+
+    fn gradient(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> Matrix<T> {
+        let epsilon = epsilon::<T>();
+
+        outputs.zip_map(targets, |o, t| -t / o.max(epsilon))
+    }
+
+    fn fused_gradient(
+        &self,
+        activation: Activation,
+        outputs: &Matrix<T>,
+        targets: &Matrix<T>,
+    ) -> Option<Matrix<T>> {
+        match activation {
+            Activation::Softmax => Some(outputs.subtract(targets)),
+            _ => None,
+        }
+    }
+
+    fn config(&self) -> LossConfig {
+        LossConfig::CrossEntropy
+    }
+}
+
+/// Binary cross-entropy averaged over all elements, e.g. pixel intensities of
+/// a reconstruction. Fused with a sigmoid output layer.
+pub struct BinaryCrossEntropy;
+
+impl<T: Float> Loss<T> for BinaryCrossEntropy {
+    fn loss(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> f64 {
+        let count = (outputs.rows * outputs.cols) as f64;
+        let (epsilon, one) = (epsilon::<T>(), T::one());
+
+        let total = outputs
+            .zip_map(targets, |o, t| {
+                let o = o.max(epsilon).min(one - epsilon);
+                -(t * o.ln() + (one - t) * (one - o).ln())
+            })
+            .sum();
+
+        total.as_f64() / count
+    }
+
+    fn gradient(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> Matrix<T> {
+        let scale = T::of(1.0 / outputs.rows as f64);
+        let (epsilon, one) = (epsilon::<T>(), T::one());
+
+        outputs.zip_map(targets, |o, t| {
+            let o = o.max(epsilon).min(one - epsilon);
+            scale * (o - t) / (o * (one - o))
+        })
+    }
+
+    fn fused_gradient(
+        &self,
+        activation: Activation,
+        outputs: &Matrix<T>,
+        targets: &Matrix<T>,
+    ) -> Option<Matrix<T>> {
+        match activation {
+            Activation::Sigmoid => Some(
+                outputs
+                    .subtract(targets)
+                    .scale(T::of(1.0 / outputs.rows as f64)),
+            ),
+            _ => None,
+        }
+    }
+
+    fn config(&self) -> LossConfig {
+        LossConfig::BinaryCrossEntropy
+    }
+}
+
+/// Mean absolute error.
+pub struct L1;
+
+impl<T: Float> Loss<T> for L1 {
+    fn loss(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> f64 {
+        let count = (outputs.rows * outputs.cols) as f64;
+
+        outputs
+            .zip_map(targets, |o, t| (o - t).abs())
+            .sum()
+            .as_f64()
+            / count
+    }
+
+    fn gradient(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> Matrix<T> {
+        let scale = T::of(1.0 / outputs.rows as f64);
+
+        outputs.zip_map(targets, |o, t| {
+            if o == t {
+                T::zero()
+            } else {
+                scale * (o - t).signum()
+            }
+        })
+    }
+
+    fn config(&self) -> LossConfig {
+        LossConfig::L1
+    }
+}
+
+/// Quadratic for errors up to `delta` and linear beyond.
+pub struct Huber {
+    pub delta: f64,
+}
+
+impl<T: Float> Loss<T> for Huber {
+    fn loss(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> f64 {
+        let count = (outputs.rows * outputs.cols) as f64;
+        let (delta, half) = (T::of(self.delta), T::of(0.5));
+
+        let total = outputs
+            .zip_map(targets, |o, t| {
+                let error = (o - t).abs();
+                if error <= delta {
+                    half * error * error
+                } else {
+                    delta * (error - half * delta)
+                }
+            })
+            .sum();
+
+        total.as_f64() / count
+    }
+
+    fn gradient(&self, outputs: &Matrix<T>, targets: &Matrix<T>) -> Matrix<T> {
+        let scale = T::of(1.0 / outputs.rows as f64);
+        let delta = T::of(self.delta);
+
+        outputs.zip_map(targets, |o, t| scale * (o - t).max(-delta).min(delta))
+    }
+
+    fn config(&self) -> LossConfig {
+        LossConfig::Huber { delta: self.delta }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::activations::{SIGMOID, SOFTMAX};
+
+    use super::*;
+
+    fn assert_gradient_matches_finite_differences(
+        loss: &dyn Loss,
+        outputs: &Matrix,
+        targets: &Matrix,
+    ) {
+        let h = 1e-6;
+        let gradient = loss.gradient(outputs, targets);
+
+        for i in 0..outputs.rows {
+            let mut plus = outputs.clone();
+            let mut minus = outputs.clone();
+            plus[(i, 0)] += h;
+            minus[(i, 0)] -= h;
+
+            let numeric = (loss.loss(&plus, targets) - loss.loss(&minus, targets)) / (2.0 * h);
+            assert!((numeric - gradient[(i, 0)]).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_loss_values() {
+        let outputs = Matrix::from(vec![vec![0.5], vec![0.0]]);
+        let targets = Matrix::from(vec![vec![1.0], vec![0.0]]);
+
+        assert_eq!(MeanSquaredError.loss(&outputs, &targets), 0.125);
+        assert_eq!(L1.loss(&outputs, &targets), 0.25);
+        assert_eq!(Huber { delta: 1.0 }.loss(&outputs, &targets), 0.0625);
+        assert!((CrossEntropy.loss(&outputs, &targets) - 2f64.ln()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_gradients_match_finite_differences() {
+        let outputs = Matrix::from(vec![vec![0.3], vec![0.6], vec![0.8]]);
+        let targets = Matrix::from(vec![vec![0.0], vec![1.0], vec![0.5]]);
+
+        let losses: Vec<Box<dyn Loss>> = vec![
+            Box::new(MeanSquaredError),
+            Box::new(CrossEntropy),
+            Box::new(BinaryCrossEntropy),
+            Box::new(L1),
+            Box::new(Huber { delta: 0.25 }),
+        ];
+
+        for loss in losses.iter() {
+            assert_gradient_matches_finite_differences(loss.as_ref(), &outputs, &targets);
+        }
+    }
+
+    #[test]
+    fn test_fused_gradients_match_chain_rule() {
+        let z = Matrix::from(vec![vec![0.5], vec![-1.0], vec![2.0]]);
+        let targets = Matrix::from(vec![vec![0.0], vec![1.0], vec![0.0]]);
+
+        for (loss, activation) in [
+            (Box::new(CrossEntropy) as Box<dyn Loss>, SOFTMAX),
+            (Box::new(BinaryCrossEntropy) as Box<dyn Loss>, SIGMOID),
+        ] {
+            let outputs = activation.apply(&z);
+            let fused = loss.fused_gradient(activation, &outputs, &targets).unwrap();
+            let chained = activation.backward(&z, &outputs, &loss.gradient(&outputs, &targets));
+
+            for (f, c) in fused.to_rows().iter().zip(chained.to_rows().iter()) {
+                assert!((f[0] - c[0]).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let config = Loss::<f64>::config(&Huber { delta: 0.5 });
+        let json = serde_json::to_string(&config).unwrap();
+        let restored: LossConfig = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored, config);
+        assert_eq!(restored.build::<f64>().config(), config);
+    }
+}
@@ -1,120 +1,515 @@
-use std::env;
+use std::path::{Path, PathBuf};
 
-use activations::SIGMOID;
+use autoencoder::{
+    config::{ExperimentConfig, ModelKind},
+    data_set::{flatten_images, load_image, save_image},
+    early_stopping::Metric,
+    error::{self, Error},
+    float::Float,
+    logger::init_logger,
+    metrics_logger::init_metrics,
+    model_file::{self, ModelFile},
+    model_format::Precision,
+    session::Session,
+    shutdown::{handle_signals, Shutdown},
+    trainer::Trainer,
+    utils::try_convert_result_vec_to_number,
+    Model,
+};
 use autometrics::autometrics;
-use network::Network;
-use std::time::Instant;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
-use chrono::Local;
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Train and use MNIST digit classifiers and autoencoders"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Trains a model in rounds, each continuing from the last saved file,
+    /// until the `[session]` bounds are reached
+    Train(TrainArgs),
+    /// Runs a saved model against the MNIST test set
+    Evaluate {
+        model: PathBuf,
+        /// Precision to run the model in, whatever it was saved with
+        #[arg(long, value_enum, default_value = "f64")]
+        precision: PrecisionType,
+        /// Experiment config whose `[data]` directory and `test_set_size` to
+        /// use, the defaults when not given
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Directory of the MNIST IDX files, overriding the config
+        #[arg(long, env = "MNIST_DIR")]
+        data_dir: Option<PathBuf>,
+    },
+    /// Classifies an image file, or reconstructs it with an autoencoder
+    Predict {
+        model: PathBuf,
+        image: PathBuf,
+        /// Where to save the reconstruction of an autoencoder
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Precision to run the model in, whatever it was saved with
+        #[arg(long, value_enum, default_value = "f64")]
+        precision: PrecisionType,
+    },
+    /// Prints a summary of a saved model
+    Inspect { model: PathBuf },
+    /// Converts a model to the format of the output extension, e.g.
+    /// `model.bin.zst`. For a directory, converts every model in it to the
+    /// given extension, e.g. `bin.zst`
+    Export { input: PathBuf, output: String },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ModelType {
+    Classifier,
+    Autoencoder,
+}
 
-use crate::data_set::mnist_data_set;
-use crate::data_set::DataSet;
-use crate::logger::init_logger;
-use metrics_logger::*;
+#[derive(Clone, Copy, ValueEnum)]
+enum PrecisionType {
+    F32,
+    F64,
+}
 
-pub mod activations;
-pub mod data_set;
-pub mod logger;
-pub mod matrix;
-pub mod metrics_logger;
-pub mod network;
-pub mod utils;
+impl From<PrecisionType> for Precision {
+    fn from(precision: PrecisionType) -> Precision {
+        match precision {
+            PrecisionType::F32 => Precision::F32,
+            PrecisionType::F64 => Precision::F64,
+        }
+    }
+}
+
+/// Options of a training run. Any given flag overrides the config file.
+#[derive(Args, Clone)]
+struct TrainArgs {
+    /// Experiment config (`.toml` or `.json`), see `configs/`
+    #[arg(long)]
+    config: Option<PathBuf>,
+    #[arg(long, value_enum, env = "MODEL_TYPE")]
+    model: Option<ModelType>,
+    /// Layer sizes, e.g. `784,800,800,10` for the classifier. For the
+    /// autoencoder the encoder sizes down to the bottleneck, e.g. `784,256,32`,
+    /// which the decoder mirrors
+    #[arg(long, value_delimiter = ',')]
+    layers: Option<Vec<usize>>,
+    /// Defaults to 10
+    #[arg(long)]
+    epochs: Option<usize>,
+    /// Defaults to 0.1 with SGD for the classifier and 0.001 with Adam for
+    /// the autoencoder
+    #[arg(long)]
+    learning_rate: Option<f64>,
+    /// Defaults to 16
+    #[arg(long)]
+    batch_size: Option<usize>,
+    /// Seeds the weight initialization and the shuffling of the training
+    /// data, so runs with the same seed save identical models
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Element type of the network, defaults to f64. A preloaded model is
+    /// converted to it
+    #[arg(long, value_enum)]
+    precision: Option<PrecisionType>,
+    /// Directory of the MNIST IDX files, e.g. `train-images-idx3-ubyte`,
+    /// optionally gzipped. Defaults to `./data`
+    #[arg(long, env = "MNIST_DIR")]
+    data_dir: Option<PathBuf>,
+    /// Defaults to `./data/networks`
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+    /// Extension, and so format, of the saved models, e.g. `bin.zst` (the
+    /// default) or `json`
+    #[arg(long, env = "MODEL_FORMAT")]
+    format: Option<String>,
+    /// Model file to continue training from
+    #[arg(long, env = "PRELOAD_NETWORK")]
+    preload: Option<PathBuf>,
+    /// Continues the first round from the latest checkpoint of the model in
+    /// `<output dir>/checkpoints`, mid-epoch if it was written mid-epoch, e.g.
+    /// on Ctrl-C
+    #[arg(long)]
+    resume: bool,
+    /// Rounds of training, each continuing from the model saved by the last.
+    /// Unbounded by default
+    #[arg(long)]
+    rounds: Option<usize>,
+}
+
+impl TrainArgs {
+    /// The config file, or the defaults, with the given flags applied,
+    /// resolved and validated.
+    fn config(&self) -> error::Result<ExperimentConfig> {
+        let mut config = match &self.config {
+            Some(path) => ExperimentConfig::load(path)?,
+            None => ExperimentConfig::default(),
+        };
+
+        if let Some(model) = self.model {
+            let model = match model {
+                ModelType::Classifier => ModelKind::Classifier,
+                ModelType::Autoencoder => ModelKind::Autoencoder,
+            };
+            config = config.with_model(model);
+        }
+        if let Some(layers) = &self.layers {
+            config.architecture.layers = Some(layers.clone());
+            if self.config.is_none() {
+                config.architecture.activations = None;
+                config.architecture.decoder_activations = None;
+                config.architecture.dropout = None;
+                config.regularization.layers = None;
+            }
+        }
+        if let Some(epochs) = self.epochs {
+            config.training.epochs = epochs;
+        }
+        if let Some(batch_size) = self.batch_size {
+            config.training.batch_size = batch_size;
+        }
+        if let Some(seed) = self.seed {
+            config.seed = Some(seed);
+        }
+        if let Some(rounds) = self.rounds {
+            config.session.rounds = Some(rounds);
+        }
+        if let Some(precision) = self.precision {
+            config.precision = precision.into();
+        }
+        if let Some(data_dir) = &self.data_dir {
+            config.data.directory = data_dir.clone();
+        }
+        if let Some(output_dir) = &self.output_dir {
+            config.output.directory = output_dir.clone();
+        }
+        if let Some(format) = &self.format {
+            config.output.format = format.clone();
+        }
+
+        let mut config = config.resolve();
+        if let (Some(learning_rate), Some(optimizer)) = (self.learning_rate, &mut config.optimizer)
+        {
+            optimizer.set_learning_rate(learning_rate);
+        }
+        config.validate()?;
+
+        Ok(config)
+    }
+}
 
 #[tokio::main]
 pub async fn main() {
     init_logger();
-    tokio::spawn(init_metrics());
 
-    let mut preload_network = env::var("PRELOAD_NETWORK").unwrap_or(String::from(""));
+    let result = match Cli::parse().command {
+        Command::Train(args) => train(args).await,
+        Command::Evaluate {
+            model,
+            precision,
+            config,
+            data_dir,
+        } => evaluate(
+            &model,
+            precision.into(),
+            config.as_deref(),
+            data_dir.as_deref(),
+        ),
+        Command::Predict {
+            model,
+            image,
+            output,
+            precision,
+        } => predict(&model, &image, output.as_deref(), precision.into()),
+        Command::Inspect { model } => inspect(&model),
+        Command::Export { input, output } => export(&input, &output),
+    };
 
-    loop {
-        let network_process = tokio::spawn(init_network(preload_network));
+    if let Err(error) = result {
+        log::error!("{}", error);
+        std::process::exit(1);
+    }
+}
+
+async fn train(args: TrainArgs) -> error::Result<()> {
+    let config = args.config()?;
+
+    let shutdown = Shutdown::default();
+    tokio::spawn(handle_signals(shutdown.clone()));
+    let metrics = tokio::spawn(init_metrics(shutdown.clone()));
+
+    let mut trainer = config.trainer();
+    trainer.resume = args.resume;
+    trainer.shutdown = shutdown.clone();
+    let mut preload = args.preload.clone();
+    let metric = match config.model {
+        ModelKind::Classifier => Metric::Accuracy,
+        ModelKind::Autoencoder => Metric::ReconstructionError,
+    };
+    let mut session = Session::new(config.session.clone(), metric);
 
-        let result = network_process.await;
+    while !shutdown.is_requested() {
+        let network_process = match config.model {
+            ModelKind::Classifier => tokio::spawn(init_network(
+                config.clone(),
+                trainer.clone(),
+                preload.clone(),
+            )),
+            ModelKind::Autoencoder => tokio::spawn(init_autoencoder(
+                config.clone(),
+                trainer.clone(),
+                preload.clone(),
+            )),
+        };
+        // Later rounds continue from the model saved by the previous one
+        trainer.resume = false;
+
+        // A failed round is logged and counted, the next one retries unless it
+        // would fail the same way
+        let more = match network_process.await {
+            Ok(Ok(file_path)) => {
+                preload = Some(file_path.clone());
+                session.finish_round(file_path).unwrap_or_else(|error| {
+                    log::error!("Unable to finish the round: {}", error);
+                    session.fail_round()
+                })
+            }
+            Ok(Err(error @ Error::Interrupted { .. })) => {
+                log::info!("{}", error);
+                false
+            }
+            Ok(Err(error)) if error.repeats() => {
+                log::error!("Training failed: {}", error);
+                session.fail_round();
+                false
+            }
+            Ok(Err(error)) => {
+                log::error!("Training failed: {}", error);
+                session.fail_round()
+            }
+            Err(error) => {
+                log::error!("Training panicked: {}", error);
+                session.fail_round()
+            }
+        };
+        if !more {
+            break;
+        }
+    }
 
-        let file_path = result.ok();
+    if let Err(error) = session.prune(false) {
+        log::error!("Unable to prune the saved models: {}", error);
+    }
+    // Ends the metrics server when the session ended by itself
+    shutdown.request();
 
-        preload_network = file_path.expect("Should return a file path")
+    match metrics.await {
+        Ok(Err(error)) => log::error!("Metrics server failed: {}", error),
+        Err(error) => log::error!("Metrics server panicked: {}", error),
+        Ok(Ok(())) => {}
     }
+    log::info!("Shut down");
+    log::logger().flush();
+
+    Ok(())
 }
 
 #[autometrics]
-async fn init_network(preload_network: String) -> String {
-    let training_set_size: u32 = 50_000;
-    let val_set_size: u32 = 10_000;
-    let test_set_size: u32 = 10_000;
+async fn init_network(
+    config: ExperimentConfig,
+    trainer: Trainer,
+    preload: Option<PathBuf>,
+) -> error::Result<PathBuf> {
+    match config.precision {
+        Precision::F32 => train_network::<f32>(&config, &trainer, preload),
+        Precision::F64 => train_network::<f64>(&config, &trainer, preload),
+    }
+}
+
+fn train_network<T: Float>(
+    config: &ExperimentConfig,
+    trainer: &Trainer,
+    preload: Option<PathBuf>,
+) -> error::Result<PathBuf> {
+    let data_set = config.data_set().map_err(|error| Error::DataSet {
+        source: Box::new(error),
+    })?;
 
-    let image_size: usize = 784;
-    let layers: Vec<usize> = vec![image_size, 800, 800, 10];
+    log::info!("Create Network... {:?}", config.architecture.layers);
 
-    fn scale_by_learning_rate(x: f64) -> f64 {
-        x * 0.001
+    let mut network = config.build_network::<T>();
+
+    if let Some(preload) = preload {
+        log::info!("Preload Network: {}...", preload.display());
+
+        network.load(&preload).map_err(|error| Error::Preload {
+            path: preload,
+            source: Box::new(error),
+        })?;
     }
 
-    let epochs = 10;
+    trainer.train_network(&mut network, &data_set)
+}
 
-    let DataSet {
-        train_inputs,
-        train_targets,
-        test_data,
-        test_labels,
-        val_data,
-        val_labels,
-    } = mnist_data_set(training_set_size, val_set_size, test_set_size);
+#[autometrics]
+async fn init_autoencoder(
+    config: ExperimentConfig,
+    trainer: Trainer,
+    preload: Option<PathBuf>,
+) -> error::Result<PathBuf> {
+    match config.precision {
+        Precision::F32 => train_autoencoder::<f32>(&config, &trainer, preload),
+        Precision::F64 => train_autoencoder::<f64>(&config, &trainer, preload),
+    }
+}
+
+fn train_autoencoder<T: Float>(
+    config: &ExperimentConfig,
+    trainer: &Trainer,
+    preload: Option<PathBuf>,
+) -> error::Result<PathBuf> {
+    let data_set = config.data_set().map_err(|error| Error::DataSet {
+        source: Box::new(error),
+    })?;
 
-    log::info!("Create Network... {:?}", layers);
+    log::info!("Create Autoencoder... {:?}", config.architecture.layers);
 
-    let mut network = Network::new(layers, scale_by_learning_rate, SIGMOID);
+    let mut autoencoder = config.build_autoencoder::<T>();
 
-    if preload_network.len() != 0 {
-        log::info!("Preload Network: {}...", preload_network);
+    if let Some(preload) = preload {
+        log::info!("Preload Autoencoder: {}...", preload.display());
 
-        network.load(preload_network);
+        autoencoder.load(&preload).map_err(|error| Error::Preload {
+            path: preload,
+            source: Box::new(error),
+        })?;
     }
 
-    log::info!("Start training with {} images", train_inputs.len());
+    trainer.train_autoencoder(&mut autoencoder, &data_set)
+}
 
-    for i in 1..=epochs {
-        let now = Instant::now();
-        log::info!("[Training] Epoch {} of {}", i, epochs);
+fn evaluate(
+    model: &Path,
+    precision: Precision,
+    config: Option<&Path>,
+    data_dir: Option<&Path>,
+) -> error::Result<()> {
+    let mut config = match config {
+        Some(path) => ExperimentConfig::load(path)?,
+        None => ExperimentConfig::default(),
+    };
+    if let Some(data_dir) = data_dir {
+        config.data.directory = data_dir.to_path_buf();
+    }
 
-        let success = network.run_training_epoch(
-            &train_inputs,
-            &train_targets,
-            &test_data,
-            &test_labels,
-            &val_data,
-            &val_labels,
-            image_size,
-            val_set_size,
-            test_set_size,
-        );
+    match precision {
+        Precision::F32 => evaluate_model(Model::<f32>::from_file(model)?, &config),
+        Precision::F64 => evaluate_model(Model::<f64>::from_file(model)?, &config),
+    }
+}
 
-        if success {
-            log::info!("Right percentage of 100% reached, will stop training");
-            break;
+fn evaluate_model<T: Float>(model: Model<T>, config: &ExperimentConfig) -> error::Result<()> {
+    let (test_data, test_labels) = config.test_set()?;
+
+    match model {
+        Model::Network(mut network) => {
+            let (count, height, width) = test_data.dim();
+            let accuracy =
+                network.validate(&test_data, &test_labels, count as u32, height * width)?;
+            println!("Test accuracy: {}%", accuracy);
+        }
+        Model::Autoencoder(autoencoder) => {
+            let error = autoencoder.validate(&flatten_images(&test_data))?;
+            println!("Test reconstruction error: {}", error);
         }
+    }
+
+    Ok(())
+}
 
-        let elapsed = now.elapsed();
-        log::info!("Epoch took: {:.2?}", elapsed);
+/// Side of the square images a model with `input_size` inputs takes.
+fn image_side(input_size: usize) -> error::Result<u32> {
+    let side = (input_size as f64).sqrt().round() as usize;
+
+    if side * side != input_size {
+        return Err(Error::InvalidLength {
+            name: "square image",
+            expected: side * side,
+            found: input_size,
+        });
     }
 
-    log::info!("Running final test...");
+    Ok(side as u32)
+}
+
+fn predict(
+    model: &Path,
+    image: &Path,
+    output: Option<&Path>,
+    precision: Precision,
+) -> error::Result<()> {
+    match precision {
+        Precision::F32 => predict_model(Model::<f32>::from_file(model)?, image, output),
+        Precision::F64 => predict_model(Model::<f64>::from_file(model)?, image, output),
+    }
+}
+
+fn predict_model<T: Float>(
+    model: Model<T>,
+    image: &Path,
+    output: Option<&Path>,
+) -> error::Result<()> {
+    let side = image_side(model.input_size())?;
+    let pixels = load_image(image, side, side)?;
 
-    let right_percentage = network.validate(&test_data, &test_labels, test_set_size, image_size);
+    match model {
+        Model::Network(network) => {
+            let outputs = network.try_predict(&pixels)?;
+            let digit = try_convert_result_vec_to_number(outputs.clone())?;
 
-    let file_path = format!(
-        "./data/networks/{}-{}-{}.json",
-        network.model(),
-        Local::now().format("%Y-%m-%dT%H:%M:%S"),
-        right_percentage
-    );
+            println!("Prediction: {}", digit);
+            for (class, probability) in outputs.iter().enumerate() {
+                println!("  {}: {:.4}", class, probability);
+            }
+        }
+        Model::Autoencoder(autoencoder) => {
+            let reconstruction = autoencoder.reconstruct(&pixels)?;
+            let error = autoencoder::autoencoder::reconstruction_error(&pixels, &reconstruction);
 
-    log::info!("Saving model at path {}", file_path);
+            println!("Reconstruction error: {}", error);
+            if let Some(output) = output {
+                save_image(output, &reconstruction, side, side)?;
+                println!("Saved reconstruction to {}", output.display());
+            }
+        }
+    }
+
+    Ok(())
+}
 
-    let file_path_copy = file_path.clone();
+fn inspect(model: &Path) -> error::Result<()> {
+    println!("{}", ModelFile::read(model)?.summary());
 
-    network.save(file_path);
+    Ok(())
+}
+
+fn export(input: &Path, output: &str) -> error::Result<()> {
+    if input.is_dir() {
+        let converted = model_file::convert_directory(input, output)?;
+        for file in converted.iter() {
+            println!("Converted {}", file.display());
+        }
+    } else {
+        model_file::convert(input, output)?;
+        println!("Converted {} to {}", input.display(), output);
+    }
 
-    file_path_copy
+    Ok(())
 }
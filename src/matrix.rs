@@ -1,194 +1,516 @@
+use std::{
+    borrow::Cow,
+    ops::{Index, IndexMut},
+};
+
 use autometrics::autometrics;
-use rand::distributions::{Distribution, Uniform};
+use rand::{
+    distributions::{Distribution, Uniform},
+    Rng, SeedableRng,
+};
 /**
  * This code is inspired by the https://www.youtube.com/watch?v=FI-8L-hobDY&t=10s video by MathleteDev
  *
  * https://github.com/mathletedev/rust-ml/
  **/
+use rand_chacha::ChaCha8Rng;
 use rayon::prelude::*;
-
-#[derive(Clone)]
-pub struct Matrix {
+use serde::{Deserialize, Serialize};
+
+use super::{
+    error::{Error, Result},
+    float::Float,
+};
+
+/// Rows and columns of the blocks `multiply` works on, small enough for a
+/// block of each operand to stay in the cache.
+const TILE: usize = 64;
+
+/// A matrix stored in a single buffer. `strides` give the distance in the
+/// buffer between consecutive rows and consecutive columns, so `transpose`
+/// only swaps them. Operations return row-major matrices.
+/// The element type defaults to `f64`, `Matrix<f32>` halves the memory.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "MatrixData<T>", into = "MatrixData<T>", bound = "T: Float")]
+pub struct Matrix<T: Float = f64> {
     pub rows: usize,
     pub cols: usize,
-    pub data: Vec<Vec<f64>>,
+    data: Vec<T>,
+    strides: (usize, usize),
+}
+
+/// Saved form of a matrix, nested rows as in the first model files.
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "T: Float")]
+struct MatrixData<T: Float> {
+    rows: usize,
+    cols: usize,
+    data: Vec<Vec<T>>,
+}
+
+impl<T: Float> TryFrom<MatrixData<T>> for Matrix<T> {
+    type Error = String;
+
+    fn try_from(matrix: MatrixData<T>) -> std::result::Result<Matrix<T>, String> {
+        let consistent = matrix.data.len() == matrix.rows
+            && matrix.data.iter().all(|row| row.len() == matrix.cols);
+
+        if !consistent {
+            return Err(format!(
+                "matrix data does not match its {}x{} shape",
+                matrix.rows, matrix.cols
+            ));
+        }
+
+        Ok(Matrix::from_vec(
+            matrix.rows,
+            matrix.cols,
+            matrix.data.concat(),
+        ))
+    }
+}
+
+impl<T: Float> From<Matrix<T>> for MatrixData<T> {
+    fn from(matrix: Matrix<T>) -> MatrixData<T> {
+        MatrixData {
+            rows: matrix.rows,
+            cols: matrix.cols,
+            data: matrix.to_rows(),
+        }
+    }
+}
+
+impl<T: Float> Index<(usize, usize)> for Matrix<T> {
+    type Output = T;
+
+    fn index(&self, (row, col): (usize, usize)) -> &T {
+        &self.data[self.offset(row, col)]
+    }
+}
+
+impl<T: Float> IndexMut<(usize, usize)> for Matrix<T> {
+    fn index_mut(&mut self, (row, col): (usize, usize)) -> &mut T {
+        let offset = self.offset(row, col);
+        &mut self.data[offset]
+    }
+}
+
+// Not instrumented, it runs for every indexed value
+impl<T: Float> Matrix<T> {
+    fn offset(&self, row: usize, col: usize) -> usize {
+        if row >= self.rows || col >= self.cols {
+            panic!("Attempted to index matrix out of bounds");
+        }
+
+        row * self.strides.0 + col * self.strides.1
+    }
 }
 
 #[autometrics]
-impl Matrix {
-    pub fn zeros(rows: usize, cols: usize) -> Matrix {
+impl<T: Float> Matrix<T> {
+    pub fn zeros(rows: usize, cols: usize) -> Matrix<T> {
+        Matrix::from_vec(rows, cols, vec![T::zero(); rows * cols])
+    }
+
+    /// Uniform values in `[-1, 1)`. Each row is drawn from its own stream of
+    /// a seed taken from `rng`, so the result does not depend on how rayon
+    /// schedules the rows.
+    pub fn random(rows: usize, cols: usize, rng: &mut impl Rng) -> Matrix<T> {
+        let seed: u64 = rng.gen();
+        let mut matrix = Matrix::zeros(rows, cols);
+
+        matrix
+            .data
+            .par_chunks_mut(cols.max(1))
+            .enumerate()
+            .for_each(|(row, values)| {
+                let between = Uniform::new(T::of(-1.0), T::one());
+                let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                rng.set_stream(row as u64);
+                values
+                    .iter_mut()
+                    .for_each(|value| *value = between.sample(&mut rng));
+            });
+
+        matrix
+    }
+
+    pub fn from(data: Vec<Vec<T>>) -> Matrix<T> {
+        Matrix::from_vec(data.len(), data[0].len(), data.concat())
+    }
+
+    /// Builds a matrix from its values in row-major order.
+    pub fn from_vec(rows: usize, cols: usize, data: Vec<T>) -> Matrix<T> {
+        if data.len() != rows * cols {
+            panic!("Attempted to build a matrix from data of incorrect length");
+        }
+
         Matrix {
             rows,
             cols,
-            data: vec![vec![0.0; cols]; rows],
+            data,
+            strides: (cols, 1),
         }
     }
 
-    pub fn random(rows: usize, cols: usize) -> Matrix {
-        let between = Uniform::new(-1.0, 1.0);
-        let res_data: Vec<Vec<f64>> = (0..rows)
-            .into_par_iter()
-            .map(|_| {
-                let mut rng = rand::thread_rng();
-                (0..cols)
-                    .into_iter()
-                    .map(|_| between.sample(&mut rng))
-                    .collect()
-            })
+    /// Builds a matrix whose columns are the given vectors, e.g. one column per
+    /// sample of a mini-batch.
+    pub fn from_columns(columns: &[&Vec<T>]) -> Matrix<T> {
+        let rows = columns[0].len();
+        let data = (0..rows)
+            .flat_map(|i| columns.iter().map(move |column| column[i]))
             .collect();
 
-        Matrix {
-            rows,
-            cols,
-            data: res_data,
-        }
+        Matrix::from_vec(rows, columns.len(), data)
     }
 
-    pub fn from(data: Vec<Vec<f64>>) -> Matrix {
-        Matrix {
-            rows: data.len(),
-            cols: data[0].len(),
-            data,
-        }
+    /// The `(rows, cols)` of the matrix.
+    pub fn shape(&self) -> (usize, usize) {
+        (self.rows, self.cols)
     }
 
-    pub fn multiply(&self, other: &Matrix) -> Matrix {
-        if self.cols != other.rows {
-            panic!("Attempted to multiply by matrix of incorrect dimensions");
+    /// Distance in the buffer between consecutive rows and columns.
+    pub fn strides(&self) -> (usize, usize) {
+        self.strides
+    }
+
+    /// Whether the buffer holds the values in row-major order.
+    pub fn is_contiguous(&self) -> bool {
+        (self.rows <= 1 || self.strides.0 == self.cols) && (self.cols <= 1 || self.strides.1 == 1)
+    }
+
+    /// The matrix in row-major order, copied only when it is not already.
+    fn contiguous(&self) -> Cow<'_, Matrix<T>> {
+        if self.is_contiguous() {
+            return Cow::Borrowed(self);
         }
 
-        let res_data: Vec<Vec<f64>> = (0..self.rows)
-            .into_par_iter()
-            .map(|i| {
-                (0..other.cols)
-                    .into_iter()
-                    .map(|j| {
-                        let mut sum = 0.0;
-                        for k in 0..self.cols {
-                            sum += self.data[i][k] * other.data[k][j];
-                        }
-                        sum
-                    })
-                    .collect()
-            })
-            .collect();
+        let mut data = vec![T::zero(); self.rows * self.cols];
+        // Copied in tiles, so neither side is walked against its layout
+        for i0 in (0..self.rows).step_by(TILE) {
+            for j0 in (0..self.cols).step_by(TILE) {
+                for i in i0..(i0 + TILE).min(self.rows) {
+                    for j in j0..(j0 + TILE).min(self.cols) {
+                        data[i * self.cols + j] =
+                            self.data[i * self.strides.0 + j * self.strides.1];
+                    }
+                }
+            }
+        }
+
+        Cow::Owned(Matrix::from_vec(self.rows, self.cols, data))
+    }
 
+    /// The matrix with its values converted to another precision.
+    pub fn cast<U: Float>(&self) -> Matrix<U> {
         Matrix {
             rows: self.rows,
-            cols: other.cols,
-            data: res_data,
+            cols: self.cols,
+            data: self.data.iter().map(|&x| U::of(x.as_f64())).collect(),
+            strides: self.strides,
         }
     }
 
-    pub fn add(&self, other: &Matrix) -> Matrix {
-        if self.rows != other.rows || self.cols != other.cols {
-            panic!("Attempted to subtract matrix of incorrect dimensions");
-        }
+    /// The values in row-major order.
+    pub fn to_vec(&self) -> Vec<T> {
+        self.contiguous().into_owned().data
+    }
 
-        let res_data: Vec<Vec<f64>> = self
+    pub fn to_rows(&self) -> Vec<Vec<T>> {
+        let matrix = self.contiguous();
+        matrix
             .data
-            .par_iter()
-            .zip(other.data.par_iter())
-            .map(|(row_self, row_other)| {
-                row_self
-                    .iter()
-                    .zip(row_other.iter())
-                    .map(|(&x, &y)| x + y)
-                    .collect()
-            })
-            .collect();
+            .chunks(self.cols.max(1))
+            .take(self.rows)
+            .map(|row| row.to_vec())
+            .collect()
+    }
 
-        Matrix {
-            rows: self.rows,
-            cols: self.cols,
-            data: res_data,
+    pub fn columns(&self) -> Vec<Vec<T>> {
+        self.transpose().to_rows()
+    }
+
+    fn shape_mismatch(&self, operation: &'static str, other: &Matrix<T>) -> Error {
+        Error::ShapeMismatch {
+            operation,
+            left: self.shape(),
+            right: other.shape(),
         }
     }
 
-    pub fn dot_multiply(&self, other: &Matrix) -> Matrix {
-        if self.rows != other.rows || self.cols != other.cols {
-            panic!("Attempted to dot multiply by matrix of incorrect dimensions");
+    pub fn multiply(&self, other: &Matrix<T>) -> Matrix<T> {
+        self.try_multiply(other)
+            .expect("Attempted to multiply by matrix of incorrect dimensions")
+    }
+
+    /// Blocked product, parallel over tiles of rows. Every value still sums
+    /// its products in order of `k`, so the result does not depend on the
+    /// tiling or the threads.
+    pub fn try_multiply(&self, other: &Matrix<T>) -> Result<Matrix<T>> {
+        if self.cols != other.rows {
+            return Err(self.shape_mismatch("multiply", other));
         }
 
-        let res_data: Vec<Vec<f64>> = self
-            .data
-            .par_iter()
-            .zip(other.data.par_iter())
-            .map(|(row_self, row_other)| {
-                row_self
-                    .iter()
-                    .zip(row_other.iter())
-                    .map(|(&x, &y)| x * y)
-                    .collect()
-            })
-            .collect();
+        let (rows, inner, cols) = (self.rows, self.cols, other.cols);
+        let mut result = Matrix::zeros(rows, cols);
+        if rows == 0 || cols == 0 {
+            return Ok(result);
+        }
 
-        Matrix {
-            rows: self.rows,
-            cols: self.cols,
-            data: res_data,
+        // With `other` stored by columns, as after a transpose, every value is
+        // the dot product of two contiguous rows
+        if other.strides.0 == 1 && other.strides.1 == inner {
+            let left = self.contiguous();
+            result
+                .data
+                .par_chunks_mut(TILE * cols)
+                .enumerate()
+                .for_each(|(tile, values)| {
+                    for j0 in (0..cols).step_by(TILE) {
+                        for (i, row) in values.chunks_mut(cols).enumerate() {
+                            let left_row = &left.data[(tile * TILE + i) * inner..][..inner];
+                            for (j, value) in row.iter_mut().enumerate().skip(j0).take(TILE) {
+                                let right_column = &other.data[j * inner..][..inner];
+                                *value = left_row
+                                    .iter()
+                                    .zip(right_column)
+                                    .fold(T::zero(), |sum, (&x, &y)| sum + x * y);
+                            }
+                        }
+                    }
+                });
+
+            return Ok(result);
         }
+
+        // `self` is read through its strides, within a tile even a transposed
+        // one stays in the cache
+        let right = other.contiguous();
+        let (row_stride, col_stride) = self.strides;
+        result
+            .data
+            .par_chunks_mut(TILE * cols)
+            .enumerate()
+            .for_each(|(tile, values)| {
+                for k0 in (0..inner).step_by(TILE) {
+                    for j0 in (0..cols).step_by(TILE) {
+                        let j1 = (j0 + TILE).min(cols);
+                        for (i, row) in values.chunks_mut(cols).enumerate() {
+                            let left_row = (tile * TILE + i) * row_stride;
+                            for k in k0..(k0 + TILE).min(inner) {
+                                let x = self.data[left_row + k * col_stride];
+                                let right_row = &right.data[k * cols..][j0..j1];
+                                for (value, &y) in row[j0..j1].iter_mut().zip(right_row) {
+                                    *value += x * y;
+                                }
+                            }
+                        }
+                    }
+                }
+            });
+
+        Ok(result)
     }
 
-    pub fn subtract(&self, other: &Matrix) -> Matrix {
-        if self.rows != other.rows || self.cols != other.cols {
-            panic!("Attempted to subtract matrix of incorrect dimensions");
+    /// Combines two matrices of equal dimensions element by element, keeping
+    /// the layout when both share it.
+    fn try_zip(
+        &self,
+        operation: &'static str,
+        other: &Matrix<T>,
+        function: impl Fn(T, T) -> T + Sync,
+    ) -> Result<Matrix<T>> {
+        if self.shape() != other.shape() {
+            return Err(self.shape_mismatch(operation, other));
         }
 
-        let res_data: Vec<Vec<f64>> = self
+        let (left, right) = if self.strides == other.strides {
+            (Cow::Borrowed(self), Cow::Borrowed(other))
+        } else {
+            (self.contiguous(), other.contiguous())
+        };
+
+        let data = left
             .data
             .par_iter()
-            .zip(other.data.par_iter())
-            .map(|(row_self, row_other)| {
-                row_self
-                    .iter()
-                    .zip(row_other.iter())
-                    .map(|(&x, &y)| x - y)
-                    .collect()
-            })
+            .zip(right.data.par_iter())
+            .map(|(&x, &y)| function(x, y))
             .collect();
 
-        Matrix {
+        Ok(Matrix {
             rows: self.rows,
             cols: self.cols,
-            data: res_data,
+            data,
+            strides: left.strides,
+        })
+    }
+
+    /// Combines `other` into the matrix element by element, without
+    /// allocating when both share a layout.
+    fn try_zip_assign(
+        &mut self,
+        operation: &'static str,
+        other: &Matrix<T>,
+        function: impl Fn(&mut T, T) + Sync,
+    ) -> Result<()> {
+        if self.shape() != other.shape() {
+            return Err(self.shape_mismatch(operation, other));
+        }
+
+        if self.strides != other.strides {
+            *self = self.contiguous().into_owned();
         }
+        let right = if self.strides == other.strides {
+            Cow::Borrowed(other)
+        } else {
+            other.contiguous()
+        };
+
+        self.data
+            .par_iter_mut()
+            .zip(right.data.par_iter())
+            .for_each(|(x, &y)| function(x, y));
+
+        Ok(())
     }
 
-    pub fn map(&self, function: fn(f64) -> f64) -> Matrix {
-        let res_data: Vec<Vec<f64>> = self
-            .data
-            .par_iter() // Parallel iterator over rows
-            .map(|row| {
-                row.iter() // Iterator over each element in the row
-                    .map(|&x| function(x)) // Apply the activation function
-                    .collect::<Vec<f64>>()
-            })
-            .collect();
+    pub fn add(&self, other: &Matrix<T>) -> Matrix<T> {
+        self.try_add(other)
+            .expect("Attempted to add matrix of incorrect dimensions")
+    }
 
-        Matrix {
-            rows: self.rows,
-            cols: self.cols,
-            data: res_data,
+    pub fn try_add(&self, other: &Matrix<T>) -> Result<Matrix<T>> {
+        self.try_zip("add", other, |x, y| x + y)
+    }
+
+    pub fn add_assign(&mut self, other: &Matrix<T>) {
+        self.try_add_assign(other)
+            .expect("Attempted to add matrix of incorrect dimensions")
+    }
+
+    pub fn try_add_assign(&mut self, other: &Matrix<T>) -> Result<()> {
+        self.try_zip_assign("add", other, |x, y| *x += y)
+    }
+
+    pub fn dot_multiply(&self, other: &Matrix<T>) -> Matrix<T> {
+        self.try_dot_multiply(other)
+            .expect("Attempted to dot multiply by matrix of incorrect dimensions")
+    }
+
+    pub fn try_dot_multiply(&self, other: &Matrix<T>) -> Result<Matrix<T>> {
+        self.try_zip("dot multiply", other, |x, y| x * y)
+    }
+
+    pub fn subtract(&self, other: &Matrix<T>) -> Matrix<T> {
+        self.try_subtract(other)
+            .expect("Attempted to subtract matrix of incorrect dimensions")
+    }
+
+    pub fn try_subtract(&self, other: &Matrix<T>) -> Result<Matrix<T>> {
+        self.try_zip("subtract", other, |x, y| x - y)
+    }
+
+    pub fn subtract_assign(&mut self, other: &Matrix<T>) {
+        self.try_subtract_assign(other)
+            .expect("Attempted to subtract matrix of incorrect dimensions")
+    }
+
+    pub fn try_subtract_assign(&mut self, other: &Matrix<T>) -> Result<()> {
+        self.try_zip_assign("subtract", other, |x, y| *x -= y)
+    }
+
+    pub fn map(&self, function: fn(T) -> T) -> Matrix<T> {
+        let mut result = self.clone();
+        result.map_inplace(function);
+        result
+    }
+
+    pub fn map_inplace(&mut self, function: impl Fn(T) -> T + Sync) {
+        self.data
+            .par_iter_mut()
+            .for_each(|value| *value = function(*value));
+    }
+
+    pub fn scale(&self, factor: T) -> Matrix<T> {
+        let mut result = self.clone();
+        result.scale_assign(factor);
+        result
+    }
+
+    pub fn scale_assign(&mut self, factor: T) {
+        self.map_inplace(|x| x * factor);
+    }
+
+    /// Adds the column vector `column` to every column of the matrix.
+    pub fn add_column(&self, column: &Matrix<T>) -> Matrix<T> {
+        if self.rows != column.rows || column.cols != 1 {
+            panic!("Attempted to add column of incorrect dimensions");
         }
+
+        let mut result = self.contiguous().into_owned();
+        result
+            .data
+            .par_chunks_mut(self.cols.max(1))
+            .enumerate()
+            .for_each(|(i, row)| {
+                let value = column[(i, 0)];
+                row.iter_mut().for_each(|x| *x += value);
+            });
+
+        result
     }
 
-    pub fn transpose(&self) -> Matrix {
-        let res_data: Vec<Vec<f64>> = (0..self.cols)
-            .into_par_iter()
-            .map(|j| {
-                (0..self.rows)
-                    .into_iter()
-                    .map(|i| self.data[i][j])
-                    .collect()
-            })
+    /// Sums every row into a single column.
+    pub fn sum_columns(&self) -> Matrix<T> {
+        let data = self
+            .contiguous()
+            .data
+            .par_chunks(self.cols.max(1))
+            .map(|row| row.iter().copied().sum())
             .collect();
 
-        Matrix {
+        Matrix::from_vec(self.rows, 1, data)
+    }
+
+    pub fn sum(&self) -> T {
+        // Rows are added up in order, a parallel reduction would round
+        // differently from run to run
+        let row_sums = self.sum_columns();
+        row_sums.data.iter().copied().sum()
+    }
+
+    /// Combines two matrices of equal dimensions element by element.
+    pub fn zip_map(&self, other: &Matrix<T>, function: impl Fn(T, T) -> T + Sync) -> Matrix<T> {
+        self.try_zip("zip", other, function)
+            .expect("Attempted to zip matrix of incorrect dimensions")
+    }
+
+    /// Like `zip_map`, replacing the values of the matrix.
+    pub fn zip_map_inplace(&mut self, other: &Matrix<T>, function: impl Fn(T, T) -> T + Sync) {
+        self.try_zip_assign("zip", other, |x, y| *x = function(*x, y))
+            .expect("Attempted to zip matrix of incorrect dimensions")
+    }
+
+    /// Swaps rows and columns by swapping the strides, the values are not
+    /// moved.
+    pub fn transpose(&self) -> Matrix<T> {
+        self.clone().into_transposed()
+    }
+
+    /// Like `transpose`, reusing the buffer.
+    pub fn into_transposed(self) -> Matrix<T> {
+        let mut transposed = Matrix {
             rows: self.cols,
             cols: self.rows,
-            data: res_data,
+            data: self.data,
+            strides: (self.strides.1, self.strides.0),
+        };
+
+        if transposed.is_contiguous() {
+            transposed.strides = (transposed.cols, 1);
         }
+
+        transposed
     }
 }
 
@@ -198,23 +520,29 @@ mod tests {
 
     #[test]
     fn test_matrix_zeros() {
-        let matrix = Matrix::zeros(2, 3);
+        let matrix: Matrix = Matrix::zeros(2, 3);
         assert_eq!(matrix.rows, 2);
         assert_eq!(matrix.cols, 3);
-        assert_eq!(matrix.data, vec![vec![0.0, 0.0, 0.0], vec![0.0, 0.0, 0.0]]);
+        assert_eq!(
+            matrix.to_rows(),
+            vec![vec![0.0, 0.0, 0.0], vec![0.0, 0.0, 0.0]]
+        );
     }
 
     #[test]
     fn test_matrix_random() {
-        let matrix = Matrix::random(2, 3);
+        let matrix = Matrix::random(2, 3, &mut rand::thread_rng());
         assert_eq!(matrix.rows, 2);
         assert_eq!(matrix.cols, 3);
         // Ensure values are within expected range; specific values are random
-        for row in matrix.data.iter() {
+        for row in matrix.to_rows().iter() {
             for &val in row.iter() {
-                assert!(val >= -1.0 && val <= 1.0);
+                assert!((-1.0..=1.0).contains(&val));
             }
         }
+
+        let seeded = || Matrix::<f64>::random(64, 3, &mut ChaCha8Rng::seed_from_u64(1)).to_vec();
+        assert_eq!(seeded(), seeded());
     }
 
     #[test]
@@ -223,7 +551,7 @@ mod tests {
         let matrix = Matrix::from(data.clone());
         assert_eq!(matrix.rows, 2);
         assert_eq!(matrix.cols, 2);
-        assert_eq!(matrix.data, data);
+        assert_eq!(matrix.to_rows(), data);
     }
 
     #[test]
@@ -231,11 +559,11 @@ mod tests {
         let matrix1 = Matrix::from(vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
         let matrix2 = Matrix::from(vec![vec![1.0, 1.0], vec![1.0, 1.0]]);
         let result = matrix1.add(&matrix2);
-        assert_eq!(result.data, vec![vec![2.0, 3.0], vec![4.0, 5.0]]);
+        assert_eq!(result.to_rows(), vec![vec![2.0, 3.0], vec![4.0, 5.0]]);
     }
 
     #[test]
-    #[should_panic(expected = "Attempted to subtract matrix of incorrect dimensions")]
+    #[should_panic(expected = "Attempted to add matrix of incorrect dimensions")]
     fn test_matrix_add_panic_on_dimension_mismatch() {
         let matrix1 = Matrix::from(vec![vec![1.0, 2.0]]);
         let matrix2 = Matrix::from(vec![vec![1.0]]);
@@ -247,7 +575,7 @@ mod tests {
         let matrix1 = Matrix::from(vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
         let matrix2 = Matrix::from(vec![vec![2.0], vec![1.0]]);
         let result = matrix1.multiply(&matrix2);
-        assert_eq!(result.data, vec![vec![4.0], vec![10.0]]);
+        assert_eq!(result.to_rows(), vec![vec![4.0], vec![10.0]]);
     }
 
     #[test]
@@ -258,25 +586,166 @@ mod tests {
         matrix1.multiply(&matrix2);
     }
 
+    #[test]
+    fn test_matrix_try_ops_report_shapes() {
+        let matrix1 = Matrix::from(vec![vec![1.0, 2.0]]);
+        let matrix2 = Matrix::from(vec![vec![1.0]]);
+
+        assert!(matches!(
+            matrix1.try_multiply(&matrix1),
+            Err(Error::ShapeMismatch {
+                operation: "multiply",
+                left: (1, 2),
+                right: (1, 2),
+            })
+        ));
+        assert!(matrix1.try_subtract(&matrix2).is_err());
+        assert!(matrix1.try_dot_multiply(&matrix2).is_err());
+
+        let error = matrix1.try_add(&matrix2).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "cannot add a 1x2 matrix and a 1x1 matrix"
+        );
+    }
+
     #[test]
     fn test_matrix_dot_multiply() {
         let matrix1 = Matrix::from(vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
         let matrix2 = Matrix::from(vec![vec![2.0, 1.0], vec![0.5, 0.5]]);
         let result = matrix1.dot_multiply(&matrix2);
-        assert_eq!(result.data, vec![vec![2.0, 2.0], vec![1.5, 2.0]]);
+        assert_eq!(result.to_rows(), vec![vec![2.0, 2.0], vec![1.5, 2.0]]);
     }
 
     #[test]
     fn test_matrix_transpose() {
         let matrix = Matrix::from(vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
         let transposed = matrix.transpose();
-        assert_eq!(transposed.data, vec![vec![1.0, 3.0], vec![2.0, 4.0]]);
+        assert_eq!(transposed.to_rows(), vec![vec![1.0, 3.0], vec![2.0, 4.0]]);
     }
 
     #[test]
     fn test_matrix_map() {
-        let matrix = Matrix::from(vec![vec![1.0, -1.0], vec![2.0, -2.0]]);
+        let matrix: Matrix = Matrix::from(vec![vec![1.0, -1.0], vec![2.0, -2.0]]);
         let result = matrix.map(|x| x.abs());
-        assert_eq!(result.data, vec![vec![1.0, 1.0], vec![2.0, 2.0]]);
+        assert_eq!(result.to_rows(), vec![vec![1.0, 1.0], vec![2.0, 2.0]]);
+    }
+
+    #[test]
+    fn test_matrix_from_columns() {
+        let matrix = Matrix::from_columns(&[&vec![1.0, 2.0], &vec![3.0, 4.0], &vec![5.0, 6.0]]);
+        assert_eq!(matrix.rows, 2);
+        assert_eq!(matrix.cols, 3);
+        assert_eq!(
+            matrix.to_rows(),
+            vec![vec![1.0, 3.0, 5.0], vec![2.0, 4.0, 6.0]]
+        );
+        assert_eq!(
+            matrix.columns(),
+            vec![vec![1.0, 2.0], vec![3.0, 4.0], vec![5.0, 6.0]]
+        );
+    }
+
+    #[test]
+    fn test_matrix_add_column() {
+        let matrix = Matrix::from(vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
+        let column = Matrix::from(vec![vec![10.0], vec![20.0]]);
+        let result = matrix.add_column(&column);
+        assert_eq!(result.to_rows(), vec![vec![11.0, 12.0], vec![23.0, 24.0]]);
+    }
+
+    #[test]
+    fn test_matrix_sum_columns_and_scale() {
+        let matrix = Matrix::from(vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
+        let result = matrix.sum_columns().scale(0.5);
+        assert_eq!(result.to_rows(), vec![vec![1.5], vec![3.5]]);
+        assert_eq!(matrix.sum(), 10.0);
+    }
+
+    #[test]
+    fn test_matrix_zip_map() {
+        let matrix1: Matrix = Matrix::from(vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
+        let matrix2 = Matrix::from(vec![vec![4.0, 3.0], vec![2.0, 1.0]]);
+        let result = matrix1.zip_map(&matrix2, |x, y| x.max(y));
+        assert_eq!(result.to_rows(), vec![vec![4.0, 3.0], vec![3.0, 4.0]]);
+    }
+
+    #[test]
+    fn test_matrix_multiply_layouts_and_tiles() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        // Larger than a tile in every dimension, and not a multiple of it
+        let left = Matrix::random(70, 130, &mut rng);
+        let right = Matrix::random(130, 67, &mut rng);
+
+        let naive: Vec<f64> = (0..70)
+            .flat_map(|i| {
+                let (left, right) = (&left, &right);
+                (0..67)
+                    .map(move |j| (0..130).fold(0.0, |sum, k| sum + left[(i, k)] * right[(k, j)]))
+            })
+            .collect();
+
+        // The same values stored by columns
+        let column_major = |matrix: &Matrix| {
+            Matrix::from_vec(matrix.cols, matrix.rows, matrix.transpose().to_vec())
+                .into_transposed()
+        };
+        assert!(!column_major(&right).is_contiguous());
+
+        assert_eq!(left.multiply(&right).to_vec(), naive);
+        assert_eq!(left.multiply(&column_major(&right)).to_vec(), naive);
+        assert_eq!(column_major(&left).multiply(&right).to_vec(), naive);
+    }
+
+    #[test]
+    fn test_matrix_transpose_strides() {
+        let matrix = Matrix::from(vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]]);
+        let transposed = matrix.transpose();
+
+        assert_eq!(transposed.strides(), (1, 3));
+        assert_eq!(transposed[(2, 1)], 6.0);
+        assert_eq!(transposed.to_vec(), vec![1.0, 4.0, 2.0, 5.0, 3.0, 6.0]);
+        assert_eq!(transposed.add(&Matrix::zeros(3, 2)).strides(), (2, 1));
+    }
+
+    #[test]
+    fn test_matrix_in_place_ops() {
+        let mut matrix = Matrix::from(vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
+
+        matrix.add_assign(&Matrix::from(vec![vec![1.0, 1.0], vec![1.0, 1.0]]).transpose());
+        matrix.scale_assign(2.0);
+        matrix.map_inplace(|x| x - 1.0);
+        matrix.subtract_assign(&Matrix::from(vec![vec![1.0, 0.0], vec![0.0, 1.0]]));
+        assert_eq!(matrix.to_rows(), vec![vec![2.0, 5.0], vec![7.0, 8.0]]);
+
+        matrix.zip_map_inplace(&Matrix::zeros(2, 2), |x, y| x * y);
+        assert_eq!(matrix.sum(), 0.0);
+        assert!(matrix.try_add_assign(&Matrix::zeros(1, 2)).is_err());
+    }
+
+    #[test]
+    fn test_matrix_serializes_as_nested_rows() {
+        let matrix = Matrix::from(vec![vec![1.0, 2.0], vec![3.0, 4.0]]).transpose();
+        let json = serde_json::to_string(&matrix).unwrap();
+        assert_eq!(json, r#"{"rows":2,"cols":2,"data":[[1.0,3.0],[2.0,4.0]]}"#);
+
+        let matrix: Matrix = serde_json::from_str(&json).unwrap();
+        assert_eq!(matrix.to_rows(), vec![vec![1.0, 3.0], vec![2.0, 4.0]]);
+        assert!(serde_json::from_str::<Matrix>(r#"{"rows":2,"cols":2,"data":[[1.0]]}"#).is_err());
+    }
+
+    #[test]
+    fn test_matrix_cast() {
+        let matrix = Matrix::from(vec![vec![0.1, 2.0], vec![-3.5, 1e-3]]).transpose();
+        let single: Matrix<f32> = matrix.cast();
+        assert_eq!(single.to_rows(), vec![vec![0.1f32, -3.5], vec![2.0, 1e-3]]);
+
+        let double: Matrix = single.cast();
+        assert!((double[(0, 0)] - 0.1).abs() < 1e-7);
+        assert_eq!(double[(1, 0)], 2.0);
+
+        let json = serde_json::to_string(&matrix).unwrap();
+        let read: Matrix<f32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.to_rows(), single.to_rows());
     }
 }
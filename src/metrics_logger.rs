@@ -1,13 +1,38 @@
-use autometrics::prometheus_exporter;
+use autometrics::{prometheus_exporter, settings::AutometricsSettings};
 use axum::{routing::*, Router};
+use prometheus_client::{metrics::gauge::Gauge, registry::Registry};
 use std::error::Error;
 use std::net::Ipv4Addr;
+use std::sync::atomic::AtomicU64;
+use std::sync::OnceLock;
 use tokio::net::TcpListener;
 
-pub async fn init_metrics() -> Result<(), Box<dyn Error + Send + Sync>> {
+use super::shutdown::Shutdown;
+
+fn learning_rate() -> &'static Gauge<f64, AtomicU64> {
+    static LEARNING_RATE: OnceLock<Gauge<f64, AtomicU64>> = OnceLock::new();
+    LEARNING_RATE.get_or_init(Gauge::default)
+}
+
+/// Exports `learning_rate` as the rate of the current epoch.
+pub fn set_learning_rate(rate: f64) {
+    learning_rate().set(rate);
+}
+
+/// Serves the metrics on port 3000 until `shutdown` is requested.
+pub async fn init_metrics(shutdown: Shutdown) -> Result<(), Box<dyn Error + Send + Sync>> {
     log::info!("Starting prometheus exporter...");
 
-    prometheus_exporter::init();
+    // The exporter encodes this registry next to the autometrics metrics
+    let mut registry = Registry::default();
+    registry.register(
+        "learning_rate",
+        "Learning rate of the current epoch",
+        learning_rate().clone(),
+    );
+    AutometricsSettings::builder()
+        .prometheus_client_registry(registry)
+        .try_init()?;
 
     let app = Router::new().route(
         "/metrics",
@@ -20,9 +45,11 @@ pub async fn init_metrics() -> Result<(), Box<dyn Error + Send + Sync>> {
 
     log::info!("Binded listener to port 3000.");
 
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move { shutdown.requested().await })
+        .await?;
 
-    log::info!("Finished setting up metrics.");
+    log::info!("Stopped the metrics server.");
 
     Ok(())
 }
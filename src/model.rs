@@ -0,0 +1,45 @@
+use std::path::Path;
+
+use super::{
+    autoencoder::Autoencoder,
+    error::Result,
+    float::Float,
+    model_file::{ModelData, ModelFile},
+    network::Network,
+};
+
+/// A saved model of either kind, as found in the model file.
+pub enum Model<T: Float = f64> {
+    Network(Box<Network<T>>),
+    Autoencoder(Box<Autoencoder<T>>),
+}
+
+impl<T: Float> Model<T> {
+    /// Reads a model file, converting the saved weights to `T`.
+    pub fn from_file(file: impl AsRef<Path>) -> Result<Model<T>> {
+        let model_file = ModelFile::read(file)?;
+
+        let model = match model_file.model {
+            ModelData::Network(data) => {
+                let mut network = Network::from_data(data)?;
+                *network.metadata_mut() = model_file.metadata;
+                Model::Network(Box::new(network))
+            }
+            ModelData::Autoencoder { encoder, decoder } => {
+                let mut autoencoder = Autoencoder::from_data(encoder, decoder)?;
+                *autoencoder.metadata_mut() = model_file.metadata;
+                Model::Autoencoder(Box::new(autoencoder))
+            }
+        };
+
+        Ok(model)
+    }
+
+    /// Number of inputs, e.g. the pixels of an image.
+    pub fn input_size(&self) -> usize {
+        match self {
+            Model::Network(network) => network.input_size(),
+            Model::Autoencoder(autoencoder) => autoencoder.input_size(),
+        }
+    }
+}
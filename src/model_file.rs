@@ -0,0 +1,666 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use super::{
+    activations::{Activation, SIGMOID},
+    layers::LayerData,
+    losses::LossConfig,
+    matrix::Matrix,
+    model_format::{self, ModelFormat},
+    optimizers::{OptimizerState, Sgd},
+};
+
+/// Version written by this build. Version 1 are the unversioned files holding
+/// only `weights` and `biases`, version 2 the dense networks listing their
+/// layer sizes. Both are upgraded on read.
+pub const FORMAT_VERSION: u32 = 3;
+
+#[derive(Debug, Error)]
+pub enum ModelFileError {
+    #[error("unable to access model file {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("model file {path} is corrupt: {source}")]
+    Corrupt {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("model file format version {found} is not supported, expected at most {supported}")]
+    UnsupportedVersion { found: u32, supported: u32 },
+    #[error("incompatible model: expected {expected}, found {found}")]
+    Incompatible { expected: String, found: String },
+    #[error("model file {path} is malformed: {reason}")]
+    Malformed { path: PathBuf, reason: String },
+    #[error(
+        "unknown model file format for {path}, expected .json or .bin, optionally with .gz or .zst"
+    )]
+    UnknownFormat { path: PathBuf },
+    #[error("invalid model: {0}")]
+    Invalid(String),
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TrainingMetadata {
+    pub epochs: usize,
+    pub accuracy: Option<f64>,
+    pub reconstruction_error: Option<f64>,
+    pub loss: Option<f64>,
+    pub timestamp: Option<String>,
+}
+
+/// Everything needed to rebuild a `Network` without knowing how it was
+/// created.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NetworkData {
+    pub layers: Vec<LayerData>,
+    pub loss: LossConfig,
+    pub optimizer: OptimizerState,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ModelData {
+    Network(NetworkData),
+    Autoencoder {
+        encoder: NetworkData,
+        decoder: NetworkData,
+    },
+}
+
+impl ModelData {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ModelData::Network(_) => "network",
+            ModelData::Autoencoder { .. } => "autoencoder",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ModelFile {
+    pub format_version: u32,
+    pub metadata: TrainingMetadata,
+    pub model: ModelData,
+}
+
+/// The unversioned format, written by the sigmoid-only networks trained
+/// with squared error and a fixed 0.001 learning rate.
+#[derive(Deserialize)]
+struct LegacySaveData {
+    weights: Vec<Vec<Vec<f64>>>,
+    biases: Vec<Vec<Vec<f64>>>,
+    #[serde(default)]
+    optimizer: Option<OptimizerState>,
+}
+
+/// Version 2, where every network was fully connected.
+#[derive(Deserialize)]
+struct DenseNetworkData {
+    activations: Vec<Activation>,
+    loss: LossConfig,
+    optimizer: OptimizerState,
+    weights: Vec<Matrix>,
+    biases: Vec<Matrix>,
+}
+
+#[derive(Deserialize)]
+enum DenseModelData {
+    Network(DenseNetworkData),
+    Autoencoder {
+        encoder: DenseNetworkData,
+        decoder: DenseNetworkData,
+    },
+}
+
+#[derive(Deserialize)]
+struct DenseModelFile {
+    metadata: TrainingMetadata,
+    model: DenseModelData,
+}
+
+impl DenseNetworkData {
+    fn upgrade(self) -> Result<NetworkData, ModelFileError> {
+        NetworkData::dense(
+            self.weights,
+            self.biases,
+            self.activations,
+            self.loss,
+            self.optimizer,
+        )
+    }
+}
+
+impl ModelFile {
+    pub fn new(model: ModelData, mut metadata: TrainingMetadata) -> ModelFile {
+        metadata.timestamp = Some(Local::now().to_rfc3339());
+
+        ModelFile {
+            format_version: FORMAT_VERSION,
+            metadata,
+            model,
+        }
+    }
+
+    /// Writes the model in the format given by the extension of `path`.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), ModelFileError> {
+        let path = path.as_ref();
+        let format = ModelFormat::from_path(path)?;
+
+        let value = serde_json::to_value(self)
+            .map_err(|error| ModelFileError::Invalid(error.to_string()))?;
+        let bytes = format.encode(value, path)?;
+
+        write_atomically(path, &bytes)
+    }
+
+    /// Reads a model file in any supported format, upgrading legacy files.
+    pub fn read(path: impl AsRef<Path>) -> Result<ModelFile, ModelFileError> {
+        let path = path.as_ref();
+        let corrupt = |source| ModelFileError::Corrupt {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        let bytes = fs::read(path).map_err(|source| ModelFileError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let value = model_format::decode(bytes, path)?;
+
+        let model_file = match value.get("format_version").and_then(Value::as_u64) {
+            None => {
+                let legacy: LegacySaveData = serde_json::from_value(value).map_err(corrupt)?;
+                ModelFile::from_legacy(legacy)?
+            }
+            Some(version) if version > FORMAT_VERSION as u64 => {
+                return Err(ModelFileError::UnsupportedVersion {
+                    found: version as u32,
+                    supported: FORMAT_VERSION,
+                })
+            }
+            Some(2) => {
+                let dense: DenseModelFile = serde_json::from_value(value).map_err(corrupt)?;
+                ModelFile::from_dense(dense)?
+            }
+            Some(_) => serde_json::from_value(value).map_err(corrupt)?,
+        };
+
+        model_file.validate()?;
+
+        Ok(model_file)
+    }
+
+    fn from_legacy(legacy: LegacySaveData) -> Result<ModelFile, ModelFileError> {
+        if legacy.weights.is_empty() || legacy.weights.len() != legacy.biases.len() {
+            return Err(ModelFileError::Invalid(String::from(
+                "legacy file needs one bias per weight matrix",
+            )));
+        }
+
+        let to_matrix = |data: Vec<Vec<f64>>| {
+            if data.is_empty() || data[0].is_empty() {
+                return Err(ModelFileError::Invalid(String::from("empty matrix")));
+            }
+            Ok(Matrix::from(data))
+        };
+
+        let weights = legacy
+            .weights
+            .into_iter()
+            .map(to_matrix)
+            .collect::<Result<Vec<Matrix>, ModelFileError>>()?;
+        let biases = legacy
+            .biases
+            .into_iter()
+            .map(to_matrix)
+            .collect::<Result<Vec<Matrix>, ModelFileError>>()?;
+
+        let activations = vec![SIGMOID; weights.len()];
+
+        Ok(ModelFile::new(
+            ModelData::Network(NetworkData::dense(
+                weights,
+                biases,
+                activations,
+                LossConfig::MeanSquaredError,
+                legacy
+                    .optimizer
+                    .unwrap_or_else(|| OptimizerState::Sgd(Sgd::new(0.001))),
+            )?),
+            TrainingMetadata::default(),
+        ))
+    }
+
+    fn from_dense(dense: DenseModelFile) -> Result<ModelFile, ModelFileError> {
+        let model = match dense.model {
+            DenseModelData::Network(data) => ModelData::Network(data.upgrade()?),
+            DenseModelData::Autoencoder { encoder, decoder } => ModelData::Autoencoder {
+                encoder: encoder.upgrade()?,
+                decoder: decoder.upgrade()?,
+            },
+        };
+
+        Ok(ModelFile {
+            format_version: FORMAT_VERSION,
+            metadata: dense.metadata,
+            model,
+        })
+    }
+
+    /// Human readable description of the model and how it was trained.
+    pub fn summary(&self) -> String {
+        let mut lines = vec![
+            format!("Kind: {}", self.model.kind()),
+            format!("Format: version {}", self.format_version),
+        ];
+
+        match &self.model {
+            ModelData::Network(data) => lines.extend(data.summary("")),
+            ModelData::Autoencoder { encoder, decoder } => {
+                lines.extend(encoder.summary("Encoder "));
+                lines.extend(decoder.summary("Decoder "));
+            }
+        }
+
+        let metadata = &self.metadata;
+        lines.push(format!("Epochs: {}", metadata.epochs));
+        if let Some(accuracy) = metadata.accuracy {
+            lines.push(format!("Accuracy: {}%", accuracy));
+        }
+        if let Some(error) = metadata.reconstruction_error {
+            lines.push(format!("Reconstruction error: {}", error));
+        }
+        if let Some(loss) = metadata.loss {
+            lines.push(format!("Loss: {}", loss));
+        }
+        if let Some(timestamp) = &metadata.timestamp {
+            lines.push(format!("Saved: {}", timestamp));
+        }
+
+        lines.join("\n")
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), ModelFileError> {
+        match &self.model {
+            ModelData::Network(data) => data.validate(),
+            ModelData::Autoencoder { encoder, decoder } => {
+                encoder.validate()?;
+                decoder.validate()?;
+
+                if encoder.output_size() != decoder.input_size() {
+                    return Err(ModelFileError::Invalid(String::from(
+                        "decoder input does not match the encoder bottleneck",
+                    )));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Writes `bytes` to a temporary file next to `path` and renames it, so a
+/// crash never leaves a partly written file at `path`.
+pub(crate) fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), ModelFileError> {
+    let io_error = |source| ModelFileError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    fs::write(&temporary, bytes).map_err(io_error)?;
+    fs::rename(&temporary, path).map_err(io_error)
+}
+
+/// Converts a model file to the format given by the extension of `output`.
+pub fn convert(input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<(), ModelFileError> {
+    ModelFile::read(input)?.write(output)
+}
+
+/// Converts every model file in `directory` that is not yet stored as
+/// `extension`, e.g. `bin.zst`, next to the original. Returns the written
+/// files.
+pub fn convert_directory(
+    directory: impl AsRef<Path>,
+    extension: &str,
+) -> Result<Vec<PathBuf>, ModelFileError> {
+    let directory = directory.as_ref();
+    let target = ModelFormat::from_path(format!("model.{}", extension))?;
+    let io_error = |source| ModelFileError::Io {
+        path: directory.to_path_buf(),
+        source,
+    };
+
+    let mut converted = vec![];
+    for entry in fs::read_dir(directory).map_err(io_error)? {
+        let input = entry.map_err(io_error)?.path();
+        let Ok(format) = ModelFormat::from_path(&input) else {
+            continue;
+        };
+        if format == target {
+            continue;
+        }
+
+        let name = input
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let stem = &name[..name.len() - format.extension().len() - 1];
+        let output = directory.join(format!("{}.{}", stem, extension));
+
+        convert(&input, &output)?;
+        converted.push(output);
+    }
+
+    Ok(converted)
+}
+
+impl NetworkData {
+    /// A fully connected network, each weight matrix followed by its
+    /// activation.
+    pub fn dense(
+        weights: Vec<Matrix>,
+        biases: Vec<Matrix>,
+        activations: Vec<Activation>,
+        loss: LossConfig,
+        optimizer: OptimizerState,
+    ) -> Result<NetworkData, ModelFileError> {
+        if weights.len() != biases.len() || weights.len() != activations.len() {
+            return Err(ModelFileError::Invalid(format!(
+                "expected one bias and activation per weight matrix, found {} weights, {} biases and {} activations",
+                weights.len(),
+                biases.len(),
+                activations.len()
+            )));
+        }
+
+        let layers = weights
+            .into_iter()
+            .zip(biases)
+            .zip(activations)
+            .flat_map(|((weights, biases), activation)| {
+                let size = weights.rows;
+                [
+                    LayerData::Dense { weights, biases },
+                    LayerData::Activation { activation, size },
+                ]
+            })
+            .collect();
+
+        Ok(NetworkData {
+            layers,
+            loss,
+            optimizer,
+        })
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.layers.first().map_or(0, LayerData::input_size)
+    }
+
+    pub fn output_size(&self) -> usize {
+        self.layers.last().map_or(0, LayerData::output_size)
+    }
+
+    /// The input size followed by the names of the layers, e.g. `784-128-10`.
+    pub fn model(&self) -> String {
+        let mut names = vec![self.input_size().to_string()];
+        names.extend(self.layers.iter().filter_map(LayerData::name));
+
+        names.join("-")
+    }
+
+    pub fn parameter_count(&self) -> usize {
+        self.layers
+            .iter()
+            .flat_map(LayerData::parameters)
+            .map(|matrix| matrix.rows * matrix.cols)
+            .sum()
+    }
+
+    fn summary(&self, prefix: &str) -> Vec<String> {
+        let activations: Vec<Activation> = self
+            .layers
+            .iter()
+            .filter_map(LayerData::activation)
+            .collect();
+        let kinds: Vec<&str> = self.layers.iter().map(LayerData::kind).collect();
+        let optimizer = self.optimizer.clone();
+        let learning_rate = optimizer.clone().into_optimizer::<f64>().learning_rate();
+
+        vec![
+            format!("{}Layers: {}", prefix, self.model()),
+            format!("{}Layer types: {}", prefix, kinds.join(", ")),
+            format!("{}Activations: {:?}", prefix, activations),
+            format!("{}Loss function: {:?}", prefix, self.loss),
+            format!(
+                "{}Optimizer: {}, learning rate {}",
+                prefix,
+                optimizer.name(),
+                learning_rate
+            ),
+            format!("{}Parameters: {}", prefix, self.parameter_count()),
+        ]
+    }
+
+    pub fn validate(&self) -> Result<(), ModelFileError> {
+        if self.layers.is_empty() {
+            return Err(ModelFileError::Invalid(String::from(
+                "a network needs at least one layer",
+            )));
+        }
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            layer
+                .validate()
+                .map_err(|error| ModelFileError::Invalid(format!("layer {}: {}", i, error)))?;
+        }
+
+        for (i, pair) in self.layers.windows(2).enumerate() {
+            if pair[0].output_size() != pair[1].input_size() {
+                return Err(ModelFileError::Invalid(format!(
+                    "layer {} outputs {} values, layer {} takes {}",
+                    i,
+                    pair[0].output_size(),
+                    i + 1,
+                    pair[1].input_size()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use crate::activations::SOFTMAX;
+
+    use super::*;
+
+    fn network_data() -> NetworkData {
+        NetworkData::dense(
+            vec![Matrix::from(vec![vec![1.0, 2.0]])],
+            vec![Matrix::from(vec![vec![0.5]])],
+            vec![SOFTMAX],
+            LossConfig::CrossEntropy,
+            OptimizerState::Sgd(Sgd::new(0.1)),
+        )
+        .unwrap()
+    }
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = temp_dir().join(format!("autoencoder-model-file-{}", name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = temp_dir().join("autoencoder-model-file-round-trip.json");
+        let metadata = TrainingMetadata {
+            epochs: 3,
+            accuracy: Some(97.5),
+            ..TrainingMetadata::default()
+        };
+
+        ModelFile::new(ModelData::Network(network_data()), metadata)
+            .write(&path)
+            .unwrap();
+        let model_file = ModelFile::read(&path).unwrap();
+
+        assert_eq!(model_file.format_version, FORMAT_VERSION);
+        assert_eq!(model_file.metadata.epochs, 3);
+        assert_eq!(model_file.metadata.accuracy, Some(97.5));
+        assert!(model_file.metadata.timestamp.is_some());
+        match model_file.model {
+            ModelData::Network(data) => {
+                assert_eq!(data.model(), "2-1");
+                assert_eq!(data.layers[1].activation(), Some(SOFTMAX));
+                assert_eq!(
+                    data.layers[0].parameters()[0].to_rows(),
+                    vec![vec![1.0, 2.0]]
+                );
+            }
+            _ => panic!("Expected a network"),
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        let summary = ModelFile::new(
+            ModelData::Network(network_data()),
+            TrainingMetadata::default(),
+        )
+        .summary();
+
+        assert!(summary.contains("Kind: network"));
+        assert!(summary.contains("Layers: 2-1"));
+        assert!(summary.contains("Optimizer: SGD, learning rate 0.1"));
+        assert!(summary.contains("Parameters: 3"));
+    }
+
+    #[test]
+    fn test_read_legacy_file() {
+        let path = temp_file(
+            "legacy.json",
+            r#"{"weights":[[[1.0,2.0],[3.0,4.0]]],"biases":[[[0.0],[1.0]]]}"#,
+        );
+
+        match ModelFile::read(&path).unwrap().model {
+            ModelData::Network(data) => {
+                assert_eq!(data.model(), "2-2");
+                assert_eq!(data.layers[1].activation(), Some(SIGMOID));
+            }
+            _ => panic!("Expected a network"),
+        }
+    }
+
+    #[test]
+    fn test_read_version_2_file() {
+        let network = serde_json::json!({
+            "layers": [2, 1],
+            "activations": [SOFTMAX],
+            "loss": LossConfig::CrossEntropy,
+            "optimizer": OptimizerState::Sgd(Sgd::new(0.1)),
+            "weights": [Matrix::from(vec![vec![1.0, 2.0]])],
+            "biases": [Matrix::from(vec![vec![0.5]])],
+        });
+        let metadata = TrainingMetadata {
+            epochs: 7,
+            ..TrainingMetadata::default()
+        };
+        let json = serde_json::json!({
+            "format_version": 2,
+            "metadata": metadata,
+            "model": { "Network": network },
+        });
+        let path = temp_file("version-2.json", &json.to_string());
+
+        let model_file = ModelFile::read(&path).unwrap();
+
+        assert_eq!(model_file.format_version, FORMAT_VERSION);
+        assert_eq!(model_file.metadata.epochs, 7);
+        match model_file.model {
+            ModelData::Network(data) => {
+                assert_eq!(data.model(), "2-1");
+                assert_eq!(data.layers[1].activation(), Some(SOFTMAX));
+                assert_eq!(data.parameter_count(), 3);
+            }
+            _ => panic!("Expected a network"),
+        }
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let missing = temp_dir().join("autoencoder-model-file-missing.json");
+        assert!(matches!(
+            ModelFile::read(missing),
+            Err(ModelFileError::Io { .. })
+        ));
+
+        let corrupt = temp_file("corrupt.json", "{\"weights\": [[[1.0");
+        assert!(matches!(
+            ModelFile::read(corrupt),
+            Err(ModelFileError::Corrupt { .. })
+        ));
+
+        let future = temp_file("future.json", r#"{"format_version": 99}"#);
+        assert!(matches!(
+            ModelFile::read(future),
+            Err(ModelFileError::UnsupportedVersion { found: 99, .. })
+        ));
+
+        let mut data = network_data();
+        data.layers[0] = LayerData::Dense {
+            weights: Matrix::from(vec![vec![1.0, 2.0], vec![3.0, 4.0]]),
+            biases: Matrix::from(vec![vec![0.5]]),
+        };
+        let json = serde_json::to_string(&ModelFile::new(
+            ModelData::Network(data),
+            TrainingMetadata::default(),
+        ))
+        .unwrap();
+        let mismatched = temp_file("mismatched.json", &json);
+        assert!(matches!(
+            ModelFile::read(mismatched),
+            Err(ModelFileError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_convert_directory() {
+        let directory = temp_dir().join("autoencoder-model-file-convert");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        ModelFile::new(
+            ModelData::Network(network_data()),
+            TrainingMetadata::default(),
+        )
+        .write(directory.join("model-97.5.json"))
+        .unwrap();
+        fs::write(directory.join("notes.txt"), "not a model").unwrap();
+
+        let converted = convert_directory(&directory, "bin.gz").unwrap();
+
+        assert_eq!(converted, vec![directory.join("model-97.5.bin.gz")]);
+        match ModelFile::read(&converted[0]).unwrap().model {
+            ModelData::Network(data) => {
+                assert_eq!(data.layers[0].parameters()[1].to_rows(), vec![vec![0.5]])
+            }
+            _ => panic!("Expected a network"),
+        }
+    }
+}
@@ -0,0 +1,447 @@
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use super::model_file::{ModelFileError, FORMAT_VERSION};
+
+/// Binary model files start with this magic, followed by
+///
+/// ```text
+/// u32 LE  format version
+/// u8      float width in bytes, 4 or 8
+/// u64 LE  header length
+/// header  the JSON model file, with every matrix replaced by
+///         {"rows": r, "cols": c, "blob": i}
+/// blobs   r * c little-endian floats per matrix, row-major, in blob order
+/// ```
+pub const MAGIC: &[u8; 4] = b"AENN";
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Precision of stored weights, and of the networks trained and run by the
+/// CLI.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Precision {
+    F32,
+    #[default]
+    F64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Json,
+    Binary(Precision),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// How a model file is stored, picked from its extension: `.json`, `.bin`
+/// for f64 or `.f32.bin` for f32 weights, optionally followed by `.gz` or
+/// `.zst`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModelFormat {
+    pub encoding: Encoding,
+    pub compression: Compression,
+}
+
+impl ModelFormat {
+    pub fn from_path(path: impl AsRef<Path>) -> Result<ModelFormat, ModelFileError> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+
+        let (name, compression) = if let Some(name) = name.strip_suffix(".gz") {
+            (name, Compression::Gzip)
+        } else if let Some(name) = name.strip_suffix(".zst") {
+            (name, Compression::Zstd)
+        } else {
+            (name, Compression::None)
+        };
+
+        let encoding = if name.ends_with(".json") {
+            Encoding::Json
+        } else if name.ends_with(".f32.bin") {
+            Encoding::Binary(Precision::F32)
+        } else if name.ends_with(".bin") {
+            Encoding::Binary(Precision::F64)
+        } else {
+            return Err(ModelFileError::UnknownFormat {
+                path: path.to_path_buf(),
+            });
+        };
+
+        Ok(ModelFormat {
+            encoding,
+            compression,
+        })
+    }
+
+    /// The extension written for this format, e.g. `bin.zst`.
+    pub fn extension(&self) -> String {
+        let encoding = match self.encoding {
+            Encoding::Json => "json",
+            Encoding::Binary(Precision::F32) => "f32.bin",
+            Encoding::Binary(Precision::F64) => "bin",
+        };
+
+        match self.compression {
+            Compression::None => String::from(encoding),
+            Compression::Gzip => format!("{}.gz", encoding),
+            Compression::Zstd => format!("{}.zst", encoding),
+        }
+    }
+
+    pub fn encode(&self, value: Value, path: &Path) -> Result<Vec<u8>, ModelFileError> {
+        let bytes = match self.encoding {
+            Encoding::Json => value.to_string().into_bytes(),
+            Encoding::Binary(precision) => encode_binary(value, precision),
+        };
+
+        compress(bytes, self.compression).map_err(|source| ModelFileError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+/// Decodes a model file of any format, detected from its contents rather than
+/// its extension.
+pub fn decode(bytes: Vec<u8>, path: &Path) -> Result<Value, ModelFileError> {
+    let bytes = decompress(bytes).map_err(|source| ModelFileError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    if bytes.starts_with(MAGIC) {
+        decode_binary(&bytes, path)
+    } else {
+        serde_json::from_slice(&bytes).map_err(|source| ModelFileError::Corrupt {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+fn compress(bytes: Vec<u8>, compression: Compression) -> std::io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(bytes),
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&bytes)?;
+            encoder.finish()
+        }
+        Compression::Zstd => zstd::encode_all(bytes.as_slice(), 0),
+    }
+}
+
+fn decompress(bytes: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    if bytes.starts_with(GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(bytes.as_slice()).read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    } else if bytes.starts_with(ZSTD_MAGIC) {
+        zstd::decode_all(bytes.as_slice())
+    } else {
+        Ok(bytes)
+    }
+}
+
+/// A serialized `Matrix` is an object holding exactly `rows`, `cols` and
+/// the nested `data` rows.
+fn is_matrix(map: &Map<String, Value>) -> bool {
+    map.len() == 3
+        && map.get("rows").is_some_and(Value::is_u64)
+        && map.get("cols").is_some_and(Value::is_u64)
+        && map.get("data").is_some_and(Value::is_array)
+}
+
+fn extract_matrices(value: &mut Value, blobs: &mut Vec<Vec<f64>>) {
+    match value {
+        Value::Object(map) if is_matrix(map) => {
+            let blob: Vec<f64> = map["data"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_array)
+                .flatten()
+                .map(|x| x.as_f64().unwrap_or(f64::NAN))
+                .collect();
+
+            *value = json!({ "rows": map["rows"], "cols": map["cols"], "blob": blobs.len() });
+            blobs.push(blob);
+        }
+        Value::Object(map) => map
+            .values_mut()
+            .for_each(|value| extract_matrices(value, blobs)),
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| extract_matrices(value, blobs)),
+        _ => {}
+    }
+}
+
+fn encode_binary(mut value: Value, precision: Precision) -> Vec<u8> {
+    let mut blobs = vec![];
+    extract_matrices(&mut value, &mut blobs);
+    let header = value.to_string().into_bytes();
+
+    let width = match precision {
+        Precision::F32 => 4,
+        Precision::F64 => 8,
+    };
+    let floats: usize = blobs.iter().map(|blob| blob.len()).sum();
+
+    let mut bytes = Vec::with_capacity(17 + header.len() + floats * width);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.push(width as u8);
+    bytes.extend_from_slice(&(header.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&header);
+
+    for x in blobs.iter().flatten() {
+        match precision {
+            Precision::F32 => bytes.extend_from_slice(&(*x as f32).to_le_bytes()),
+            Precision::F64 => bytes.extend_from_slice(&x.to_le_bytes()),
+        }
+    }
+
+    bytes
+}
+
+/// Reads the binary layout described at `MAGIC`, tracking the position to
+/// report truncated files.
+struct BinaryReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    path: &'a Path,
+}
+
+impl<'a> BinaryReader<'a> {
+    fn malformed(&self, reason: String) -> ModelFileError {
+        ModelFileError::Malformed {
+            path: PathBuf::from(self.path),
+            reason,
+        }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], ModelFileError> {
+        let end = self.position.saturating_add(count);
+
+        if end > self.bytes.len() {
+            return Err(self.malformed(format!(
+                "truncated at byte {}, expected {} more bytes",
+                self.bytes.len(),
+                end - self.bytes.len()
+            )));
+        }
+
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, ModelFileError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, ModelFileError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn floats(&mut self, count: usize, width: usize) -> Result<Vec<f64>, ModelFileError> {
+        let bytes = self.take(count.saturating_mul(width))?;
+
+        Ok(bytes
+            .chunks_exact(width)
+            .map(|chunk| match width {
+                4 => f32::from_le_bytes(chunk.try_into().unwrap()) as f64,
+                _ => f64::from_le_bytes(chunk.try_into().unwrap()),
+            })
+            .collect())
+    }
+}
+
+/// Placeholder left in the header for the matrix stored in blob `blob`.
+struct BlobShape {
+    blob: usize,
+    rows: usize,
+    cols: usize,
+}
+
+fn collect_blob_shapes(value: &Value, shapes: &mut Vec<BlobShape>) {
+    match value {
+        Value::Object(map) if map.contains_key("blob") => {
+            let field = |key: &str| map.get(key).and_then(Value::as_u64).unwrap_or(0) as usize;
+            shapes.push(BlobShape {
+                blob: field("blob"),
+                rows: field("rows"),
+                cols: field("cols"),
+            });
+        }
+        Value::Object(map) => map
+            .values()
+            .for_each(|value| collect_blob_shapes(value, shapes)),
+        Value::Array(values) => values
+            .iter()
+            .for_each(|value| collect_blob_shapes(value, shapes)),
+        _ => {}
+    }
+}
+
+fn restore_matrices(value: &mut Value, matrices: &mut [Option<Value>]) {
+    match value {
+        Value::Object(map) if map.contains_key("blob") => {
+            let blob = map["blob"].as_u64().unwrap_or(0) as usize;
+            *value = matrices[blob].take().unwrap_or(Value::Null);
+        }
+        Value::Object(map) => map
+            .values_mut()
+            .for_each(|value| restore_matrices(value, matrices)),
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| restore_matrices(value, matrices)),
+        _ => {}
+    }
+}
+
+fn decode_binary(bytes: &[u8], path: &Path) -> Result<Value, ModelFileError> {
+    let mut reader = BinaryReader {
+        bytes,
+        position: MAGIC.len(),
+        path,
+    };
+
+    let version = reader.u32()?;
+    if version > FORMAT_VERSION {
+        return Err(ModelFileError::UnsupportedVersion {
+            found: version,
+            supported: FORMAT_VERSION,
+        });
+    }
+
+    let width = reader.take(1)?[0] as usize;
+    if width != 4 && width != 8 {
+        return Err(reader.malformed(format!("unsupported float width {}", width)));
+    }
+
+    let header_length = reader.u64()? as usize;
+    let mut header: Value =
+        serde_json::from_slice(reader.take(header_length)?).map_err(|source| {
+            ModelFileError::Corrupt {
+                path: path.to_path_buf(),
+                source,
+            }
+        })?;
+
+    let mut shapes = vec![];
+    collect_blob_shapes(&header, &mut shapes);
+    shapes.sort_by_key(|shape| shape.blob);
+
+    let mut matrices = Vec::with_capacity(shapes.len());
+    for (i, shape) in shapes.iter().enumerate() {
+        if shape.blob != i {
+            return Err(reader.malformed(format!("missing or duplicate blob {}", i)));
+        }
+
+        let values = reader.floats(shape.rows.saturating_mul(shape.cols), width)?;
+        let data: Vec<&[f64]> = values.chunks(shape.cols.max(1)).collect();
+        matrices.push(Some(
+            json!({ "rows": shape.rows, "cols": shape.cols, "data": data }),
+        ));
+    }
+
+    if reader.position != bytes.len() {
+        return Err(reader.malformed(format!(
+            "{} unexpected bytes after the last blob",
+            bytes.len() - reader.position
+        )));
+    }
+
+    restore_matrices(&mut header, &mut matrices);
+
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model() -> Value {
+        json!({
+            "format_version": FORMAT_VERSION,
+            "layers": [2, 1],
+            "weights": [{ "rows": 1, "cols": 2, "data": [[0.1, -2.5]] }],
+            "biases": [{ "rows": 1, "cols": 1, "data": [[0.3]] }],
+        })
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        let format = ModelFormat::from_path("data/networks/model.f32.bin.zst").unwrap();
+
+        assert_eq!(format.encoding, Encoding::Binary(Precision::F32));
+        assert_eq!(format.compression, Compression::Zstd);
+        assert_eq!(format.extension(), "f32.bin.zst");
+        assert_eq!(
+            ModelFormat::from_path("model.json").unwrap().encoding,
+            Encoding::Json
+        );
+        assert!(matches!(
+            ModelFormat::from_path("model.txt"),
+            Err(ModelFileError::UnknownFormat { .. })
+        ));
+    }
+
+    #[test]
+    fn test_encode_decode_all_formats() {
+        let path = Path::new("model");
+
+        for encoding in [
+            Encoding::Json,
+            Encoding::Binary(Precision::F64),
+            Encoding::Binary(Precision::F32),
+        ] {
+            for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+                let format = ModelFormat {
+                    encoding,
+                    compression,
+                };
+                let bytes = format.encode(model(), path).unwrap();
+                let decoded = decode(bytes, path).unwrap();
+
+                assert_eq!(decoded["layers"], json!([2, 1]));
+                assert_eq!(decoded["biases"][0]["rows"], json!(1));
+                let weight = decoded["weights"][0]["data"][0][1].as_f64().unwrap();
+                assert_eq!(weight, -2.5, "{:?}", format);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_truncated_binary() {
+        let path = Path::new("model.bin");
+        let format = ModelFormat::from_path(path).unwrap();
+        let mut bytes = format.encode(model(), path).unwrap();
+        bytes.truncate(bytes.len() - 4);
+
+        assert!(matches!(
+            decode(bytes, path),
+            Err(ModelFileError::Malformed { .. })
+        ));
+    }
+}
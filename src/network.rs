@@ -3,135 +3,516 @@
  *
  * https://github.com/mathletedev/rust-ml/
  **/
-use std::{
-    fs::File,
-    io::{Read, Write},
-};
+use std::path::Path;
 
 use autometrics::autometrics;
 use ndarray::{s, Array2, ArrayBase, Dim};
 use rand::prelude::SliceRandom;
-use rand::thread_rng;
-use rayon::prelude::*;
-use serde::{Deserialize, Serialize};
-use serde_json::{from_str, json};
-use spinners::{Spinner, Spinners};
+use rand::Rng;
 
 use super::{
-    activations::Activation, matrix::Matrix, utils::convert_number_to_target_vec,
-    utils::convert_result_vec_to_number,
+    activations::Activation,
+    data_set::DataSet,
+    error::{Error, Result},
+    float::Float,
+    layers::{ActivationLayer, Dense, Dropout, Layer, LayerData, Mode},
+    losses::Loss,
+    matrix::Matrix,
+    model_file::{ModelData, ModelFile, ModelFileError, NetworkData, TrainingMetadata},
+    normalization::{BatchNorm, LayerNorm, Normalization},
+    optimizers::Optimizer,
+    progress::Progress,
+    regularization::{GradientClipping, Regularization},
+    utils::convert_number_to_target_vec,
+    utils::try_convert_result_vec_to_number,
 };
 
-pub struct Network {
-    layers: Vec<usize>,
-    weights: Vec<Matrix>,
-    biases: Vec<Matrix>,
-    data: Vec<Matrix>,
-    scale_by_learning_rate: fn(f64) -> f64,
-    activation: Activation,
+/// A sequence of layers computing in `T`, `f64` unless given. Samples and
+/// targets are passed as `f64` and converted.
+pub struct Network<T: Float = f64> {
+    layers: Vec<Box<dyn Layer<T>>>,
+    /// Shape of the outputs of the last `feed_forward_batch`.
+    outputs: Option<(usize, usize)>,
+    mode: Mode,
+    /// Penalties on the weights of every layer.
+    regularization: Vec<Regularization>,
+    clipping: GradientClipping,
+    optimizer: Box<dyn Optimizer<T>>,
+    loss: Box<dyn Loss<T>>,
+    metadata: TrainingMetadata,
+}
+
+/// One column per sample, converted to the precision of the network.
+pub(crate) fn batch<T: Float>(columns: &[&Vec<f64>]) -> Matrix<T> {
+    let rows = columns[0].len();
+    let data = (0..rows)
+        .flat_map(|i| columns.iter().map(move |column| T::of(column[i])))
+        .collect();
+
+    Matrix::from_vec(rows, columns.len(), data)
+}
+
+/// Fully connected layers with `sizes`, each followed by its activation.
+/// `dropout[i]`, when given, is the rate at which the outputs of layer `i`
+/// are dropped before they reach the next one, so the input layer can have
+/// dropout but the output layer cannot. A rate of 0 adds no layer. The
+/// `normalization` goes between every hidden dense layer and its activation.
+pub fn dense_layers<T: Float>(
+    sizes: &[usize],
+    activations: &[Activation],
+    dropout: &[f64],
+    normalization: Option<Normalization>,
+    rng: &mut impl Rng,
+) -> Vec<Box<dyn Layer<T>>> {
+    let mut layers: Vec<Box<dyn Layer<T>>> = vec![];
+    let outputs = sizes.len() - 2;
+
+    for (i, (pair, activation)) in sizes.windows(2).zip(activations).enumerate() {
+        match dropout.get(i) {
+            Some(&rate) if rate > 0.0 => {
+                layers.push(Box::new(Dropout::with_rng(rate, pair[0], rng)));
+            }
+            _ => {}
+        }
+        layers.push(Box::new(Dense::with_rng(pair[0], pair[1], rng)));
+        match normalization {
+            Some(Normalization::BatchNorm) if i < outputs => {
+                layers.push(Box::new(BatchNorm::new(pair[1])));
+            }
+            Some(Normalization::LayerNorm) if i < outputs => {
+                layers.push(Box::new(LayerNorm::new(pair[1])));
+            }
+            _ => {}
+        }
+        layers.push(Box::new(ActivationLayer::new(*activation, pair[1])));
+    }
+
+    layers
 }
 
-#[derive(Serialize, Deserialize)]
-struct SaveData {
-    weights: Vec<Vec<Vec<f64>>>,
-    biases: Vec<Vec<Vec<f64>>>,
+/// The values of a single sample column as `f64`.
+fn sample<T: Float>(column: &Matrix<T>) -> Vec<f64> {
+    column.to_vec().into_iter().map(T::as_f64).collect()
 }
 
 #[autometrics]
 impl Network {
-    pub fn new<'a>(
+    /// A fully connected network with `layers` sizes. `activations[i]` is
+    /// applied to the outputs of layer `i + 1`, so one activation is needed
+    /// for every layer but the input layer. Use `with_rng` for other
+    /// precisions.
+    pub fn new(
         layers: Vec<usize>,
-        scale_by_learning_rate: fn(f64) -> f64,
-        activation: Activation,
+        optimizer: Box<dyn Optimizer>,
+        activations: Vec<Activation>,
+        loss: Box<dyn Loss>,
     ) -> Network {
-        let mut weights = vec![];
-        let mut biases = vec![];
+        Network::with_rng(
+            layers,
+            optimizer,
+            activations,
+            loss,
+            &mut rand::thread_rng(),
+        )
+    }
+}
 
-        for i in 0..layers.len() - 1 {
-            weights.push(Matrix::random(layers[i + 1], layers[i]));
-            biases.push(Matrix::random(layers[i + 1], 1));
+#[autometrics]
+impl<T: Float> Network<T> {
+    /// Like `new`, drawing the initial weights and biases from `rng`.
+    pub fn with_rng(
+        layers: Vec<usize>,
+        optimizer: Box<dyn Optimizer<T>>,
+        activations: Vec<Activation>,
+        loss: Box<dyn Loss<T>>,
+        rng: &mut impl Rng,
+    ) -> Network<T> {
+        if layers.len() < 2 || activations.len() != layers.len() - 1 {
+            panic!("Invalid activations length");
+        }
+
+        Network::from_layers(
+            dense_layers(&layers, &activations, &[], None, rng),
+            optimizer,
+            loss,
+        )
+    }
+
+    /// Chains `layers`, e.g. convolutions followed by dense layers. Panics if
+    /// the outputs of a layer do not fit the inputs of the next one.
+    pub fn from_layers(
+        layers: Vec<Box<dyn Layer<T>>>,
+        optimizer: Box<dyn Optimizer<T>>,
+        loss: Box<dyn Loss<T>>,
+    ) -> Network<T> {
+        if layers.is_empty() {
+            panic!("Network has no layers");
+        }
+        for pair in layers.windows(2) {
+            if pair[0].output_size() != pair[1].input_size() {
+                panic!(
+                    "Layer outputs {} values, next layer takes {}",
+                    pair[0].output_size(),
+                    pair[1].input_size()
+                );
+            }
         }
 
-        Network {
+        let mut network = Network {
+            regularization: vec![Regularization::default(); layers.len()],
+            clipping: GradientClipping::default(),
             layers,
-            weights,
-            biases,
-            data: vec![],
-            scale_by_learning_rate,
-            activation,
+            outputs: None,
+            mode: Mode::Training,
+            optimizer,
+            loss,
+            metadata: TrainingMetadata::default(),
+        };
+        network.set_mode(Mode::Evaluation);
+
+        network
+    }
+
+    /// Rebuilds a network, including its optimizer state, from saved data,
+    /// converting it to the precision `T`.
+    pub fn from_data(data: NetworkData) -> std::result::Result<Network<T>, ModelFileError> {
+        data.validate()?;
+
+        Ok(Network::from_layers(
+            data.layers.iter().map(LayerData::build).collect(),
+            data.optimizer.into_optimizer(),
+            data.loss.build(),
+        ))
+    }
+
+    pub fn from_file(file: impl AsRef<Path>) -> Result<Network<T>> {
+        let model_file = ModelFile::read(file)?;
+
+        match model_file.model {
+            ModelData::Network(data) => {
+                let mut network = Network::from_data(data)?;
+                network.metadata = model_file.metadata;
+                Ok(network)
+            }
+            model => Err(Error::ModelFile(ModelFileError::Incompatible {
+                expected: String::from("network"),
+                found: String::from(model.kind()),
+            })),
         }
     }
 
     pub fn feed_forward(&mut self, inputs: Vec<f64>) -> Vec<f64> {
-        if inputs.len() != self.layers[0] {
-            panic!("Invalid inputs length");
+        self.try_feed_forward(inputs)
+            .expect("Invalid inputs length")
+    }
+
+    pub fn try_feed_forward(&mut self, inputs: Vec<f64>) -> Result<Vec<f64>> {
+        let outputs = self.try_feed_forward_batch(batch(&[&inputs]))?;
+
+        Ok(sample(&outputs))
+    }
+
+    pub fn feed_forward_batch(&mut self, inputs: Matrix<T>) -> Matrix<T> {
+        self.try_feed_forward_batch(inputs)
+            .expect("Invalid inputs length")
+    }
+
+    /// Runs a block of inputs, one sample per column, through every layer,
+    /// which keep what they need for back propagation.
+    pub fn try_feed_forward_batch(&mut self, inputs: Matrix<T>) -> Result<Matrix<T>> {
+        self.outputs = None;
+
+        let mut current = inputs;
+        for layer in self.layers.iter_mut() {
+            current = layer.forward(&current)?;
         }
+        self.outputs = Some(current.shape());
 
-        let mut current = Matrix::from(vec![inputs]).transpose();
-        self.data = vec![current.clone()];
+        Ok(current)
+    }
+
+    pub fn predict(&self, inputs: &[f64]) -> Vec<f64> {
+        self.try_predict(inputs).expect("Invalid inputs length")
+    }
 
-        for i in 0..self.layers.len() - 1 {
-            current = self.weights[i]
-                .multiply(&current)
-                .add(&self.biases[i])
-                .map(self.activation.function);
-            self.data.push(current.clone());
+    pub fn try_predict(&self, inputs: &[f64]) -> Result<Vec<f64>> {
+        let mut current = batch(&[&inputs.to_vec()]);
+        for layer in &self.layers {
+            current = layer.predict(&current)?;
         }
 
-        current.transpose().data[0].to_owned()
+        Ok(sample(&current))
+    }
+
+    pub fn back_propogate(&mut self, outputs: Vec<f64>, targets: Vec<f64>) -> f64 {
+        self.try_back_propogate(outputs, targets)
+            .expect("Invalid targets length")
+    }
+
+    pub fn try_back_propogate(&mut self, outputs: Vec<f64>, targets: Vec<f64>) -> Result<f64> {
+        self.back_propogate_batch(&batch(&[&outputs]), &batch(&[&targets]))
+    }
+
+    /// Updates the network from the outputs of the last `feed_forward_batch`
+    /// and returns the loss of the batch.
+    pub fn back_propogate_batch(
+        &mut self,
+        outputs: &Matrix<T>,
+        targets: &Matrix<T>,
+    ) -> Result<f64> {
+        let (loss, _) = self.propagate_loss(outputs, targets)?;
+
+        Ok(loss)
     }
 
-    pub fn back_propogate(&mut self, outputs: Vec<f64>, targets: Vec<f64>) {
-        if targets.len() != self.layers[self.layers.len() - 1] {
-            panic!("Invalid targets length");
+    /// Like `back_propogate_batch`, but also returns the errors at the input
+    /// layer so networks can be chained.
+    pub fn propagate_loss(
+        &mut self,
+        outputs: &Matrix<T>,
+        targets: &Matrix<T>,
+    ) -> Result<(f64, Matrix<T>)> {
+        let output_size = self.output_size();
+        if targets.rows != output_size {
+            return Err(Error::InvalidLength {
+                name: "targets",
+                expected: output_size,
+                found: targets.rows,
+            });
         }
+        if outputs.shape() != targets.shape() {
+            return Err(Error::ShapeMismatch {
+                operation: "compare",
+                left: outputs.shape(),
+                right: targets.shape(),
+            });
+        }
+        self.check_forward_pass(outputs)?;
+
+        let loss = self.loss.loss(outputs, targets) + self.penalty();
+        let last = self.layers.len() - 1;
+
+        // Losses such as cross entropy after softmax give the gradients before
+        // the output activation directly
+        let fused = self.layers[last]
+            .activation()
+            .and_then(|activation| self.loss.fused_gradient(activation, outputs, targets));
+
+        let errors = match fused {
+            Some(gradients) => self.propagate_gradients(last, gradients)?,
+            None => {
+                self.propagate_gradients(self.layers.len(), self.loss.gradient(outputs, targets))?
+            }
+        };
 
-        let parsed = Matrix::from(vec![outputs]).transpose();
-        let mut errors = Matrix::from(vec![targets]).transpose().subtract(&parsed);
-        let mut gradients = parsed.map(self.activation.derivative);
+        Ok((loss, errors))
+    }
 
-        for i in (0..self.layers.len() - 1).rev() {
-            gradients = gradients
-                .dot_multiply(&errors)
-                .map(self.scale_by_learning_rate);
+    /// Applies the output `errors` of the last `feed_forward_batch` and returns
+    /// the errors at the input layer, so networks can be chained.
+    pub fn propagate_errors(&mut self, errors: Matrix<T>) -> Result<Matrix<T>> {
+        self.check_forward_pass(&errors)?;
 
-            self.weights[i] = self.weights[i].add(&gradients.multiply(&self.data[i].transpose()));
-            self.biases[i] = self.biases[i].add(&gradients);
+        self.propagate_gradients(self.layers.len(), errors)
+    }
 
-            errors = self.weights[i].transpose().multiply(&errors);
-            gradients = self.data[i].map(self.activation.derivative);
+    /// Errors must match the outputs of the last `feed_forward_batch`.
+    fn check_forward_pass(&self, errors: &Matrix<T>) -> Result<()> {
+        match self.outputs {
+            None => Err(Error::MissingForwardPass),
+            Some(outputs) if outputs != errors.shape() => Err(Error::ShapeMismatch {
+                operation: "back propagate",
+                left: outputs,
+                right: errors.shape(),
+            }),
+            Some(_) => Ok(()),
         }
     }
 
-    pub fn train(&mut self, inputs: &Vec<&Vec<f64>>, targets: &Vec<&Vec<f64>>) {
-        let input_length = inputs.len();
-        let mut last_progress_pct = 0;
-        let mut sp = Spinner::new(
-            Spinners::Dots9,
-            format!("Training with {} images... [0%]", input_length).into(),
-        );
-        for j in 0..input_length {
-            let progress_pct = 100 * j / input_length;
-
-            if last_progress_pct != progress_pct {
-                last_progress_pct = progress_pct;
-                sp.stop();
-                sp = Spinner::new(
-                    Spinners::Dots9,
-                    format!(
-                        "Training with {} images... [{}%]",
-                        input_length, progress_pct
-                    )
-                    .into(),
-                );
+    /// Back propagates the gradients with respect to the outputs of layer
+    /// `end - 1` through it and every layer before it, then updates them.
+    /// Each layer averages its gradients over the batch. The regularization
+    /// gradients are added and the clipping applied before all of them go to
+    /// the optimizer, which knows the parameters by ids numbered consecutively
+    /// across the layers in order, each layer in the order of its
+    /// `parameters`. The ids of a layer so follow those of every layer with
+    /// parameters before it, such as batch normalization or convolutions, not
+    /// only the dense layers.
+    fn propagate_gradients(&mut self, end: usize, mut gradients: Matrix<T>) -> Result<Matrix<T>> {
+        for layer in self.layers[..end].iter_mut().rev() {
+            gradients = layer.backward(&gradients)?;
+        }
+
+        let mut parameter_gradients = vec![];
+        for (layer, regularization) in self.layers[..end].iter().zip(&self.regularization) {
+            for (i, (values, gradients)) in layer
+                .parameters()
+                .into_iter()
+                .zip(layer.gradients())
+                .enumerate()
+            {
+                let mut gradients = gradients.clone();
+                if layer.is_weight(i) {
+                    regularization.add_gradient(values, &mut gradients);
+                }
+                parameter_gradients.push(gradients);
+            }
+        }
+        self.clipping.clip(&mut parameter_gradients);
+
+        let mut parameter_gradients = parameter_gradients.into_iter().enumerate();
+        for layer in self.layers[..end].iter_mut() {
+            let updated: Vec<Matrix<T>> = layer
+                .parameters()
+                .into_iter()
+                .zip(parameter_gradients.by_ref())
+                .map(|(values, (parameter, gradients))| {
+                    self.optimizer.update(parameter, values, &gradients)
+                })
+                .collect();
+            for (values, updated) in layer.parameters_mut().into_iter().zip(updated) {
+                *values = updated;
             }
+        }
+
+        Ok(gradients)
+    }
+
+    /// The regularization penalty of the current weights, which the loss of
+    /// every batch includes.
+    pub fn penalty(&self) -> f64 {
+        self.layers
+            .iter()
+            .zip(&self.regularization)
+            .filter(|(_, regularization)| !regularization.is_none())
+            .flat_map(|(layer, regularization)| {
+                layer
+                    .parameters()
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(i, _)| layer.is_weight(*i))
+                    .map(|(_, weights)| regularization.penalty(weights))
+                    .collect::<Vec<f64>>()
+            })
+            .sum()
+    }
+
+    /// Applies `regularization` to the weights of every layer.
+    pub fn set_regularization(&mut self, regularization: Regularization) {
+        self.regularization = vec![regularization; self.layers.len()];
+    }
+
+    /// Applies `regularization` to the weights of `layers()[layer]` only.
+    pub fn set_layer_regularization(&mut self, layer: usize, regularization: Regularization) {
+        self.regularization[layer] = regularization;
+    }
+
+    pub fn regularization(&self) -> &[Regularization] {
+        &self.regularization
+    }
+
+    pub fn set_clipping(&mut self, clipping: GradientClipping) {
+        self.clipping = clipping;
+    }
+
+    pub fn clipping(&self) -> GradientClipping {
+        self.clipping
+    }
+
+    pub fn layers(&self) -> &[Box<dyn Layer<T>>] {
+        &self.layers
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.layers[0].input_size()
+    }
 
-            let outputs = self.feed_forward(inputs[j].clone());
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
 
-            self.back_propogate(outputs, targets[j].clone());
+    /// `train` switches to training and back to evaluation by itself. Set
+    /// the mode to train with `feed_forward` and `back_propogate` directly.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        for layer in self.layers.iter_mut() {
+            layer.set_mode(mode);
         }
-        sp.stop_with_message("Training done!".into());
-        log::info!("Completed training")
+    }
+
+    pub fn output_size(&self) -> usize {
+        self.layers[self.layers.len() - 1].output_size()
+    }
+
+    pub fn optimizer(&self) -> &dyn Optimizer<T> {
+        self.optimizer.as_ref()
+    }
+
+    pub fn optimizer_mut(&mut self) -> &mut dyn Optimizer<T> {
+        self.optimizer.as_mut()
+    }
+
+    pub fn loss(&self) -> &dyn Loss<T> {
+        self.loss.as_ref()
+    }
+
+    /// Trains one pass over `inputs` and returns the mean loss of the batches.
+    pub fn train(
+        &mut self,
+        inputs: &[&Vec<f64>],
+        targets: &[&Vec<f64>],
+        batch_size: usize,
+    ) -> Result<f64> {
+        self.train_from(inputs, targets, batch_size, 0, |_, _| Ok(()))
+    }
+
+    /// Trains on the batches from `first_batch` on, calling `on_batch` with
+    /// the count of completed batches after each one. Returns the mean loss
+    /// of the batches trained.
+    pub fn train_from(
+        &mut self,
+        inputs: &[&Vec<f64>],
+        targets: &[&Vec<f64>],
+        batch_size: usize,
+        first_batch: usize,
+        mut on_batch: impl FnMut(&Network<T>, usize) -> Result<()>,
+    ) -> Result<f64> {
+        let batch_size = batch_size.max(1);
+        let mut progress = Progress::new(inputs.len().saturating_sub(first_batch * batch_size));
+        let mut total_loss = 0.0;
+        let mut batches = 0;
+
+        self.set_mode(Mode::Training);
+        let trained = (|| -> Result<()> {
+            for (index, (batch_inputs, batch_targets)) in inputs
+                .chunks(batch_size)
+                .zip(targets.chunks(batch_size))
+                .enumerate()
+                .skip(first_batch)
+            {
+                let outputs = self.try_feed_forward_batch(batch(batch_inputs))?;
+
+                let loss = self.back_propogate_batch(&outputs, &batch(batch_targets))?;
+
+                log::debug!("Batch {} loss: {}", index, loss);
+                total_loss += loss;
+                batches += 1;
+
+                progress.advance(batch_inputs.len(), loss);
+                on_batch(self, index + 1)?;
+            }
+            Ok(())
+        })();
+        // Also when a batch fails or `on_batch` stops training, e.g. on an
+        // interrupt, so the network is not saved or validated while training
+        self.set_mode(Mode::Evaluation);
+        trained?;
+        progress.finish();
+
+        let mean_loss = total_loss / batches.max(1) as f64;
+        log::info!("Completed training, loss: {:?}", mean_loss);
+
+        Ok(mean_loss)
     }
 
     pub fn validate(
@@ -140,26 +521,32 @@ impl Network {
         test_labels: &Array2<f64>,
         validation_set_size: u32,
         shape: usize,
-    ) -> f64 {
+    ) -> Result<f64> {
+        self.set_mode(Mode::Evaluation);
+
         let mut rights = 0.0;
         let mut wrongs = 0.0;
 
         let mut failed = vec![0.0; 10];
 
         for i in 0..(validation_set_size as usize) {
-            let image = test_data
-                .slice(s![i, .., ..])
-                .to_owned()
+            let image = test_data.slice(s![i, .., ..]).to_owned();
+            let image_size = image.len();
+            let image = image
                 .into_shape((shape,))
-                .unwrap()
+                .map_err(|_| Error::InvalidLength {
+                    name: "image",
+                    expected: shape,
+                    found: image_size,
+                })?
                 .to_vec();
             let label =
                 convert_number_to_target_vec(test_labels.slice(s![i, ..]).to_vec()[0] as usize);
 
-            let result = self.feed_forward(image);
+            let result = self.try_feed_forward(image)?;
 
-            let result_number = convert_result_vec_to_number(result);
-            let label_number = convert_result_vec_to_number(label);
+            let result_number = try_convert_result_vec_to_number(result)?;
+            let label_number = try_convert_result_vec_to_number(label)?;
 
             if label_number == result_number {
                 rights += 1.0;
@@ -180,134 +567,217 @@ impl Network {
             failed
         );
 
-        right_percentage
+        Ok(right_percentage)
     }
 
+    /// The input size followed by the names of the layers, e.g. `784-128-10`
+    /// for a dense network.
     pub fn model(&self) -> String {
-        let network_model_str: Vec<String> =
-            self.layers.par_iter().map(|n| n.to_string()).collect();
-        let network_model_concatenated = network_model_str.join("-");
+        self.to_data().model()
+    }
+
+    /// The network for saving, with its values converted to `f64`.
+    pub fn to_data(&self) -> NetworkData {
+        NetworkData {
+            layers: self.layers.iter().map(|layer| layer.to_data()).collect(),
+            loss: self.loss.config(),
+            optimizer: self.optimizer.state(),
+        }
+    }
+
+    pub fn metadata(&self) -> &TrainingMetadata {
+        &self.metadata
+    }
 
-        network_model_concatenated
+    pub fn metadata_mut(&mut self) -> &mut TrainingMetadata {
+        &mut self.metadata
     }
 
-    pub fn save(&self, file: String) {
-        let mut file = File::create(file).expect("Unable to touch save file");
+    pub fn save(&self, file: impl AsRef<Path>) -> Result<()> {
+        ModelFile::new(ModelData::Network(self.to_data()), self.metadata.clone()).write(file)?;
 
-        file.write_all(
-			json!({
-				"weights": self.weights.clone().into_iter().map(|matrix| matrix.data).collect::<Vec<Vec<Vec<f64>>>>(),
-				"biases": self.biases.clone().into_iter().map(|matrix| matrix.data).collect::<Vec<Vec<Vec<f64>>>>()
-			}).to_string().as_bytes(),
-		).expect("Unable to write to save file");
+        Ok(())
     }
 
-    pub fn load(&mut self, file: String) {
-        let mut file = File::open(file).expect("Unable to open save file");
-        let mut buffer = String::new();
+    /// Loads the layers and optimizer state of a saved network with the same
+    /// layers and kind of optimizer, keeping the loss, regularization and
+    /// clipping of this one.
+    pub fn load(&mut self, file: impl AsRef<Path>) -> Result<()> {
+        let saved = Network::<T>::from_file(file)?;
+        let metadata = saved.metadata.clone();
 
-        file.read_to_string(&mut buffer)
-            .expect("Unable to read save file");
+        self.replace(saved)?;
+        self.metadata = metadata;
 
-        let save_data: SaveData = from_str(&buffer).expect("Unable to serialize save data");
+        Ok(())
+    }
+
+    /// Restores the layers and optimizer state of `data`, e.g. a snapshot
+    /// taken with `to_data`, keeping the metadata.
+    pub fn restore(&mut self, data: NetworkData) -> Result<()> {
+        self.replace(Network::from_data(data)?)
+    }
 
-        let mut weights = vec![];
-        let mut biases = vec![];
+    /// Fails unless `saved` has the same layers, down to their shapes and
+    /// settings such as dropout rates and activations, and the same kind of
+    /// optimizer, so it can take the place of this network.
+    pub(crate) fn check_compatible(&self, saved: &Network<T>) -> Result<()> {
+        let incompatible = |expected: String, found: String| {
+            Err(Error::ModelFile(ModelFileError::Incompatible {
+                expected,
+                found,
+            }))
+        };
+
+        if saved.layers.len() != self.layers.len() {
+            return incompatible(
+                format!("{} with {} layers", self.model(), self.layers.len()),
+                format!("{} with {} layers", saved.model(), saved.layers.len()),
+            );
+        }
+        for (i, (layer, saved)) in self.layers.iter().zip(&saved.layers).enumerate() {
+            let (expected, found) = (layer.to_data().description(), saved.to_data().description());
+            if expected != found {
+                return incompatible(
+                    format!("layer {} {}", i, expected),
+                    format!("layer {} {}", i, found),
+                );
+            }
+        }
 
-        for i in 0..self.layers.len() - 1 {
-            weights.push(Matrix::from(save_data.weights[i].clone()));
-            biases.push(Matrix::from(save_data.biases[i].clone()));
+        let (expected, found) = (
+            self.optimizer.state().name(),
+            saved.optimizer.state().name(),
+        );
+        if expected != found {
+            return incompatible(
+                format!("{} optimizer", expected),
+                format!("{} optimizer", found),
+            );
         }
 
-        self.weights = weights;
-        self.biases = biases;
+        Ok(())
+    }
+
+    pub(crate) fn replace(&mut self, saved: Network<T>) -> Result<()> {
+        self.check_compatible(&saved)?;
+
+        self.layers = saved.layers;
+        self.outputs = None;
+        self.set_mode(self.mode);
+        self.optimizer = saved.optimizer;
+
+        Ok(())
     }
 
+    /// Trains one epoch on the training set shuffled with `rng`, then validates
+    /// it on the validation set, never the test set. Returns true once every
+    /// image is classified right.
     pub fn run_training_epoch(
         &mut self,
-        train_inputs: &Vec<Vec<f64>>,
-        train_targets: &Vec<Vec<f64>>,
-        test_data: &ArrayBase<ndarray::OwnedRepr<f64>, Dim<[usize; 3]>>,
-        test_labels: &Array2<f64>,
-        val_data: &ArrayBase<ndarray::OwnedRepr<f64>, Dim<[usize; 3]>>,
-        val_labels: &Array2<f64>,
-        image_size: usize,
-        val_set_size: u32,
-        test_set_size: u32,
-    ) -> bool {
-        // Clone data to mutable variables for shuffling
-        let mut rng = thread_rng();
-        let inputs_shuffled = train_inputs.clone();
-        let targets_shuffled = train_targets.clone();
+        data_set: &DataSet,
+        batch_size: usize,
+        rng: &mut impl Rng,
+    ) -> Result<bool> {
+        self.resume_training_epoch(data_set, batch_size, rng, 0, |_, _| Ok(()))
+    }
+
+    /// Continues an epoch from `first_batch`, see `train_from`. Shuffling
+    /// with `rng` in the state it had at the start of the epoch repeats its
+    /// order.
+    pub fn resume_training_epoch(
+        &mut self,
+        data_set: &DataSet,
+        batch_size: usize,
+        rng: &mut impl Rng,
+        first_batch: usize,
+        on_batch: impl FnMut(&Network<T>, usize) -> Result<()>,
+    ) -> Result<bool> {
+        let image_size = self.input_size();
+        let val_set_size = data_set.val_labels.shape()[0] as u32;
 
         // Shuffle inputs and targets in unison
-        let mut combined: Vec<(&Vec<f64>, &Vec<f64>)> = inputs_shuffled
+        let mut combined: Vec<(&Vec<f64>, &Vec<f64>)> = data_set
+            .train_inputs
             .iter()
-            .zip(targets_shuffled.iter())
+            .zip(data_set.train_targets.iter())
             .collect();
-        combined.shuffle(&mut rng);
+        combined.shuffle(rng);
 
         // Unzip them back into separate vectors
-        let (inputs_shuffled, targets_shuffled): (Vec<_>, Vec<_>) =
-            combined.iter().cloned().unzip();
+        let (inputs_shuffled, targets_shuffled): (Vec<_>, Vec<_>) = combined.into_iter().unzip();
 
         // Now train with the shuffled data
-        self.train(&inputs_shuffled, &targets_shuffled);
-
-        log::info!("Network trained with training data");
-
-        let right_percentage = self.validate(&val_data, &val_labels, val_set_size, image_size);
-
-        if right_percentage == 100.0 {
-            log::info!("Right percentage of 100% reached, will stop training");
-            return true;
-        }
-
-        log::info!("Validate using final test data set");
-
-        let right_percentage_test =
-            self.validate(&test_data, &test_labels, test_set_size, image_size);
-
-        if right_percentage_test == 100.0 {
-            log::info!("Right percentage of 100% reached, will stop training");
-            return true;
-        }
-
-        return false;
+        let loss = self.train_from(
+            &inputs_shuffled,
+            &targets_shuffled,
+            batch_size,
+            first_batch,
+            on_batch,
+        )?;
+
+        log::info!("Network trained with training data, epoch loss: {:?}", loss);
+
+        let right_percentage = self.validate(
+            &data_set.val_data,
+            &data_set.val_labels,
+            val_set_size,
+            image_size,
+        )?;
+
+        self.metadata.epochs += 1;
+        self.metadata.loss = Some(loss);
+        self.metadata.accuracy = Some(right_percentage);
+
+        Ok(right_percentage == 100.0)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::activations::SIGMOID;
+    use crate::activations::{RELU, SIGMOID, SOFTMAX, TANH};
+    use crate::losses::{BinaryCrossEntropy, CrossEntropy, MeanSquaredError};
+    use crate::optimizers::Sgd;
 
     use super::*;
 
+    fn parameters(network: &Network) -> Vec<Matrix> {
+        network
+            .layers()
+            .iter()
+            .flat_map(|layer| layer.parameters().into_iter().cloned())
+            .collect()
+    }
+
     #[test]
     fn test_network_initialization() {
         let layers = vec![3, 5, 2];
-        let network = Network::new(layers.clone(), |x| x * 0.1, SIGMOID);
-
-        // Check if all layers except the input have weights and biases initialized
-        assert_eq!(network.weights.len(), 2); // Since there are 2 connections between 3 layers
-        assert_eq!(network.biases.len(), 2);
+        let network = Network::new(
+            layers.clone(),
+            Box::new(Sgd::new(0.1)),
+            vec![SIGMOID; 2],
+            Box::new(MeanSquaredError),
+        );
 
-        // Check dimensions of weights and biases
-        assert_eq!(network.weights[0].rows, 5);
-        assert_eq!(network.weights[0].cols, 3);
-        assert_eq!(network.biases[0].rows, 5);
-        assert_eq!(network.biases[0].cols, 1);
+        // A dense and an activation layer for each of the 2 connections
+        assert_eq!(network.layers().len(), 4);
+        assert_eq!(network.model(), "3-5-2");
 
-        assert_eq!(network.weights[1].rows, 2);
-        assert_eq!(network.weights[1].cols, 5);
-        assert_eq!(network.biases[1].rows, 2);
-        assert_eq!(network.biases[1].cols, 1);
+        // Weights and biases of both dense layers, in the order of the
+        // optimizer ids
+        let shapes: Vec<(usize, usize)> = parameters(&network).iter().map(Matrix::shape).collect();
+        assert_eq!(shapes, vec![(5, 3), (5, 1), (2, 5), (2, 1)]);
     }
 
     #[test]
     fn test_feed_forward() {
         let layers = vec![2, 3, 1];
-        let mut network = Network::new(layers, |x| x * 0.1, SIGMOID);
+        let mut network = Network::new(
+            layers,
+            Box::new(Sgd::new(0.1)),
+            vec![SIGMOID; 2],
+            Box::new(MeanSquaredError),
+        );
 
         // Example input
         let inputs = vec![0.5, -0.1];
@@ -320,21 +790,525 @@ mod tests {
     #[test]
     fn test_back_propagation() {
         let layers = vec![2, 3, 1];
-        let mut network = Network::new(layers, |x| x * 0.1, SIGMOID);
+        let mut network = Network::new(
+            layers,
+            Box::new(Sgd::new(0.1)),
+            vec![SIGMOID; 2],
+            Box::new(MeanSquaredError),
+        );
 
         let inputs = vec![0.5, -0.1];
         let targets = vec![1.0];
 
         // Capture initial weights and biases
-        let initial_weights = network.weights[0].clone();
-        let initial_biases = network.biases[0].clone();
+        let initial = parameters(&network);
 
         // Perform back propagation
         let outputs = network.feed_forward(inputs);
         network.back_propogate(outputs, targets);
 
         // Check if weights and biases have changed
-        assert_ne!(network.weights[0].data, initial_weights.data);
-        assert_ne!(network.biases[0].data, initial_biases.data);
+        for (changed, initial) in parameters(&network).iter().zip(initial.iter()) {
+            assert_ne!(changed.to_rows(), initial.to_rows());
+        }
+    }
+
+    #[test]
+    fn test_back_propagation_batch_matches_single_sample() {
+        let layers = vec![2, 3, 1];
+        let mut single = Network::new(
+            layers,
+            Box::new(Sgd::new(0.1)),
+            vec![SIGMOID; 2],
+            Box::new(MeanSquaredError),
+        );
+        let mut batched = Network::<f64>::from_data(single.to_data()).unwrap();
+
+        // A batch of two identical samples averages to the single sample update
+        let inputs = vec![0.5, -0.1];
+        let targets = vec![1.0];
+
+        let outputs = single.feed_forward(inputs.clone());
+        single.back_propogate(outputs, targets.clone());
+
+        let outputs = batched.feed_forward_batch(Matrix::from_columns(&[&inputs, &inputs]));
+        batched
+            .back_propogate_batch(&outputs, &Matrix::from_columns(&[&targets, &targets]))
+            .unwrap();
+
+        for (a, b) in parameters(&single).iter().zip(parameters(&batched).iter()) {
+            for (x, y) in a.to_vec().iter().zip(b.to_vec().iter()) {
+                assert!((x - y).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_shapes_return_errors() {
+        let mut network = Network::new(
+            vec![2, 3, 1],
+            Box::new(Sgd::new(0.1)),
+            vec![SIGMOID; 2],
+            Box::new(MeanSquaredError),
+        );
+
+        assert!(matches!(
+            network.try_back_propogate(vec![0.5], vec![1.0]),
+            Err(Error::MissingForwardPass)
+        ));
+        assert!(matches!(
+            network.try_feed_forward(vec![1.0, 2.0, 3.0]),
+            Err(Error::InvalidLength {
+                name: "inputs",
+                expected: 2,
+                found: 3
+            })
+        ));
+
+        let outputs = network.feed_forward(vec![1.0, 0.5]);
+        assert!(matches!(
+            network.try_back_propogate(outputs, vec![1.0, 0.0]),
+            Err(Error::InvalidLength {
+                name: "targets",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_save_and_from_file_resume_exactly() {
+        use crate::optimizers::Adam;
+
+        let path = std::env::temp_dir().join("autoencoder-network-resume.json");
+        let mut network = Network::new(
+            vec![2, 3, 2],
+            Box::new(Adam::new(0.01)),
+            vec![RELU, SOFTMAX],
+            Box::new(CrossEntropy),
+        );
+        let outputs = network.feed_forward(vec![0.5, -0.1]);
+        network.back_propogate(outputs, vec![1.0, 0.0]);
+        network.metadata_mut().epochs = 4;
+
+        network.save(&path).unwrap();
+        let mut resumed = Network::<f64>::from_file(&path).unwrap();
+
+        assert_eq!(resumed.model(), "2-3-2");
+        let activations: Vec<Activation> = resumed
+            .layers()
+            .iter()
+            .filter_map(|layer| layer.activation())
+            .collect();
+        assert_eq!(activations, vec![RELU, SOFTMAX]);
+        assert_eq!(resumed.metadata().epochs, 4);
+
+        let outputs = network.feed_forward(vec![0.5, -0.1]);
+        network.back_propogate(outputs, vec![1.0, 0.0]);
+        let outputs = resumed.feed_forward(vec![0.5, -0.1]);
+        resumed.back_propogate(outputs, vec![1.0, 0.0]);
+
+        for (a, b) in parameters(&network).iter().zip(parameters(&resumed).iter()) {
+            assert_eq!(a.to_rows(), b.to_rows());
+        }
+    }
+
+    #[test]
+    fn test_load_rejects_other_layers() {
+        let path = std::env::temp_dir().join("autoencoder-network-layers.json");
+        let network = Network::new(
+            vec![2, 3, 1],
+            Box::new(Sgd::new(0.1)),
+            vec![SIGMOID; 2],
+            Box::new(MeanSquaredError),
+        );
+        network.save(&path).unwrap();
+
+        let mut other = Network::new(
+            vec![2, 4, 1],
+            Box::new(Sgd::new(0.1)),
+            vec![SIGMOID; 2],
+            Box::new(MeanSquaredError),
+        );
+
+        assert!(matches!(
+            other.load(&path),
+            Err(Error::ModelFile(ModelFileError::Incompatible { .. }))
+        ));
+
+        // Same sizes, but dropout, other activations or another optimizer
+        let mut rng = crate::random::rng(Some(2), crate::random::Stream::Initialization);
+        let others = [
+            Network::from_layers(
+                dense_layers(&[2, 3, 1], &[SIGMOID; 2], &[0.0, 0.5], None, &mut rng),
+                Box::new(Sgd::new(0.1)),
+                Box::new(MeanSquaredError),
+            ),
+            Network::new(
+                vec![2, 3, 1],
+                Box::new(Sgd::new(0.1)),
+                vec![RELU, SIGMOID],
+                Box::new(MeanSquaredError),
+            ),
+            Network::new(
+                vec![2, 3, 1],
+                Box::new(crate::optimizers::Adam::new(0.1)),
+                vec![SIGMOID; 2],
+                Box::new(MeanSquaredError),
+            ),
+        ];
+        for mut other in others {
+            let layers = other.to_data().layers.len();
+            assert_eq!(other.model(), "2-3-1");
+            assert!(matches!(
+                other.load(&path),
+                Err(Error::ModelFile(ModelFileError::Incompatible { .. }))
+            ));
+            assert_eq!(other.to_data().layers.len(), layers);
+        }
+
+        let mut same = Network::new(
+            vec![2, 3, 1],
+            Box::new(Sgd::new(0.5)),
+            vec![SIGMOID; 2],
+            Box::new(MeanSquaredError),
+        );
+        same.load(&path).unwrap();
+        assert_eq!(same.predict(&[0.3, 0.7]), network.predict(&[0.3, 0.7]));
+    }
+
+    #[test]
+    fn test_per_layer_activations() {
+        let mut network = Network::new(
+            vec![3, 4, 3],
+            Box::new(Sgd::new(0.1)),
+            vec![RELU, SOFTMAX],
+            Box::new(CrossEntropy),
+        );
+
+        let output = network.feed_forward(vec![0.2, -0.4, 0.9]);
+
+        assert!((output.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_propagated_errors_match_finite_differences() {
+        let cases: Vec<(Vec<Activation>, Box<dyn Loss>)> = vec![
+            (vec![TANH, SIGMOID], Box::new(MeanSquaredError)),
+            (vec![TANH, SOFTMAX], Box::new(CrossEntropy)),
+            (vec![TANH, SIGMOID], Box::new(BinaryCrossEntropy)),
+        ];
+
+        for (activations, loss) in cases {
+            // A zero learning rate keeps the weights fixed while propagating
+            let mut network =
+                Network::new(vec![3, 4, 2], Box::new(Sgd::new(0.0)), activations, loss);
+            let inputs = vec![0.3, -0.7, 0.5];
+            let targets = Matrix::from(vec![vec![1.0], vec![0.0]]);
+
+            let loss_at = |network: &Network, inputs: &[f64]| -> f64 {
+                let outputs = Matrix::from(vec![network.predict(inputs)]).transpose();
+                network.loss().loss(&outputs, &targets)
+            };
+
+            let outputs =
+                network.feed_forward_batch(Matrix::from(vec![inputs.clone()]).transpose());
+            let (_, errors) = network.propagate_loss(&outputs, &targets).unwrap();
+
+            let h = 1e-6;
+            for j in 0..inputs.len() {
+                let mut plus = inputs.clone();
+                let mut minus = inputs.clone();
+                plus[j] += h;
+                minus[j] -= h;
+
+                let numeric = (loss_at(&network, &plus) - loss_at(&network, &minus)) / (2.0 * h);
+                assert!((numeric - errors[(j, 0)]).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_f32_network_learns() {
+        use crate::optimizers::Adam;
+
+        let mut network = Network::<f32>::with_rng(
+            vec![2, 4, 1],
+            Box::new(Adam::new(0.05)),
+            vec![TANH, SIGMOID],
+            Box::new(BinaryCrossEntropy),
+            &mut crate::random::rng(Some(3), crate::random::Stream::Initialization),
+        );
+        let inputs = [
+            vec![0.0, 0.0],
+            vec![0.0, 1.0],
+            vec![1.0, 0.0],
+            vec![1.0, 1.0],
+        ];
+        let targets = [vec![0.0], vec![1.0], vec![1.0], vec![0.0]];
+        let batch_loss = |network: &mut Network<f32>| {
+            let outputs = network.feed_forward_batch(batch(&inputs.iter().collect::<Vec<_>>()));
+            let targets = batch(&targets.iter().collect::<Vec<_>>());
+            network.back_propogate_batch(&outputs, &targets).unwrap()
+        };
+
+        let initial_loss = batch_loss(&mut network);
+        for _ in 0..500 {
+            batch_loss(&mut network);
+        }
+
+        assert!(batch_loss(&mut network) < initial_loss / 2.0);
+    }
+
+    #[test]
+    fn test_f64_save_loads_into_f32() {
+        use crate::optimizers::Adam;
+
+        let path = std::env::temp_dir().join("autoencoder-network-precision.json");
+        let mut network = Network::new(
+            vec![2, 3, 2],
+            Box::new(Adam::new(0.01)),
+            vec![RELU, SOFTMAX],
+            Box::new(CrossEntropy),
+        );
+        let outputs = network.feed_forward(vec![0.5, -0.1]);
+        network.back_propogate(outputs, vec![1.0, 0.0]);
+        network.save(&path).unwrap();
+
+        let mut converted = Network::<f32>::from_file(&path).unwrap();
+        assert_eq!(converted.model(), network.model());
+        for (x, y) in network
+            .predict(&[0.3, 0.7])
+            .iter()
+            .zip(converted.predict(&[0.3, 0.7]).iter())
+        {
+            assert!((x - y).abs() < 1e-6);
+        }
+
+        // The optimizer state comes along, so training continues in f32
+        let outputs = converted.feed_forward(vec![0.5, -0.1]);
+        assert!(converted
+            .back_propogate(outputs, vec![1.0, 0.0])
+            .is_finite());
+    }
+
+    #[test]
+    fn test_convolutional_classifier() {
+        use crate::convolution::{Conv2D, Flatten, Pool2D, Shape, Window};
+        use crate::optimizers::Adam;
+
+        let mut rng = crate::random::rng(Some(5), crate::random::Stream::Initialization);
+        let image = Shape::new(1, 4, 4);
+        let conv = Conv2D::with_rng(image, 2, Window::new(3, 1, 1), &mut rng);
+        let pool = Pool2D::max(conv.output_shape(), Window::new(2, 2, 0));
+        let features = pool.output_shape();
+        let layers: Vec<Box<dyn Layer>> = vec![
+            Box::new(conv),
+            Box::new(ActivationLayer::new(RELU, image.size() * 2)),
+            Box::new(pool),
+            Box::new(Flatten::new(features)),
+            Box::new(Dense::with_rng(features.size(), 2, &mut rng)),
+            Box::new(ActivationLayer::new(SOFTMAX, 2)),
+        ];
+        let mut network =
+            Network::from_layers(layers, Box::new(Adam::new(0.01)), Box::new(CrossEntropy));
+        assert_eq!(network.model(), "16-conv2-maxpool-2");
+
+        // A bright left half against a bright right half
+        let left: Vec<f64> = (0..16).map(|i| if i % 4 < 2 { 1.0 } else { 0.0 }).collect();
+        let right: Vec<f64> = left.iter().map(|x| 1.0 - x).collect();
+        let inputs = [&left, &right];
+        let targets = [&vec![1.0, 0.0], &vec![0.0, 1.0]];
+
+        let mut loss = f64::MAX;
+        for _ in 0..100 {
+            loss = network.train(&inputs, &targets, 2).unwrap();
+        }
+        assert!(loss < 0.1);
+
+        let path = std::env::temp_dir().join("autoencoder-network-convolution.bin");
+        network.save(&path).unwrap();
+        let loaded = Network::<f64>::from_file(&path).unwrap();
+
+        assert_eq!(loaded.model(), network.model());
+        assert_eq!(loaded.predict(&left), network.predict(&left));
+    }
+
+    #[test]
+    fn test_dropout_only_while_training() {
+        let mut rng = crate::random::rng(Some(9), crate::random::Stream::Initialization);
+        let layers =
+            dense_layers::<f64>(&[4, 64, 2], &[RELU, SOFTMAX], &[0.0, 0.5], None, &mut rng);
+        let mut network =
+            Network::from_layers(layers, Box::new(Sgd::new(0.1)), Box::new(CrossEntropy));
+        let inputs = vec![0.5, -0.1, 0.3, 0.9];
+
+        assert_eq!(network.model(), "4-64-2");
+        assert_eq!(network.mode(), Mode::Evaluation);
+        assert_eq!(
+            network.feed_forward(inputs.clone()),
+            network.predict(&inputs)
+        );
+
+        network.set_mode(Mode::Training);
+        assert_ne!(
+            network.feed_forward(inputs.clone()),
+            network.predict(&inputs)
+        );
+
+        // Training switches back to evaluation once done
+        let targets = vec![1.0, 0.0];
+        network.train(&[&inputs], &[&targets], 1).unwrap();
+        assert_eq!(network.mode(), Mode::Evaluation);
+        assert_eq!(
+            network.feed_forward(inputs.clone()),
+            network.predict(&inputs)
+        );
+
+        // Also when stopped after a batch or failing on one
+        let stopped = network.train_from(&[&inputs], &[&targets], 1, 0, |_, _| {
+            Err(Error::EmptyResult)
+        });
+        assert!(stopped.is_err());
+        assert!(network.train(&[&vec![0.5]], &[&targets], 1).is_err());
+        assert_eq!(network.mode(), Mode::Evaluation);
+        assert_eq!(
+            network.feed_forward(inputs.clone()),
+            network.predict(&inputs)
+        );
+    }
+
+    #[test]
+    fn test_dropout_network_rebuilds_identically() {
+        let mut rng = crate::random::rng(Some(6), crate::random::Stream::Initialization);
+        let layers =
+            dense_layers::<f64>(&[4, 16, 2], &[RELU, SOFTMAX], &[0.2, 0.5], None, &mut rng);
+        let mut network =
+            Network::from_layers(layers, Box::new(Sgd::new(0.1)), Box::new(CrossEntropy));
+        let inputs = vec![0.5, -0.1, 0.3, 0.9];
+        let targets = vec![1.0, 0.0];
+        network.train(&[&inputs], &[&targets], 1).unwrap();
+
+        // The masks continue from where the saved generators were
+        let mut rebuilt = Network::<f64>::from_data(network.to_data()).unwrap();
+        for network in [&mut network, &mut rebuilt] {
+            network.train(&[&inputs], &[&targets], 1).unwrap();
+            network.set_mode(Mode::Training);
+        }
+
+        assert_eq!(
+            network.feed_forward(inputs.clone()),
+            rebuilt.feed_forward(inputs)
+        );
+        assert_eq!(
+            serde_json::to_string(&network.to_data()).unwrap(),
+            serde_json::to_string(&rebuilt.to_data()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_batch_norm_network_saves_running_statistics() {
+        let mut rng = crate::random::rng(Some(4), crate::random::Stream::Initialization);
+        let layers = dense_layers::<f64>(
+            &[3, 8, 2],
+            &[SIGMOID, SOFTMAX],
+            &[],
+            Some(Normalization::BatchNorm),
+            &mut rng,
+        );
+        let mut network =
+            Network::from_layers(layers, Box::new(Sgd::new(0.1)), Box::new(CrossEntropy));
+        assert_eq!(network.layers()[1].to_data().kind(), "batch_norm");
+
+        let inputs = [&vec![0.2, -0.4, 0.9], &vec![0.7, 0.1, -0.3]];
+        let targets = [&vec![1.0, 0.0], &vec![0.0, 1.0]];
+        for _ in 0..5 {
+            network.train(&inputs, &targets, 2).unwrap();
+        }
+
+        let path = std::env::temp_dir().join("autoencoder-network-batch-norm.bin");
+        network.save(&path).unwrap();
+        let mut loaded = Network::<f64>::from_file(&path).unwrap();
+        assert_eq!(loaded.predict(inputs[0]), network.predict(inputs[0]));
+
+        // Loading into a network of the same model brings the statistics along
+        let mut other = Network::<f64>::from_layers(
+            dense_layers(
+                &[3, 8, 2],
+                &[SIGMOID, SOFTMAX],
+                &[],
+                Some(Normalization::BatchNorm),
+                &mut rng,
+            ),
+            Box::new(Sgd::new(0.1)),
+            Box::new(CrossEntropy),
+        );
+        other.load(&path).unwrap();
+        assert_eq!(other.predict(inputs[1]), loaded.predict(inputs[1]));
+        assert!(loaded
+            .feed_forward(inputs[1].clone())
+            .iter()
+            .all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn test_regularization_spares_biases_and_adds_penalty() {
+        let mut regularized = Network::new(
+            vec![2, 3, 1],
+            Box::new(Sgd::new(0.1)),
+            vec![SIGMOID; 2],
+            Box::new(MeanSquaredError),
+        );
+        let mut plain = Network::<f64>::from_data(regularized.to_data()).unwrap();
+        regularized.set_regularization(Regularization::new(0.0, 0.5));
+        let initial = parameters(&regularized);
+
+        let penalty: f64 = [&initial[0], &initial[2]]
+            .iter()
+            .map(|weights| 0.25 * weights.to_vec().iter().map(|w| w * w).sum::<f64>())
+            .sum();
+        assert!((regularized.penalty() - penalty).abs() < 1e-12);
+
+        let (inputs, targets) = (vec![0.5, -0.1], vec![1.0]);
+        let outputs = regularized.feed_forward(inputs.clone());
+        let regularized_loss = regularized.back_propogate(outputs, targets.clone());
+        let outputs = plain.feed_forward(inputs);
+        let plain_loss = plain.back_propogate(outputs, targets);
+        assert!((regularized_loss - plain_loss - penalty).abs() < 1e-12);
+
+        let (decayed, plain) = (parameters(&regularized), parameters(&plain));
+        assert_eq!(decayed[1].to_rows(), plain[1].to_rows());
+        for ((w, p), initial) in decayed[0]
+            .to_vec()
+            .iter()
+            .zip(plain[0].to_vec())
+            .zip(initial[0].to_vec())
+        {
+            assert!((w - (p - 0.1 * 0.5 * initial)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_gradient_clipping_bounds_updates() {
+        let mut network = Network::new(
+            vec![2, 3, 2],
+            Box::new(Sgd::new(1.0)),
+            vec![RELU, SOFTMAX],
+            Box::new(CrossEntropy),
+        );
+        network.set_clipping(GradientClipping {
+            value: Some(1.0),
+            norm: Some(0.01),
+        });
+        let initial = parameters(&network);
+
+        let outputs = network.feed_forward(vec![5.0, -3.0]);
+        network.back_propogate(outputs, vec![0.0, 1.0]);
+
+        let steps: Vec<Matrix> = parameters(&network)
+            .iter()
+            .zip(&initial)
+            .map(|(updated, initial)| initial.subtract(updated))
+            .collect();
+        assert!(crate::regularization::global_norm(&steps) <= 0.01 + 1e-12);
     }
 }
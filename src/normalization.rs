@@ -0,0 +1,552 @@
+use autometrics::autometrics;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    convolution::Shape,
+    error::{Error, Result},
+    float::Float,
+    layers::{check_gradients, check_inputs, Layer, LayerData, Mode},
+    matrix::Matrix,
+};
+
+pub const MOMENTUM: f64 = 0.1;
+pub const EPSILON: f64 = 1e-5;
+
+/// Normalization added between the dense layers of a network and their
+/// activations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Normalization {
+    BatchNorm,
+    LayerNorm,
+}
+
+/// What `backward` needs from the last `forward`.
+struct Normalized<T> {
+    /// The inputs shifted to zero mean and scaled to unit variance.
+    values: Vec<T>,
+    /// One over the standard deviation of every group of values.
+    inverse_deviations: Vec<T>,
+    batch: usize,
+    /// Whether the mean and variance came from the batch itself, and so
+    /// depend on the inputs.
+    batch_statistics: bool,
+}
+
+/// Gradients of `normalized = (x - mean) / deviation` for one group of values
+/// sharing a mean and deviation computed from the group.
+fn group_gradients<T: Float>(gradients: &[T], normalized: &[T], inverse_deviation: T) -> Vec<T> {
+    let count = T::of(gradients.len() as f64);
+    let sum: T = gradients.iter().copied().sum();
+    let dot: T = gradients.iter().zip(normalized).map(|(&g, &x)| g * x).sum();
+
+    gradients
+        .iter()
+        .zip(normalized)
+        .map(|(&g, &x)| inverse_deviation / count * (count * g - sum - x * dot))
+        .collect()
+}
+
+fn mean_and_variance<T: Float>(values: impl Iterator<Item = T> + Clone) -> (T, T) {
+    let count = T::of(values.clone().count() as f64);
+    let mean = values.clone().sum::<T>() / count;
+    let variance = values.map(|x| (x - mean) * (x - mean)).sum::<T>() / count;
+
+    (mean, variance)
+}
+
+/// Batch normalization with a learned scale and shift. Normalizes every value
+/// of dense layer outputs, or every channel of convolution outputs, over the
+/// batch while training and with running statistics otherwise.
+pub struct BatchNorm<T: Float = f64> {
+    channels: usize,
+    /// Values per channel, 1 after dense layers.
+    positions: usize,
+    momentum: f64,
+    epsilon: f64,
+    scale: Matrix<T>,
+    shift: Matrix<T>,
+    scale_gradients: Matrix<T>,
+    shift_gradients: Matrix<T>,
+    running_mean: Matrix<T>,
+    running_variance: Matrix<T>,
+    mode: Mode,
+    cache: Option<Normalized<T>>,
+}
+
+#[autometrics]
+impl<T: Float> BatchNorm<T> {
+    /// Normalizes each of `size` values, e.g. after a dense layer.
+    pub fn new(size: usize) -> BatchNorm<T> {
+        BatchNorm::with_channels(size, 1)
+    }
+
+    /// Normalizes each channel of images, e.g. after a `Conv2D`.
+    pub fn spatial(shape: Shape) -> BatchNorm<T> {
+        BatchNorm::with_channels(shape.channels, shape.height * shape.width)
+    }
+
+    fn with_channels(channels: usize, positions: usize) -> BatchNorm<T> {
+        BatchNorm::from_parameters(
+            channels,
+            positions,
+            MOMENTUM,
+            EPSILON,
+            [
+                Matrix::from_vec(channels, 1, vec![T::one(); channels]),
+                Matrix::zeros(channels, 1),
+                Matrix::zeros(channels, 1),
+                Matrix::from_vec(channels, 1, vec![T::one(); channels]),
+            ],
+        )
+    }
+
+    /// Builds the layer from its scale, shift, running mean and running
+    /// variance, one value per channel each.
+    pub fn from_parameters(
+        channels: usize,
+        positions: usize,
+        momentum: f64,
+        epsilon: f64,
+        [scale, shift, running_mean, running_variance]: [Matrix<T>; 4],
+    ) -> BatchNorm<T> {
+        for matrix in [&scale, &shift, &running_mean, &running_variance] {
+            if matrix.shape() != (channels, 1) {
+                panic!("Batch normalization needs one value per channel");
+            }
+        }
+
+        BatchNorm {
+            channels,
+            positions,
+            momentum,
+            epsilon,
+            scale_gradients: Matrix::zeros(channels, 1),
+            shift_gradients: Matrix::zeros(channels, 1),
+            scale,
+            shift,
+            running_mean,
+            running_variance,
+            mode: Mode::default(),
+            cache: None,
+        }
+    }
+
+    pub fn running_mean(&self) -> &Matrix<T> {
+        &self.running_mean
+    }
+
+    pub fn running_variance(&self) -> &Matrix<T> {
+        &self.running_variance
+    }
+
+    /// Input indices of the values of `channel`, with `batch` samples.
+    fn channel(&self, channel: usize, batch: usize) -> std::ops::Range<usize> {
+        channel * self.positions * batch..(channel + 1) * self.positions * batch
+    }
+
+    /// Normalizes with the running statistics, or with those of the batch
+    /// and then updates the running ones.
+    fn normalize(
+        &mut self,
+        inputs: &Matrix<T>,
+        batch_statistics: bool,
+    ) -> Result<(Matrix<T>, Normalized<T>)> {
+        check_inputs(inputs, self.channels * self.positions)?;
+
+        let batch = inputs.cols;
+        let values = inputs.to_vec();
+        let mut normalized = vec![T::zero(); values.len()];
+        let mut outputs = vec![T::zero(); values.len()];
+        let mut inverse_deviations = vec![];
+
+        for channel in 0..self.channels {
+            let range = self.channel(channel, batch);
+            let (mean, variance) = if batch_statistics {
+                let (mean, variance) = mean_and_variance(values[range.clone()].iter().copied());
+                let count = range.len() as f64;
+                let unbiased = variance * T::of(count / (count - 1.0).max(1.0));
+                let momentum = T::of(self.momentum);
+                let keep = T::one() - momentum;
+                self.running_mean[(channel, 0)] =
+                    keep * self.running_mean[(channel, 0)] + momentum * mean;
+                self.running_variance[(channel, 0)] =
+                    keep * self.running_variance[(channel, 0)] + momentum * unbiased;
+                (mean, variance)
+            } else {
+                (
+                    self.running_mean[(channel, 0)],
+                    self.running_variance[(channel, 0)],
+                )
+            };
+
+            let inverse_deviation = T::one() / (variance + T::of(self.epsilon)).sqrt();
+            let (scale, shift) = (self.scale[(channel, 0)], self.shift[(channel, 0)]);
+            for i in range {
+                normalized[i] = (values[i] - mean) * inverse_deviation;
+                outputs[i] = scale * normalized[i] + shift;
+            }
+            inverse_deviations.push(inverse_deviation);
+        }
+
+        Ok((
+            Matrix::from_vec(inputs.rows, batch, outputs),
+            Normalized {
+                values: normalized,
+                inverse_deviations,
+                batch,
+                batch_statistics,
+            },
+        ))
+    }
+
+    fn predict_running(&self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        check_inputs(inputs, self.channels * self.positions)?;
+
+        let batch = inputs.cols;
+        let mut values = inputs.to_vec();
+
+        for channel in 0..self.channels {
+            let inverse_deviation =
+                T::one() / (self.running_variance[(channel, 0)] + T::of(self.epsilon)).sqrt();
+            let (mean, scale, shift) = (
+                self.running_mean[(channel, 0)],
+                self.scale[(channel, 0)],
+                self.shift[(channel, 0)],
+            );
+            for value in &mut values[self.channel(channel, batch)] {
+                *value = scale * (*value - mean) * inverse_deviation + shift;
+            }
+        }
+
+        Ok(Matrix::from_vec(inputs.rows, batch, values))
+    }
+}
+
+impl<T: Float> Layer<T> for BatchNorm<T> {
+    fn forward(&mut self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        let (outputs, normalized) = self.normalize(inputs, self.mode == Mode::Training)?;
+        self.cache = Some(normalized);
+
+        Ok(outputs)
+    }
+
+    fn predict(&self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        self.predict_running(inputs)
+    }
+
+    fn backward(&mut self, gradients: &Matrix<T>) -> Result<Matrix<T>> {
+        let normalized = self.cache.as_ref().ok_or(Error::MissingForwardPass)?;
+        let batch = normalized.batch;
+        check_gradients(gradients, self.channels * self.positions, batch)?;
+
+        let values = gradients.to_vec();
+        let batch_scale = T::of(1.0 / batch as f64);
+        let mut errors = vec![T::zero(); values.len()];
+
+        for channel in 0..self.channels {
+            let range = self.channel(channel, batch);
+            let gradients = &values[range.clone()];
+            let normalized_values = &normalized.values[range.clone()];
+            let scale = self.scale[(channel, 0)];
+
+            self.scale_gradients[(channel, 0)] = gradients
+                .iter()
+                .zip(normalized_values)
+                .map(|(&g, &x)| g * x)
+                .sum::<T>()
+                * batch_scale;
+            self.shift_gradients[(channel, 0)] = gradients.iter().copied().sum::<T>() * batch_scale;
+
+            let scaled: Vec<T> = gradients.iter().map(|&g| g * scale).collect();
+            let inverse_deviation = normalized.inverse_deviations[channel];
+            let channel_errors = if normalized.batch_statistics {
+                group_gradients(&scaled, normalized_values, inverse_deviation)
+            } else {
+                scaled.iter().map(|&g| g * inverse_deviation).collect()
+            };
+            errors[range].copy_from_slice(&channel_errors);
+        }
+
+        Ok(Matrix::from_vec(gradients.rows, batch, errors))
+    }
+
+    fn parameters(&self) -> Vec<&Matrix<T>> {
+        vec![&self.scale, &self.shift]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        vec![&mut self.scale, &mut self.shift]
+    }
+
+    fn gradients(&self) -> Vec<&Matrix<T>> {
+        vec![&self.scale_gradients, &self.shift_gradients]
+    }
+
+    fn input_size(&self) -> usize {
+        self.channels * self.positions
+    }
+
+    fn output_size(&self) -> usize {
+        self.channels * self.positions
+    }
+
+    fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    fn to_data(&self) -> LayerData {
+        LayerData::BatchNorm {
+            channels: self.channels,
+            positions: self.positions,
+            momentum: self.momentum,
+            epsilon: self.epsilon,
+            scale: self.scale.cast(),
+            shift: self.shift.cast(),
+            running_mean: self.running_mean.cast(),
+            running_variance: self.running_variance.cast(),
+        }
+    }
+}
+
+/// Layer normalization with a learned scale and shift per value. Normalizes
+/// every sample over its own values, the same while training and otherwise.
+pub struct LayerNorm<T: Float = f64> {
+    epsilon: f64,
+    scale: Matrix<T>,
+    shift: Matrix<T>,
+    scale_gradients: Matrix<T>,
+    shift_gradients: Matrix<T>,
+    cache: Option<Normalized<T>>,
+}
+
+#[autometrics]
+impl<T: Float> LayerNorm<T> {
+    pub fn new(size: usize) -> LayerNorm<T> {
+        LayerNorm::from_parameters(
+            EPSILON,
+            Matrix::from_vec(size, 1, vec![T::one(); size]),
+            Matrix::zeros(size, 1),
+        )
+    }
+
+    pub fn from_parameters(epsilon: f64, scale: Matrix<T>, shift: Matrix<T>) -> LayerNorm<T> {
+        if scale.cols != 1 || shift.shape() != scale.shape() {
+            panic!("Layer normalization needs one scale and shift per value");
+        }
+
+        LayerNorm {
+            epsilon,
+            scale_gradients: Matrix::zeros(scale.rows, 1),
+            shift_gradients: Matrix::zeros(scale.rows, 1),
+            scale,
+            shift,
+            cache: None,
+        }
+    }
+
+    fn normalize(&self, inputs: &Matrix<T>) -> Result<(Matrix<T>, Normalized<T>)> {
+        check_inputs(inputs, self.scale.rows)?;
+
+        let (size, batch) = inputs.shape();
+        let values = inputs.to_vec();
+        let mut normalized = vec![T::zero(); values.len()];
+        let mut outputs = vec![T::zero(); values.len()];
+        let mut inverse_deviations = vec![];
+
+        for b in 0..batch {
+            let sample = (0..size).map(|i| values[i * batch + b]);
+            let (mean, variance) = mean_and_variance(sample);
+            let inverse_deviation = T::one() / (variance + T::of(self.epsilon)).sqrt();
+
+            for i in 0..size {
+                let index = i * batch + b;
+                normalized[index] = (values[index] - mean) * inverse_deviation;
+                outputs[index] = self.scale[(i, 0)] * normalized[index] + self.shift[(i, 0)];
+            }
+            inverse_deviations.push(inverse_deviation);
+        }
+
+        Ok((
+            Matrix::from_vec(size, batch, outputs),
+            Normalized {
+                values: normalized,
+                inverse_deviations,
+                batch,
+                batch_statistics: true,
+            },
+        ))
+    }
+}
+
+impl<T: Float> Layer<T> for LayerNorm<T> {
+    fn forward(&mut self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        let (outputs, normalized) = self.normalize(inputs)?;
+        self.cache = Some(normalized);
+
+        Ok(outputs)
+    }
+
+    fn predict(&self, inputs: &Matrix<T>) -> Result<Matrix<T>> {
+        Ok(self.normalize(inputs)?.0)
+    }
+
+    fn backward(&mut self, gradients: &Matrix<T>) -> Result<Matrix<T>> {
+        let normalized = self.cache.as_ref().ok_or(Error::MissingForwardPass)?;
+        let (size, batch) = (self.scale.rows, normalized.batch);
+        check_gradients(gradients, size, batch)?;
+
+        let values = gradients.to_vec();
+        let batch_scale = T::of(1.0 / batch as f64);
+        let mut errors = vec![T::zero(); values.len()];
+
+        for i in 0..size {
+            let row = i * batch..(i + 1) * batch;
+            self.scale_gradients[(i, 0)] = values[row.clone()]
+                .iter()
+                .zip(&normalized.values[row.clone()])
+                .map(|(&g, &x)| g * x)
+                .sum::<T>()
+                * batch_scale;
+            self.shift_gradients[(i, 0)] = values[row].iter().copied().sum::<T>() * batch_scale;
+        }
+
+        for b in 0..batch {
+            let indices: Vec<usize> = (0..size).map(|i| i * batch + b).collect();
+            let scaled: Vec<T> = (0..size)
+                .map(|i| values[indices[i]] * self.scale[(i, 0)])
+                .collect();
+            let sample: Vec<T> = indices.iter().map(|&i| normalized.values[i]).collect();
+
+            let sample_errors = group_gradients(&scaled, &sample, normalized.inverse_deviations[b]);
+            for (&index, error) in indices.iter().zip(sample_errors) {
+                errors[index] = error;
+            }
+        }
+
+        Ok(Matrix::from_vec(size, batch, errors))
+    }
+
+    fn parameters(&self) -> Vec<&Matrix<T>> {
+        vec![&self.scale, &self.shift]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Matrix<T>> {
+        vec![&mut self.scale, &mut self.shift]
+    }
+
+    fn gradients(&self) -> Vec<&Matrix<T>> {
+        vec![&self.scale_gradients, &self.shift_gradients]
+    }
+
+    fn input_size(&self) -> usize {
+        self.scale.rows
+    }
+
+    fn output_size(&self) -> usize {
+        self.scale.rows
+    }
+
+    fn to_data(&self) -> LayerData {
+        LayerData::LayerNorm {
+            epsilon: self.epsilon,
+            scale: self.scale.cast(),
+            shift: self.shift.cast(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs() -> Matrix {
+        Matrix::from(vec![
+            vec![0.5, -0.3, 1.2, 0.1],
+            vec![0.1, 0.8, -0.4, 0.6],
+            vec![-0.7, 0.2, 0.3, -1.1],
+        ])
+    }
+
+    /// Checks the input gradients of a layer in training mode against finite
+    /// differences of a loss weighing every output differently.
+    fn assert_input_gradients(layer: &mut dyn Layer) {
+        let inputs = inputs();
+        let weights = Matrix::from_vec(
+            inputs.rows,
+            inputs.cols,
+            (0..inputs.rows * inputs.cols)
+                .map(|i| (i as f64 * 0.37).sin())
+                .collect(),
+        );
+        let loss = |layer: &mut dyn Layer, inputs: &Matrix| -> f64 {
+            layer.forward(inputs).unwrap().dot_multiply(&weights).sum()
+        };
+
+        loss(layer, &inputs);
+        let errors = layer.backward(&weights).unwrap();
+        let h = 1e-6;
+
+        for i in 0..inputs.rows {
+            for j in 0..inputs.cols {
+                let mut plus = inputs.clone();
+                let mut minus = inputs.clone();
+                plus[(i, j)] += h;
+                minus[(i, j)] -= h;
+
+                let numeric = (loss(layer, &plus) - loss(layer, &minus)) / (2.0 * h);
+                assert!((numeric - errors[(i, j)]).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_batch_norm_gradients_match_finite_differences() {
+        let mut batch_norm = BatchNorm::new(3);
+        batch_norm.scale = Matrix::from(vec![vec![1.5], vec![-0.5], vec![2.0]]);
+        batch_norm.set_mode(Mode::Training);
+        assert_input_gradients(&mut batch_norm);
+
+        batch_norm.set_mode(Mode::Evaluation);
+        assert_input_gradients(&mut batch_norm);
+    }
+
+    #[test]
+    fn test_layer_norm_gradients_match_finite_differences() {
+        let mut layer_norm = LayerNorm::new(3);
+        layer_norm.scale = Matrix::from(vec![vec![1.5], vec![-0.5], vec![2.0]]);
+        assert_input_gradients(&mut layer_norm);
+    }
+
+    #[test]
+    fn test_batch_norm_running_statistics() {
+        let mut batch_norm = BatchNorm::spatial(Shape::new(2, 1, 2));
+        let inputs = Matrix::from(vec![
+            vec![1.0, 3.0],
+            vec![1.0, 3.0],
+            vec![-2.0, 2.0],
+            vec![-2.0, 2.0],
+        ]);
+
+        batch_norm.set_mode(Mode::Training);
+        let outputs = batch_norm.forward(&inputs).unwrap();
+
+        // Each channel has zero mean and unit variance over batch and positions
+        for channel in outputs.to_rows().chunks(2) {
+            let values: Vec<f64> = channel.concat();
+            let (mean, variance) = mean_and_variance(values.into_iter());
+            assert!(mean.abs() < 1e-12);
+            assert!((variance - 1.0).abs() < 1e-4);
+        }
+        assert!((batch_norm.running_mean()[(0, 0)] - 0.2).abs() < 1e-12);
+        assert!((batch_norm.running_mean()[(1, 0)]).abs() < 1e-12);
+
+        let data = batch_norm.to_data();
+        let json = serde_json::to_string(&data).unwrap();
+        let restored: LayerData = serde_json::from_str(&json).unwrap();
+        assert!(restored.validate().is_ok());
+        assert_eq!(
+            restored.build::<f64>().predict(&inputs).unwrap().to_vec(),
+            batch_norm.predict(&inputs).unwrap().to_vec()
+        );
+    }
+}
@@ -0,0 +1,513 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{float::Float, matrix::Matrix};
+
+/// Updates parameters from their gradients. Every parameter of a network is
+/// identified by a stable index so optimizers can keep per-parameter state.
+pub trait Optimizer<T: Float = f64>: Send {
+    fn update(&mut self, parameter: usize, values: &Matrix<T>, gradients: &Matrix<T>) -> Matrix<T>;
+
+    fn learning_rate(&self) -> f64;
+
+    fn set_learning_rate(&mut self, learning_rate: f64);
+
+    /// Snapshot of the configuration and the accumulated state, used to save
+    /// and resume training. The state is kept in `f64` whatever the precision
+    /// of the network.
+    fn state(&self) -> OptimizerState;
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum OptimizerState {
+    Sgd(Sgd),
+    Nesterov(Nesterov),
+    RmsProp(RmsProp),
+    Adam(Adam),
+    AdamW(AdamW),
+}
+
+impl OptimizerState {
+    /// The optimizer for a network of precision `T`, with its state converted.
+    pub fn into_optimizer<T: Float>(self) -> Box<dyn Optimizer<T>> {
+        match self {
+            OptimizerState::Sgd(optimizer) => Box::new(optimizer),
+            OptimizerState::Nesterov(optimizer) => Box::new(optimizer.cast()),
+            OptimizerState::RmsProp(optimizer) => Box::new(optimizer.cast()),
+            OptimizerState::Adam(optimizer) => Box::new(optimizer.cast()),
+            OptimizerState::AdamW(optimizer) => Box::new(optimizer.cast()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OptimizerState::Sgd(_) => "SGD",
+            OptimizerState::Nesterov(_) => "Nesterov",
+            OptimizerState::RmsProp(_) => "RMSProp",
+            OptimizerState::Adam(_) => "Adam",
+            OptimizerState::AdamW(_) => "AdamW",
+        }
+    }
+}
+
+/// Optimizer and hyperparameters without any accumulated state, as given in
+/// experiment configs.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum OptimizerConfig {
+    Sgd {
+        learning_rate: f64,
+    },
+    Nesterov {
+        learning_rate: f64,
+        momentum: f64,
+    },
+    RmsProp {
+        learning_rate: f64,
+        decay: f64,
+    },
+    Adam {
+        learning_rate: f64,
+    },
+    AdamW {
+        learning_rate: f64,
+        weight_decay: f64,
+    },
+}
+
+impl OptimizerConfig {
+    pub fn build<T: Float>(self) -> Box<dyn Optimizer<T>> {
+        match self {
+            OptimizerConfig::Sgd { learning_rate } => Box::new(Sgd::new(learning_rate)),
+            OptimizerConfig::Nesterov {
+                learning_rate,
+                momentum,
+            } => Box::new(Nesterov::new(learning_rate, momentum)),
+            OptimizerConfig::RmsProp {
+                learning_rate,
+                decay,
+            } => Box::new(RmsProp::new(learning_rate, decay)),
+            OptimizerConfig::Adam { learning_rate } => Box::new(Adam::new(learning_rate)),
+            OptimizerConfig::AdamW {
+                learning_rate,
+                weight_decay,
+            } => Box::new(AdamW::new(learning_rate, weight_decay)),
+        }
+    }
+
+    pub fn learning_rate(&self) -> f64 {
+        match *self {
+            OptimizerConfig::Sgd { learning_rate }
+            | OptimizerConfig::Nesterov { learning_rate, .. }
+            | OptimizerConfig::RmsProp { learning_rate, .. }
+            | OptimizerConfig::Adam { learning_rate }
+            | OptimizerConfig::AdamW { learning_rate, .. } => learning_rate,
+        }
+    }
+
+    pub fn set_learning_rate(&mut self, value: f64) {
+        match self {
+            OptimizerConfig::Sgd { learning_rate }
+            | OptimizerConfig::Nesterov { learning_rate, .. }
+            | OptimizerConfig::RmsProp { learning_rate, .. }
+            | OptimizerConfig::Adam { learning_rate }
+            | OptimizerConfig::AdamW { learning_rate, .. } => *learning_rate = value,
+        }
+    }
+}
+
+fn zeros_like<T: Float>(matrix: &Matrix<T>) -> Matrix<T> {
+    Matrix::zeros(matrix.rows, matrix.cols)
+}
+
+/// Per-parameter state converted to another precision.
+fn cast_state<T: Float, U: Float>(
+    state: &BTreeMap<usize, Matrix<T>>,
+) -> BTreeMap<usize, Matrix<U>> {
+    state
+        .iter()
+        .map(|(&parameter, matrix)| (parameter, matrix.cast()))
+        .collect()
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Sgd {
+    learning_rate: f64,
+}
+
+impl Sgd {
+    pub fn new(learning_rate: f64) -> Sgd {
+        Sgd { learning_rate }
+    }
+}
+
+impl<T: Float> Optimizer<T> for Sgd {
+    fn update(
+        &mut self,
+        _parameter: usize,
+        values: &Matrix<T>,
+        gradients: &Matrix<T>,
+    ) -> Matrix<T> {
+        let learning_rate = T::of(self.learning_rate);
+        let mut values = values.clone();
+        values.zip_map_inplace(gradients, |w, g| w - learning_rate * g);
+        values
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState::Sgd(self.clone())
+    }
+}
+
+/// SGD with Nesterov momentum.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(bound = "T: Float")]
+pub struct Nesterov<T: Float = f64> {
+    learning_rate: f64,
+    momentum: f64,
+    velocities: BTreeMap<usize, Matrix<T>>,
+}
+
+impl<T: Float> Nesterov<T> {
+    pub fn new(learning_rate: f64, momentum: f64) -> Nesterov<T> {
+        Nesterov {
+            learning_rate,
+            momentum,
+            velocities: BTreeMap::new(),
+        }
+    }
+
+    pub fn cast<U: Float>(&self) -> Nesterov<U> {
+        Nesterov {
+            learning_rate: self.learning_rate,
+            momentum: self.momentum,
+            velocities: cast_state(&self.velocities),
+        }
+    }
+}
+
+impl<T: Float> Optimizer<T> for Nesterov<T> {
+    fn update(&mut self, parameter: usize, values: &Matrix<T>, gradients: &Matrix<T>) -> Matrix<T> {
+        let momentum = T::of(self.momentum);
+        let velocity = self
+            .velocities
+            .entry(parameter)
+            .or_insert_with(|| zeros_like(gradients));
+
+        velocity.zip_map_inplace(gradients, |v, g| momentum * v + g);
+
+        let mut step = gradients.zip_map(velocity, |g, v| g + momentum * v);
+        step.scale_assign(T::of(self.learning_rate));
+
+        values.subtract(&step)
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState::Nesterov(self.cast())
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(bound = "T: Float")]
+pub struct RmsProp<T: Float = f64> {
+    learning_rate: f64,
+    decay: f64,
+    epsilon: f64,
+    mean_squares: BTreeMap<usize, Matrix<T>>,
+}
+
+impl<T: Float> RmsProp<T> {
+    pub fn new(learning_rate: f64, decay: f64) -> RmsProp<T> {
+        RmsProp {
+            learning_rate,
+            decay,
+            epsilon: 1e-8,
+            mean_squares: BTreeMap::new(),
+        }
+    }
+
+    pub fn cast<U: Float>(&self) -> RmsProp<U> {
+        RmsProp {
+            learning_rate: self.learning_rate,
+            decay: self.decay,
+            epsilon: self.epsilon,
+            mean_squares: cast_state(&self.mean_squares),
+        }
+    }
+}
+
+impl<T: Float> Optimizer<T> for RmsProp<T> {
+    fn update(&mut self, parameter: usize, values: &Matrix<T>, gradients: &Matrix<T>) -> Matrix<T> {
+        let (decay, epsilon, learning_rate) = (
+            T::of(self.decay),
+            T::of(self.epsilon),
+            T::of(self.learning_rate),
+        );
+        let mean_square = self
+            .mean_squares
+            .entry(parameter)
+            .or_insert_with(|| zeros_like(gradients));
+
+        mean_square.zip_map_inplace(gradients, |s, g| decay * s + (T::one() - decay) * g * g);
+
+        let step = gradients.zip_map(mean_square, |g, s| learning_rate * g / (s.sqrt() + epsilon));
+
+        values.subtract(&step)
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState::RmsProp(self.cast())
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(bound = "T: Float")]
+pub struct Moments<T: Float = f64> {
+    step: i32,
+    first: Matrix<T>,
+    second: Matrix<T>,
+}
+
+impl<T: Float> Moments<T> {
+    fn new(gradients: &Matrix<T>) -> Moments<T> {
+        Moments {
+            step: 0,
+            first: zeros_like(gradients),
+            second: zeros_like(gradients),
+        }
+    }
+
+    /// Advances the moment estimates and returns the bias corrected Adam step.
+    fn step(&mut self, gradients: &Matrix<T>, beta1: f64, beta2: f64, epsilon: f64) -> Matrix<T> {
+        self.step += 1;
+        let first_correction = T::of(1.0 - beta1.powi(self.step));
+        let second_correction = T::of(1.0 - beta2.powi(self.step));
+        let (beta1, beta2, epsilon) = (T::of(beta1), T::of(beta2), T::of(epsilon));
+        let one = T::one();
+
+        self.first
+            .zip_map_inplace(gradients, |m, g| beta1 * m + (one - beta1) * g);
+        self.second
+            .zip_map_inplace(gradients, |v, g| beta2 * v + (one - beta2) * g * g);
+
+        self.first.zip_map(&self.second, |m, v| {
+            (m / first_correction) / ((v / second_correction).sqrt() + epsilon)
+        })
+    }
+}
+
+fn cast_moments<T: Float, U: Float>(
+    moments: &BTreeMap<usize, Moments<T>>,
+) -> BTreeMap<usize, Moments<U>> {
+    moments
+        .iter()
+        .map(|(&parameter, moments)| {
+            let moments = Moments {
+                step: moments.step,
+                first: moments.first.cast(),
+                second: moments.second.cast(),
+            };
+            (parameter, moments)
+        })
+        .collect()
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(bound = "T: Float")]
+pub struct Adam<T: Float = f64> {
+    learning_rate: f64,
+    beta1: f64,
+    beta2: f64,
+    epsilon: f64,
+    moments: BTreeMap<usize, Moments<T>>,
+}
+
+impl<T: Float> Adam<T> {
+    pub fn new(learning_rate: f64) -> Adam<T> {
+        Adam {
+            learning_rate,
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+            moments: BTreeMap::new(),
+        }
+    }
+
+    pub fn cast<U: Float>(&self) -> Adam<U> {
+        Adam {
+            learning_rate: self.learning_rate,
+            beta1: self.beta1,
+            beta2: self.beta2,
+            epsilon: self.epsilon,
+            moments: cast_moments(&self.moments),
+        }
+    }
+}
+
+impl<T: Float> Optimizer<T> for Adam<T> {
+    fn update(&mut self, parameter: usize, values: &Matrix<T>, gradients: &Matrix<T>) -> Matrix<T> {
+        let mut step = self
+            .moments
+            .entry(parameter)
+            .or_insert_with(|| Moments::new(gradients))
+            .step(gradients, self.beta1, self.beta2, self.epsilon);
+        step.scale_assign(T::of(self.learning_rate));
+
+        values.subtract(&step)
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState::Adam(self.cast())
+    }
+}
+
+/// Adam with weight decay decoupled from the gradient.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(bound = "T: Float")]
+pub struct AdamW<T: Float = f64> {
+    learning_rate: f64,
+    beta1: f64,
+    beta2: f64,
+    epsilon: f64,
+    weight_decay: f64,
+    moments: BTreeMap<usize, Moments<T>>,
+}
+
+impl<T: Float> AdamW<T> {
+    pub fn new(learning_rate: f64, weight_decay: f64) -> AdamW<T> {
+        AdamW {
+            learning_rate,
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+            weight_decay,
+            moments: BTreeMap::new(),
+        }
+    }
+
+    pub fn cast<U: Float>(&self) -> AdamW<U> {
+        AdamW {
+            learning_rate: self.learning_rate,
+            beta1: self.beta1,
+            beta2: self.beta2,
+            epsilon: self.epsilon,
+            weight_decay: self.weight_decay,
+            moments: cast_moments(&self.moments),
+        }
+    }
+}
+
+impl<T: Float> Optimizer<T> for AdamW<T> {
+    fn update(&mut self, parameter: usize, values: &Matrix<T>, gradients: &Matrix<T>) -> Matrix<T> {
+        let mut step = self
+            .moments
+            .entry(parameter)
+            .or_insert_with(|| Moments::new(gradients))
+            .step(gradients, self.beta1, self.beta2, self.epsilon);
+
+        let weight_decay = T::of(self.weight_decay);
+        step.zip_map_inplace(values, |s, w| s + weight_decay * w);
+        step.scale_assign(T::of(self.learning_rate));
+
+        values.subtract(&step)
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState::AdamW(self.cast())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minimize(optimizer: &mut dyn Optimizer) -> f64 {
+        // Minimize f(x) = x^2 starting at x = 1
+        let mut x = Matrix::from(vec![vec![1.0]]);
+        for _ in 0..500 {
+            let gradients = x.scale(2.0);
+            x = optimizer.update(0, &x, &gradients);
+        }
+        x[(0, 0)]
+    }
+
+    #[test]
+    fn test_sgd_update() {
+        let mut optimizer = Sgd::new(0.1);
+        let values = Matrix::from(vec![vec![1.0, 2.0]]);
+        let gradients = Matrix::from(vec![vec![1.0, -1.0]]);
+        let result = optimizer.update(0, &values, &gradients);
+        assert_eq!(result.to_rows(), vec![vec![0.9, 2.1]]);
+    }
+
+    #[test]
+    fn test_optimizers_converge() {
+        let mut optimizers: Vec<Box<dyn Optimizer>> = vec![
+            Box::new(Sgd::new(0.1)),
+            Box::new(Nesterov::new(0.05, 0.9)),
+            Box::new(RmsProp::new(0.01, 0.9)),
+            Box::new(Adam::new(0.05)),
+            Box::new(AdamW::new(0.05, 0.01)),
+        ];
+
+        for optimizer in optimizers.iter_mut() {
+            assert!(minimize(optimizer.as_mut()).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn test_state_round_trip_resumes_exactly() {
+        let mut original = Adam::new(0.01);
+        let values = Matrix::from(vec![vec![1.0, -1.0]]);
+        let gradients = Matrix::from(vec![vec![0.5, 0.25]]);
+        let values = original.update(3, &values, &gradients);
+
+        let json = serde_json::to_string(&original.state()).unwrap();
+        let state: OptimizerState = serde_json::from_str(&json).unwrap();
+        let mut restored = state.into_optimizer();
+
+        let expected = original.update(3, &values, &gradients);
+        let actual = restored.update(3, &values, &gradients);
+        assert_eq!(expected.to_rows(), actual.to_rows());
+    }
+}
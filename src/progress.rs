@@ -0,0 +1,49 @@
+use spinners::{Spinner, Spinners};
+
+pub struct Progress {
+    total: usize,
+    done: usize,
+    last_progress_pct: usize,
+    spinner: Spinner,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Progress {
+        Progress {
+            total,
+            done: 0,
+            last_progress_pct: 0,
+            spinner: Spinner::new(Spinners::Dots9, Progress::message(total, 0, None)),
+        }
+    }
+
+    fn message(total: usize, progress_pct: usize, loss: Option<f64>) -> String {
+        match loss {
+            Some(loss) => format!(
+                "Training with {} images... [{}%] loss: {:.5}",
+                total, progress_pct, loss
+            ),
+            None => format!("Training with {} images... [{}%]", total, progress_pct),
+        }
+    }
+
+    /// Marks `count` more samples as done, `loss` being the loss of the batch
+    /// they were trained in.
+    pub fn advance(&mut self, count: usize, loss: f64) {
+        self.done += count;
+        let progress_pct = 100 * self.done / self.total.max(1);
+
+        if self.last_progress_pct != progress_pct {
+            self.last_progress_pct = progress_pct;
+            self.spinner.stop();
+            self.spinner = Spinner::new(
+                Spinners::Dots9,
+                Progress::message(self.total, progress_pct, Some(loss)),
+            );
+        }
+    }
+
+    pub fn finish(mut self) {
+        self.spinner.stop_with_message("Training done!".into());
+    }
+}
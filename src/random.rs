@@ -0,0 +1,85 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+/// Random number generator of a run. Reproducible on every platform, unlike
+/// `StdRng`, whose algorithm may change between `rand` versions.
+pub type RunRng = ChaCha8Rng;
+
+/// Parts of a run drawing random numbers. Each gets its own stream of the
+/// seed, so drawing more numbers in one does not change the others.
+#[derive(Clone, Copy, Debug)]
+pub enum Stream {
+    Initialization = 0,
+    Shuffling = 1,
+}
+
+/// Generator for `stream` of `seed`, or of a random seed when `None`.
+pub fn rng(seed: Option<u64>, stream: Stream) -> RunRng {
+    let mut rng = match seed {
+        Some(seed) => RunRng::seed_from_u64(seed),
+        None => RunRng::from_entropy(),
+    };
+    rng.set_stream(stream as u64);
+    rng
+}
+
+/// Like `rng`, for a part of the run after `offset` of its units, e.g. the
+/// epochs a preloaded model was already trained for, so each round of a
+/// session draws other numbers. The same as `rng` for an offset of 0.
+pub fn rng_after(seed: Option<u64>, stream: Stream, offset: u64) -> RunRng {
+    let mut rng = rng(seed, stream);
+    rng.set_stream((offset << 8) | stream as u64);
+    rng
+}
+
+/// State of a `RunRng`, which continues exactly where it was once restored.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RngState {
+    seed: [u8; 32],
+    stream: u64,
+    word_position: u64,
+}
+
+impl From<&RunRng> for RngState {
+    fn from(rng: &RunRng) -> RngState {
+        RngState {
+            seed: rng.get_seed(),
+            stream: rng.get_stream(),
+            word_position: rng.get_word_pos() as u64,
+        }
+    }
+}
+
+impl RngState {
+    pub fn rng(&self) -> RunRng {
+        let mut rng = RunRng::from_seed(self.seed);
+        rng.set_stream(self.stream);
+        rng.set_word_pos(self.word_position as u128);
+        rng
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    #[test]
+    fn test_streams_are_reproducible_and_independent() {
+        let draw = |stream| rng(Some(7), stream).gen::<u64>();
+
+        assert_eq!(draw(Stream::Initialization), draw(Stream::Initialization));
+        assert_ne!(draw(Stream::Initialization), draw(Stream::Shuffling));
+    }
+
+    #[test]
+    fn test_offsets_draw_other_numbers() {
+        let draw = |offset| rng_after(Some(7), Stream::Shuffling, offset).gen::<u64>();
+
+        assert_eq!(draw(0), rng(Some(7), Stream::Shuffling).gen::<u64>());
+        assert_ne!(draw(0), draw(3));
+        assert_eq!(draw(3), draw(3));
+    }
+}
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+use super::{float::Float, matrix::Matrix};
+
+/// L1 and L2 penalties on the weights of a layer, never on biases. The
+/// penalty `l1 * sum(|w|) + l2 / 2 * sum(w^2)` is added to the loss.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Regularization {
+    pub l1: f64,
+    pub l2: f64,
+}
+
+impl Regularization {
+    pub fn new(l1: f64, l2: f64) -> Regularization {
+        Regularization { l1, l2 }
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.l1 == 0.0 && self.l2 == 0.0
+    }
+
+    pub fn penalty<T: Float>(&self, weights: &Matrix<T>) -> f64 {
+        if self.is_none() {
+            return 0.0;
+        }
+
+        let (absolute, square) = weights
+            .to_vec()
+            .into_iter()
+            .map(T::as_f64)
+            .fold((0.0, 0.0), |(a, s), w| (a + w.abs(), s + w * w));
+
+        self.l1 * absolute + self.l2 / 2.0 * square
+    }
+
+    /// Adds the gradient of the penalty to the loss `gradients` of `weights`.
+    pub fn add_gradient<T: Float>(&self, weights: &Matrix<T>, gradients: &mut Matrix<T>) {
+        if self.is_none() {
+            return;
+        }
+
+        let (l1, l2) = (T::of(self.l1), T::of(self.l2));
+        gradients.zip_map_inplace(weights, |g, w| {
+            let sign = if w > T::zero() {
+                T::one()
+            } else if w < T::zero() {
+                -T::one()
+            } else {
+                T::zero()
+            };
+            g + l1 * sign + l2 * w
+        });
+    }
+}
+
+/// Bounds on the gradients handed to the optimizer. Values are clamped to
+/// `[-value, value]` first, then all gradients of a network are scaled down
+/// together when their global L2 norm exceeds `norm`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GradientClipping {
+    pub value: Option<f64>,
+    pub norm: Option<f64>,
+}
+
+impl GradientClipping {
+    pub fn clip<T: Float>(&self, gradients: &mut [Matrix<T>]) {
+        if let Some(value) = self.value {
+            let value = T::of(value);
+            for matrix in gradients.iter_mut() {
+                matrix.map_inplace(|g| g.max(-value).min(value));
+            }
+        }
+
+        if let Some(norm) = self.norm {
+            let total = global_norm(gradients);
+            if total > norm {
+                let factor = T::of(norm / total);
+                for matrix in gradients.iter_mut() {
+                    matrix.scale_assign(factor);
+                }
+            }
+        }
+    }
+}
+
+/// L2 norm of all `gradients` taken together.
+pub fn global_norm<T: Float>(gradients: &[Matrix<T>]) -> f64 {
+    gradients
+        .iter()
+        .flat_map(|matrix| matrix.to_vec())
+        .map(|g| g.as_f64() * g.as_f64())
+        .sum::<f64>()
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_penalty_and_gradient() {
+        let weights = Matrix::from(vec![vec![1.0, -2.0], vec![0.0, 3.0]]);
+        let regularization = Regularization::new(0.1, 0.01);
+
+        assert!((regularization.penalty(&weights) - (0.1 * 6.0 + 0.005 * 14.0)).abs() < 1e-12);
+
+        let mut gradients = Matrix::zeros(2, 2);
+        regularization.add_gradient(&weights, &mut gradients);
+        assert_eq!(
+            gradients.to_rows(),
+            vec![vec![0.1 + 0.01, -0.1 - 0.02], vec![0.0, 0.1 + 0.03]]
+        );
+    }
+
+    #[test]
+    fn test_clipping() {
+        let mut gradients = vec![
+            Matrix::from(vec![vec![3.0, -10.0]]),
+            Matrix::from(vec![vec![4.0]]),
+        ];
+
+        GradientClipping {
+            value: Some(4.0),
+            norm: None,
+        }
+        .clip(&mut gradients);
+        assert_eq!(gradients[0].to_rows(), vec![vec![3.0, -4.0]]);
+
+        GradientClipping {
+            value: None,
+            norm: Some(1.0),
+        }
+        .clip(&mut gradients);
+        assert!((global_norm(&gradients) - 1.0).abs() < 1e-12);
+        assert!((gradients[1][(0, 0)] - 4.0 / 41f64.sqrt()).abs() < 1e-12);
+    }
+}
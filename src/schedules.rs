@@ -0,0 +1,294 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+/// How the learning rate changes from epoch to epoch, relative to the base
+/// learning rate of the optimizer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Schedule {
+    #[default]
+    Constant,
+    /// Multiplies by `gamma` every `step_size` epochs.
+    Step { step_size: usize, gamma: f64 },
+    /// Multiplies by `gamma` every epoch.
+    Exponential { gamma: f64 },
+    /// Cosine annealing from the base rate down to `minimum` over `period`
+    /// epochs, then restarting with the period multiplied by `multiplier`.
+    CosineWarmRestarts {
+        period: usize,
+        multiplier: usize,
+        minimum: f64,
+    },
+    /// Rises from the base rate to `maximum` over the first `peak` share of
+    /// `epochs`, then anneals down to `minimum`.
+    OneCycle {
+        maximum: f64,
+        epochs: usize,
+        peak: f64,
+        minimum: f64,
+    },
+    /// Multiplies by `factor` when the validation metric has not improved by
+    /// more than `min_delta` for `patience` epochs, down to `minimum`.
+    ReduceOnPlateau {
+        factor: f64,
+        patience: usize,
+        min_delta: f64,
+        minimum: f64,
+    },
+}
+
+/// Interpolates from `start` at `progress` 0 to `end` at 1 along half a cosine.
+fn cosine(start: f64, end: f64, progress: f64) -> f64 {
+    end + (start - end) * (1.0 + (PI * progress.clamp(0.0, 1.0)).cos()) / 2.0
+}
+
+impl Schedule {
+    /// Checks the hyperparameters, returning what is wrong.
+    pub fn validate(&self) -> Result<(), String> {
+        let positive = |value: f64| value.is_finite() && value > 0.0;
+        let valid = match *self {
+            Schedule::Constant => true,
+            Schedule::Step { step_size, gamma } => step_size > 0 && positive(gamma),
+            Schedule::Exponential { gamma } => positive(gamma),
+            Schedule::CosineWarmRestarts {
+                period,
+                multiplier,
+                minimum,
+            } => period > 0 && multiplier > 0 && minimum >= 0.0,
+            Schedule::OneCycle {
+                maximum,
+                epochs,
+                peak,
+                minimum,
+            } => positive(maximum) && epochs > 0 && (0.0..=1.0).contains(&peak) && minimum >= 0.0,
+            Schedule::ReduceOnPlateau {
+                factor,
+                min_delta,
+                minimum,
+                ..
+            } => factor > 0.0 && factor < 1.0 && min_delta >= 0.0 && minimum >= 0.0,
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("invalid learning rate schedule {:?}", self))
+        }
+    }
+}
+
+/// A `Schedule` applied to a base learning rate, with an optional linear
+/// warmup and the state the plateau schedule keeps between epochs.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LearningRateSchedule {
+    pub schedule: Schedule,
+    pub base: f64,
+    /// Epochs rising linearly up to the scheduled rate.
+    pub warmup_epochs: usize,
+    /// Best validation metric so far, epochs since it improved and the
+    /// factor the plateau schedule reduced the rate to.
+    best: Option<f64>,
+    stale_epochs: usize,
+    factor: f64,
+}
+
+impl LearningRateSchedule {
+    pub fn new(schedule: Schedule, base: f64, warmup_epochs: usize) -> LearningRateSchedule {
+        LearningRateSchedule {
+            schedule,
+            base,
+            warmup_epochs,
+            best: None,
+            stale_epochs: 0,
+            factor: 1.0,
+        }
+    }
+
+    /// Learning rate of `epoch`, counted from 0.
+    pub fn learning_rate(&self, epoch: usize) -> f64 {
+        let base = self.base;
+        let rate = match self.schedule {
+            Schedule::Constant => base,
+            Schedule::Step { step_size, gamma } => base * gamma.powi((epoch / step_size) as i32),
+            Schedule::Exponential { gamma } => base * gamma.powi(epoch as i32),
+            Schedule::CosineWarmRestarts {
+                period,
+                multiplier,
+                minimum,
+            } => {
+                let (mut position, mut period) = (epoch, period);
+                while position >= period {
+                    position -= period;
+                    period *= multiplier;
+                }
+                cosine(base, minimum, position as f64 / period as f64)
+            }
+            Schedule::OneCycle {
+                maximum,
+                epochs,
+                peak,
+                minimum,
+            } => {
+                let rising = (epochs as f64 * peak).round();
+                let epoch = epoch as f64;
+                if epoch < rising {
+                    cosine(base, maximum, epoch / rising)
+                } else {
+                    let falling = (epochs as f64 - rising - 1.0).max(1.0);
+                    cosine(maximum, minimum, (epoch - rising) / falling)
+                }
+            }
+            Schedule::ReduceOnPlateau { minimum, .. } => (base * self.factor).max(minimum),
+        };
+
+        if epoch < self.warmup_epochs {
+            rate * (epoch + 1) as f64 / (self.warmup_epochs + 1) as f64
+        } else {
+            rate
+        }
+    }
+
+    /// Records the validation metric of an epoch, an accuracy when
+    /// `higher_is_better` and an error otherwise. Only the plateau schedule
+    /// uses it.
+    pub fn observe(&mut self, metric: f64, higher_is_better: bool) {
+        let Schedule::ReduceOnPlateau {
+            factor,
+            patience,
+            min_delta,
+            ..
+        } = self.schedule
+        else {
+            return;
+        };
+
+        let improvement = match self.best {
+            None => f64::INFINITY,
+            Some(best) if higher_is_better => metric - best,
+            Some(best) => best - metric,
+        };
+
+        if improvement > min_delta {
+            self.best = Some(metric);
+            self.stale_epochs = 0;
+        } else {
+            self.stale_epochs += 1;
+            if self.stale_epochs > patience {
+                self.factor *= factor;
+                self.stale_epochs = 0;
+                log::info!("Validation plateaued, reducing the learning rate");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rates(schedule: Schedule, warmup_epochs: usize, epochs: usize) -> Vec<f64> {
+        let schedule = LearningRateSchedule::new(schedule, 0.1, warmup_epochs);
+        (0..epochs)
+            .map(|epoch| schedule.learning_rate(epoch))
+            .collect()
+    }
+
+    fn assert_close(found: &[f64], expected: &[f64]) {
+        assert_eq!(found.len(), expected.len());
+        for (x, y) in found.iter().zip(expected) {
+            assert!((x - y).abs() < 1e-12, "{:?} != {:?}", found, expected);
+        }
+    }
+
+    #[test]
+    fn test_decay_schedules() {
+        let step = Schedule::Step {
+            step_size: 2,
+            gamma: 0.5,
+        };
+        assert_close(&rates(step, 0, 5), &[0.1, 0.1, 0.05, 0.05, 0.025]);
+
+        let exponential = Schedule::Exponential { gamma: 0.5 };
+        assert_close(&rates(exponential, 0, 3), &[0.1, 0.05, 0.025]);
+
+        // A warmup scales the first epochs up to the scheduled rate
+        assert_close(
+            &rates(Schedule::Constant, 3, 5),
+            &[0.025, 0.05, 0.075, 0.1, 0.1],
+        );
+    }
+
+    #[test]
+    fn test_cosine_warm_restarts() {
+        let schedule = Schedule::CosineWarmRestarts {
+            period: 2,
+            multiplier: 2,
+            minimum: 0.0,
+        };
+
+        // Periods of 2 and then 4 epochs, restarting at the base rate
+        assert_close(
+            &rates(schedule, 0, 7),
+            &[
+                0.1,
+                0.05,
+                0.1,
+                0.1 * (1.0 + (PI / 4.0).cos()) / 2.0,
+                0.05,
+                0.1 * (1.0 + (3.0 * PI / 4.0).cos()) / 2.0,
+                0.1,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_one_cycle() {
+        let schedule = Schedule::OneCycle {
+            maximum: 1.0,
+            epochs: 5,
+            peak: 0.4,
+            minimum: 0.0,
+        };
+        let rates = rates(schedule, 0, 5);
+
+        assert_close(&rates[..3], &[0.1, 0.55, 1.0]);
+        assert!(rates[3] < 1.0 && rates[3] > 0.0);
+        assert!(rates[4].abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_reduce_on_plateau() {
+        let mut schedule = LearningRateSchedule::new(
+            Schedule::ReduceOnPlateau {
+                factor: 0.5,
+                patience: 1,
+                min_delta: 0.1,
+                minimum: 0.03,
+            },
+            0.1,
+            0,
+        );
+
+        // Errors, so lower is better; 0.95 is not enough of an improvement
+        for (metric, expected) in [
+            (1.0, 0.1),
+            (0.95, 0.1),
+            (0.95, 0.05),
+            (0.5, 0.05),
+            (0.5, 0.05),
+            (0.5, 0.03),
+        ] {
+            schedule.observe(metric, false);
+            assert!((schedule.learning_rate(0) - expected).abs() < 1e-12);
+        }
+
+        assert!(Schedule::ReduceOnPlateau {
+            factor: 1.5,
+            patience: 1,
+            min_delta: 0.0,
+            minimum: 0.0
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    early_stopping::Metric,
+    error::{Error, Result},
+    model_file::ModelFile,
+};
+
+/// Failed rounds in a row that end a session.
+const MAX_FAILED_ROUNDS: usize = 3;
+
+/// Which of the models saved by a session to keep.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Retention {
+    /// The given number with the best validation metric.
+    Best(usize),
+    /// The given number saved last.
+    Last(usize),
+}
+
+impl Retention {
+    pub fn count(&self) -> usize {
+        match *self {
+            Retention::Best(count) | Retention::Last(count) => count,
+        }
+    }
+}
+
+/// Bounds of a training session, the rounds of which each continue from the
+/// model saved by the previous one.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Unbounded when `None`.
+    pub rounds: Option<usize>,
+    /// Validation accuracy, or reconstruction error for an autoencoder, that
+    /// ends the session once reached.
+    pub target: Option<f64>,
+    /// No round starts after this many minutes.
+    pub max_minutes: Option<f64>,
+    /// Keeps every saved model when `None`.
+    pub retention: Option<Retention>,
+}
+
+/// A model saved by a round, scored by its validation metric.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SavedModel {
+    pub path: PathBuf,
+    pub score: Option<f64>,
+}
+
+/// Tracks the rounds of a session, deciding when it ends and pruning the
+/// models it saved.
+pub struct Session {
+    config: SessionConfig,
+    metric: Metric,
+    started: Instant,
+    rounds: usize,
+    failed_rounds: usize,
+    models: Vec<SavedModel>,
+}
+
+impl Session {
+    pub fn new(config: SessionConfig, metric: Metric) -> Session {
+        Session {
+            config,
+            metric,
+            started: Instant::now(),
+            rounds: 0,
+            failed_rounds: 0,
+            models: vec![],
+        }
+    }
+
+    /// Records a round that saved `path`, pruning the older models. Returns
+    /// whether another round should run.
+    pub fn finish_round(&mut self, path: PathBuf) -> Result<bool> {
+        let score = self.metric.value(&ModelFile::read(&path)?.metadata);
+        self.models.push(SavedModel { path, score });
+        self.rounds += 1;
+        self.failed_rounds = 0;
+
+        // The latest model is kept for the next round to continue from
+        self.prune(true)?;
+
+        if let (Some(target), Some(score)) = (self.config.target, score) {
+            let reached = if self.metric.higher_is_better() {
+                score >= target
+            } else {
+                score <= target
+            };
+            if reached {
+                log::info!(
+                    "{:?} of {} reached the target of {}",
+                    self.metric,
+                    score,
+                    target
+                );
+                return Ok(false);
+            }
+        }
+
+        Ok(self.has_time_left())
+    }
+
+    /// Records a round that failed. Returns whether another round should run.
+    pub fn fail_round(&mut self) -> bool {
+        self.rounds += 1;
+        self.failed_rounds += 1;
+
+        if self.failed_rounds >= MAX_FAILED_ROUNDS {
+            log::info!(
+                "{} rounds failed in a row, starting no more rounds",
+                self.failed_rounds
+            );
+            return false;
+        }
+
+        self.has_time_left()
+    }
+
+    fn has_time_left(&self) -> bool {
+        if self
+            .config
+            .rounds
+            .is_some_and(|rounds| self.rounds >= rounds)
+        {
+            log::info!("Finished all {} rounds", self.rounds);
+            return false;
+        }
+
+        let elapsed = self.started.elapsed();
+        if let Some(minutes) = self.config.max_minutes {
+            if elapsed >= Duration::from_secs_f64(minutes * 60.0) {
+                log::info!("Session ran for {:.2?}, starting no more rounds", elapsed);
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Removes the saved models, and their configs, the retention policy does
+    /// not keep, sparing the latest one when `keep_latest`.
+    pub fn prune(&mut self, keep_latest: bool) -> Result<()> {
+        let Some(retention) = self.config.retention else {
+            return Ok(());
+        };
+
+        let mut ranked: Vec<usize> = (0..self.models.len()).rev().collect();
+        if let Retention::Best(_) = retention {
+            let higher_is_better = self.metric.higher_is_better();
+            // Models without a score rank last, ties go to the newer one
+            ranked.sort_by(|&a, &b| {
+                let (a, b) = (self.models[a].score, self.models[b].score);
+                let order = match (a, b) {
+                    (Some(a), Some(b)) => a.total_cmp(&b),
+                    _ => a.is_some().cmp(&b.is_some()),
+                };
+                if higher_is_better || a.is_none() || b.is_none() {
+                    order.reverse()
+                } else {
+                    order
+                }
+            });
+        }
+
+        let latest = self.models.len().saturating_sub(1);
+        let mut kept = vec![false; self.models.len()];
+        for &index in ranked.iter().take(retention.count()) {
+            kept[index] = true;
+        }
+        if keep_latest && !self.models.is_empty() {
+            kept[latest] = true;
+        }
+
+        let mut models = vec![];
+        for (model, kept) in self.models.drain(..).zip(kept) {
+            if kept {
+                models.push(model);
+            } else {
+                log::info!("Removing model {}", model.path.display());
+                remove_model(&model.path)?;
+            }
+        }
+        self.models = models;
+
+        Ok(())
+    }
+
+    /// The models kept so far, oldest first.
+    pub fn models(&self) -> &[SavedModel] {
+        &self.models
+    }
+}
+
+/// Removes a model file and the config saved next to it.
+fn remove_model(path: &Path) -> Result<()> {
+    let mut config_path = path.as_os_str().to_owned();
+    config_path.push(".config.toml");
+
+    for path in [path.to_path_buf(), PathBuf::from(config_path)] {
+        match fs::remove_file(&path) {
+            Err(error) if error.kind() != ErrorKind::NotFound => {
+                return Err(Error::Io {
+                    path,
+                    source: error,
+                })
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::activations::SIGMOID;
+    use crate::losses::MeanSquaredError;
+    use crate::model_file::{ModelData, TrainingMetadata};
+    use crate::network::Network;
+    use crate::optimizers::Sgd;
+
+    use super::*;
+
+    /// Saves a classifier with the given validation accuracy.
+    fn save(directory: &Path, name: &str, accuracy: f64) -> PathBuf {
+        let network = Network::<f64>::new(
+            vec![2, 2],
+            Box::new(Sgd::new(0.1)),
+            vec![SIGMOID],
+            Box::new(MeanSquaredError),
+        );
+        let metadata = TrainingMetadata {
+            accuracy: Some(accuracy),
+            ..TrainingMetadata::default()
+        };
+
+        let path = directory.join(format!("{}.json", name));
+        ModelFile::new(ModelData::Network(network.to_data()), metadata)
+            .write(&path)
+            .unwrap();
+        path
+    }
+
+    fn session(directory: &str, config: SessionConfig) -> (PathBuf, Session) {
+        let directory = std::env::temp_dir().join(directory);
+        fs::create_dir_all(&directory).unwrap();
+        (directory, Session::new(config, Metric::Accuracy))
+    }
+
+    #[test]
+    fn test_keeps_best_models_and_the_latest() {
+        let (directory, mut session) = session(
+            "autoencoder-session-best",
+            SessionConfig {
+                retention: Some(Retention::Best(2)),
+                ..SessionConfig::default()
+            },
+        );
+
+        for (name, accuracy) in [
+            ("a", 90.0),
+            ("b", 95.0),
+            ("c", 80.0),
+            ("d", 93.0),
+            ("e", 70.0),
+        ] {
+            assert!(session
+                .finish_round(save(&directory, name, accuracy))
+                .unwrap());
+        }
+
+        let kept = |session: &Session| -> Vec<PathBuf> {
+            session
+                .models()
+                .iter()
+                .map(|model| model.path.clone())
+                .collect()
+        };
+        assert_eq!(
+            kept(&session),
+            [
+                directory.join("b.json"),
+                directory.join("d.json"),
+                directory.join("e.json")
+            ]
+        );
+        assert!(!directory.join("a.json").exists());
+
+        session.prune(false).unwrap();
+        assert_eq!(
+            kept(&session),
+            [directory.join("b.json"), directory.join("d.json")]
+        );
+        assert!(!directory.join("e.json").exists());
+    }
+
+    #[test]
+    fn test_stops_after_rounds_or_target() {
+        let (directory, mut session) = session(
+            "autoencoder-session-stop",
+            SessionConfig {
+                rounds: Some(3),
+                target: Some(99.0),
+                retention: Some(Retention::Last(1)),
+                ..SessionConfig::default()
+            },
+        );
+
+        assert!(session.finish_round(save(&directory, "a", 90.0)).unwrap());
+        assert!(!session.finish_round(save(&directory, "b", 99.5)).unwrap());
+        assert_eq!(session.models().len(), 1);
+
+        assert!(!session.fail_round());
+    }
+
+    #[test]
+    fn test_stops_after_failed_rounds_in_a_row() {
+        let (directory, mut session) =
+            session("autoencoder-session-failed", SessionConfig::default());
+
+        assert!(session.fail_round());
+        assert!(session.fail_round());
+        assert!(session.finish_round(save(&directory, "a", 90.0)).unwrap());
+
+        assert!(session.fail_round());
+        assert!(session.fail_round());
+        assert!(!session.fail_round());
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use tokio::sync::Notify;
+
+/// Shared request to stop, checked by training after every batch and awaited
+/// by the metrics server.
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+impl Shutdown {
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Completes once a shutdown is requested.
+    pub async fn requested(&self) {
+        let notified = self.notify.notified();
+        if self.is_requested() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Requests `shutdown` on the first SIGINT or SIGTERM and exits the process
+/// on the second.
+pub async fn handle_signals(shutdown: Shutdown) {
+    signal().await;
+    log::warn!("Stopping after the current batch, signal again to exit immediately");
+    shutdown.request();
+
+    signal().await;
+    log::warn!("Exiting without saving");
+    log::logger().flush();
+    std::process::exit(130);
+}
+
+#[cfg(unix)]
+async fn signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(error) => {
+            log::error!("Unable to listen for SIGTERM: {}", error);
+            return ctrl_c().await;
+        }
+    };
+
+    tokio::select! {
+        _ = ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() {
+    ctrl_c().await
+}
+
+async fn ctrl_c() {
+    if let Err(error) = tokio::signal::ctrl_c().await {
+        log::error!("Unable to listen for Ctrl-C: {}", error);
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requested_completes_once_requested() {
+        let shutdown = Shutdown::default();
+        assert!(!shutdown.is_requested());
+
+        let waiting = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.requested().await }
+        });
+        shutdown.request();
+
+        waiting.await.unwrap();
+        assert!(shutdown.is_requested());
+        // Also once it has already been requested
+        shutdown.requested().await;
+    }
+}
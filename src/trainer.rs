@@ -0,0 +1,762 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use autometrics::autometrics;
+use chrono::Local;
+
+use super::{
+    autoencoder::Autoencoder,
+    checkpoint::{self, Checkpoint, Checkpointing, Position, CHECKPOINT_DIRECTORY},
+    config::ExperimentConfig,
+    data_set::{flatten_images, DataSet},
+    early_stopping::{BestEpoch, BestState, EarlyStopping, Metric},
+    error::{Error, Result},
+    float::Float,
+    metrics_logger,
+    model_file::{ModelData, ModelFile, ModelFileError, TrainingMetadata},
+    network::Network,
+    random::{self, RngState, RunRng, Stream},
+    schedules::{LearningRateSchedule, Schedule},
+    shutdown::Shutdown,
+};
+
+/// Runs the training epochs of a model, tests it on the test set and saves it.
+#[derive(Clone)]
+pub struct Trainer {
+    pub epochs: usize,
+    pub batch_size: usize,
+    /// Directory the trained models are saved to, created when missing.
+    pub output_directory: PathBuf,
+    /// Extension, and so format, of the saved models, e.g. `bin.zst` or `json`.
+    pub model_extension: String,
+    /// Seeds the shuffling of the training data, random when `None`. Use the
+    /// same seed for the initialization to repeat a run exactly.
+    pub seed: Option<u64>,
+    /// Config of the run, written next to every saved model as
+    /// `<model file name>.config.toml`.
+    pub config: Option<ExperimentConfig>,
+    pub schedule: Schedule,
+    pub warmup_epochs: usize,
+    /// Base learning rate of the schedule, that of the optimizer when `None`.
+    pub learning_rate: Option<f64>,
+    /// Trains for all `epochs` and keeps the last weights when `None`.
+    pub early_stopping: Option<EarlyStopping>,
+    pub checkpointing: Checkpointing,
+    /// Continues from the latest checkpoint of the model in the output
+    /// directory, if there is one.
+    pub resume: bool,
+    /// Stops the run after the current batch once requested.
+    pub shutdown: Shutdown,
+}
+
+#[autometrics]
+impl Trainer {
+    pub fn new(epochs: usize, batch_size: usize) -> Trainer {
+        Trainer {
+            epochs,
+            batch_size,
+            output_directory: PathBuf::from("./data/networks"),
+            model_extension: String::from("bin.zst"),
+            seed: None,
+            config: None,
+            schedule: Schedule::Constant,
+            warmup_epochs: 0,
+            learning_rate: None,
+            early_stopping: None,
+            checkpointing: Checkpointing::default(),
+            resume: false,
+            shutdown: Shutdown::default(),
+        }
+    }
+
+    /// Shuffles after `epochs` already trained, so a model continued in a
+    /// later round of a session does not repeat the order of the first.
+    fn rng(&self, epochs: usize) -> RunRng {
+        random::rng_after(self.seed, Stream::Shuffling, epochs as u64)
+    }
+
+    fn learning_rate_schedule(&self, optimizer_learning_rate: f64) -> LearningRateSchedule {
+        LearningRateSchedule::new(
+            self.schedule,
+            self.learning_rate.unwrap_or(optimizer_learning_rate),
+            self.warmup_epochs,
+        )
+    }
+
+    /// Trains the classifier for `epochs`, until it classifies the whole
+    /// validation set right or until early stopping, then tests and saves it.
+    /// Returns the saved file.
+    pub fn train_network<T: Float>(
+        &self,
+        network: &mut Network<T>,
+        data_set: &DataSet,
+    ) -> Result<PathBuf> {
+        let model = network.model();
+        let mut rng = self.rng(network.metadata().epochs);
+        let mut schedule = self.learning_rate_schedule(network.optimizer().learning_rate());
+        let mut best = self.best_epoch(Metric::Accuracy);
+        let mut start = Position::default();
+
+        if let Some(checkpoint) = self.resume_checkpoint(&model)? {
+            let ModelData::Network(data) = checkpoint.model.model else {
+                return Err(incompatible("network", &checkpoint.model.model));
+            };
+            network.restore(data)?;
+            *network.metadata_mut() = checkpoint.model.metadata;
+            rng = checkpoint.rng.rng();
+            schedule = checkpoint.schedule;
+            start = checkpoint.position;
+            resume_best(&mut best, checkpoint.best);
+        }
+
+        log::info!("Start training with {} images", data_set.train_inputs.len());
+
+        for epoch in start.epoch..self.epochs {
+            let now = Instant::now();
+            let first_batch = if epoch == start.epoch { start.batch } else { 0 };
+            log_epoch(epoch, self.epochs, first_batch);
+
+            let learning_rate = schedule.learning_rate(epoch);
+            network.optimizer_mut().set_learning_rate(learning_rate);
+            record_learning_rate(learning_rate);
+
+            let epoch_rng = rng.clone();
+            let success = network.resume_training_epoch(
+                data_set,
+                self.batch_size,
+                &mut rng,
+                first_batch,
+                |network, batch| {
+                    self.after_batch(&model, batch, || {
+                        self.checkpoint(
+                            ModelData::Network(network.to_data()),
+                            network.metadata(),
+                            Position { epoch, batch },
+                            &epoch_rng,
+                            &schedule,
+                            best.as_ref(),
+                        )
+                    })
+                },
+            )?;
+            if let Some(accuracy) = network.metadata().accuracy {
+                schedule.observe(accuracy, true);
+            }
+
+            let stop = best.as_mut().is_some_and(|best| {
+                observe(best, network.metadata(), || {
+                    ModelFile::new(
+                        ModelData::Network(network.to_data()),
+                        network.metadata().clone(),
+                    )
+                })
+            });
+
+            // After observing the epoch, which a resumed run does not repeat
+            if self.checkpointing.after_epoch(epoch + 1) {
+                let checkpoint = self.checkpoint(
+                    ModelData::Network(network.to_data()),
+                    network.metadata(),
+                    Position::after_epoch(epoch),
+                    &rng,
+                    &schedule,
+                    best.as_ref(),
+                );
+                self.write_checkpoint(&model, checkpoint)?;
+            }
+
+            if success {
+                log::info!("Right percentage of 100% reached, will stop training");
+                break;
+            }
+
+            log::info!("Epoch took: {:.2?}", now.elapsed());
+
+            if stop {
+                break;
+            }
+        }
+
+        if let Some(snapshot) = best.and_then(BestEpoch::into_snapshot) {
+            let ModelData::Network(data) = snapshot.model else {
+                return Err(incompatible("network", &snapshot.model));
+            };
+            log::info!("Restoring the model of epoch {}", snapshot.metadata.epochs);
+            network.restore(data)?;
+            *network.metadata_mut() = snapshot.metadata;
+        }
+
+        log::info!("Running final test...");
+
+        let (test_set_size, height, width) = data_set.test_data.dim();
+        let right_percentage = network.validate(
+            &data_set.test_data,
+            &data_set.test_labels,
+            test_set_size as u32,
+            height * width,
+        )?;
+
+        let file_path = self.model_path(&model, right_percentage)?;
+        log::info!("Saving model at path {}", file_path.display());
+        network.save(&file_path)?;
+        self.save_config(&file_path)?;
+        self.remove_checkpoint(&model)?;
+
+        Ok(file_path)
+    }
+
+    /// Trains the autoencoder on the training images for `epochs` or until
+    /// early stopping, then tests and saves it. Returns the saved file.
+    pub fn train_autoencoder<T: Float>(
+        &self,
+        autoencoder: &mut Autoencoder<T>,
+        data_set: &DataSet,
+    ) -> Result<PathBuf> {
+        let model = autoencoder.model();
+        let val_inputs = flatten_images(&data_set.val_data);
+        let test_inputs = flatten_images(&data_set.test_data);
+        let mut rng = self.rng(autoencoder.metadata().epochs);
+        let mut schedule = self.learning_rate_schedule(autoencoder.learning_rate());
+        let mut best = self.best_epoch(Metric::ReconstructionError);
+        let mut start = Position::default();
+
+        if let Some(checkpoint) = self.resume_checkpoint(&model)? {
+            let ModelData::Autoencoder { encoder, decoder } = checkpoint.model.model else {
+                return Err(incompatible("autoencoder", &checkpoint.model.model));
+            };
+            autoencoder.restore(encoder, decoder)?;
+            *autoencoder.metadata_mut() = checkpoint.model.metadata;
+            rng = checkpoint.rng.rng();
+            schedule = checkpoint.schedule;
+            start = checkpoint.position;
+            resume_best(&mut best, checkpoint.best);
+        }
+
+        log::info!("Start training with {} images", data_set.train_inputs.len());
+
+        for epoch in start.epoch..self.epochs {
+            let now = Instant::now();
+            let first_batch = if epoch == start.epoch { start.batch } else { 0 };
+            log_epoch(epoch, self.epochs, first_batch);
+
+            let learning_rate = schedule.learning_rate(epoch);
+            autoencoder.set_learning_rate(learning_rate);
+            record_learning_rate(learning_rate);
+
+            let epoch_rng = rng.clone();
+            let error = autoencoder.resume_training_epoch(
+                &data_set.train_inputs,
+                &val_inputs,
+                self.batch_size,
+                &mut rng,
+                first_batch,
+                |autoencoder, batch| {
+                    self.after_batch(&model, batch, || {
+                        self.checkpoint(
+                            autoencoder.to_data(),
+                            autoencoder.metadata(),
+                            Position { epoch, batch },
+                            &epoch_rng,
+                            &schedule,
+                            best.as_ref(),
+                        )
+                    })
+                },
+            )?;
+            schedule.observe(error, false);
+
+            log::info!("Epoch took: {:.2?}", now.elapsed());
+
+            let stop = best.as_mut().is_some_and(|best| {
+                observe(best, autoencoder.metadata(), || {
+                    ModelFile::new(autoencoder.to_data(), autoencoder.metadata().clone())
+                })
+            });
+
+            // After observing the epoch, see `train_network`
+            if self.checkpointing.after_epoch(epoch + 1) {
+                let checkpoint = self.checkpoint(
+                    autoencoder.to_data(),
+                    autoencoder.metadata(),
+                    Position::after_epoch(epoch),
+                    &rng,
+                    &schedule,
+                    best.as_ref(),
+                );
+                self.write_checkpoint(&model, checkpoint)?;
+            }
+
+            if stop {
+                break;
+            }
+        }
+
+        if let Some(snapshot) = best.and_then(BestEpoch::into_snapshot) {
+            let ModelData::Autoencoder { encoder, decoder } = snapshot.model else {
+                return Err(incompatible("autoencoder", &snapshot.model));
+            };
+            log::info!("Restoring the model of epoch {}", snapshot.metadata.epochs);
+            autoencoder.restore(encoder, decoder)?;
+            *autoencoder.metadata_mut() = snapshot.metadata;
+        }
+
+        log::info!("Running final test...");
+
+        let reconstruction_error = autoencoder.validate(&test_inputs)?;
+
+        let file_path = self.model_path(&model, reconstruction_error)?;
+        log::info!("Saving model at path {}", file_path.display());
+        autoencoder.save(&file_path)?;
+        self.save_config(&file_path)?;
+        self.remove_checkpoint(&model)?;
+
+        Ok(file_path)
+    }
+
+    fn best_epoch<S>(&self, default_metric: Metric) -> Option<BestEpoch<S>> {
+        self.early_stopping
+            .map(|early_stopping| BestEpoch::new(early_stopping, default_metric))
+    }
+
+    fn checkpoint(
+        &self,
+        model: ModelData,
+        metadata: &TrainingMetadata,
+        position: Position,
+        rng: &RunRng,
+        schedule: &LearningRateSchedule,
+        best: Option<&BestEpoch<ModelFile>>,
+    ) -> Checkpoint {
+        Checkpoint {
+            model: ModelFile::new(model, metadata.clone()),
+            position,
+            rng: RngState::from(rng),
+            schedule: schedule.clone(),
+            best: best.and_then(BestEpoch::state),
+        }
+    }
+
+    /// `<output directory>/checkpoints/<model>.<extension>`, overwritten by
+    /// every checkpoint of the run.
+    fn checkpoint_path(&self, model: &str) -> PathBuf {
+        self.output_directory
+            .join(CHECKPOINT_DIRECTORY)
+            .join(format!("{}.{}", model, self.model_extension))
+    }
+
+    fn write_checkpoint(&self, model: &str, checkpoint: Checkpoint) -> Result<()> {
+        let path = self.checkpoint_path(model);
+        create_directory(&self.output_directory.join(CHECKPOINT_DIRECTORY))?;
+
+        log::info!(
+            "Writing checkpoint after {} epochs and {} batches to {}",
+            checkpoint.position.epoch,
+            checkpoint.position.batch,
+            path.display()
+        );
+        checkpoint.write(&path)?;
+
+        Ok(())
+    }
+
+    /// Writes a checkpoint when one is due after `batch`, or when a shutdown
+    /// was requested, then stops with `Error::Interrupted`.
+    fn after_batch(
+        &self,
+        model: &str,
+        batch: usize,
+        checkpoint: impl FnOnce() -> Checkpoint,
+    ) -> Result<()> {
+        let interrupted = self.shutdown.is_requested();
+        if interrupted || self.checkpointing.after_batch(batch) {
+            self.write_checkpoint(model, checkpoint())?;
+        }
+
+        if interrupted {
+            return Err(Error::Interrupted {
+                checkpoint: self.checkpoint_path(model),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The latest checkpoint of `model` when resuming.
+    fn resume_checkpoint(&self, model: &str) -> Result<Option<Checkpoint>> {
+        if !self.resume {
+            return Ok(None);
+        }
+
+        let directory = self.output_directory.join(CHECKPOINT_DIRECTORY);
+        match checkpoint::latest(&directory, model)? {
+            Some(path) => {
+                log::info!("Resuming from checkpoint {}", path.display());
+                Ok(Some(Checkpoint::read(&path)?))
+            }
+            None => {
+                log::info!("No checkpoint of {} to resume, starting fresh", model);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Removes the checkpoint of a finished run, so it is not resumed.
+    fn remove_checkpoint(&self, model: &str) -> Result<()> {
+        let path = self.checkpoint_path(model);
+
+        match fs::remove_file(&path) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(Error::Io {
+                path,
+                source: error,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// `<output directory>/<model>-<timestamp>-<score>.<extension>`
+    fn model_path(&self, model: &str, score: f64) -> Result<PathBuf> {
+        create_directory(&self.output_directory)?;
+
+        Ok(self.output_directory.join(format!(
+            "{}-{}-{}.{}",
+            model,
+            Local::now().format("%Y-%m-%dT%H:%M:%S"),
+            score,
+            self.model_extension
+        )))
+    }
+
+    fn save_config(&self, model_path: &Path) -> Result<()> {
+        if let Some(config) = &self.config {
+            let mut config_path = model_path.as_os_str().to_owned();
+            config_path.push(".config.toml");
+            config.write(PathBuf::from(config_path))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Records the metric of the last epoch in `best`, returning true when
+/// training should stop.
+fn observe<S>(
+    best: &mut BestEpoch<S>,
+    metadata: &TrainingMetadata,
+    snapshot: impl FnOnce() -> S,
+) -> bool {
+    let Some(value) = best.metric.value(metadata) else {
+        return false;
+    };
+
+    let stop = best.observe(value, snapshot);
+    if stop {
+        log::info!(
+            "{:?} of {} did not improve on the best of {}, stopping early",
+            best.metric,
+            value,
+            best.value().unwrap_or(value)
+        );
+    }
+
+    stop
+}
+
+/// Continues early stopping from the state saved in a checkpoint, if both the
+/// run and the checkpoint have one.
+fn resume_best(best: &mut Option<BestEpoch<ModelFile>>, state: Option<BestState<ModelFile>>) {
+    if let (Some(best), Some(state)) = (best.as_mut(), state) {
+        best.restore(state);
+    }
+}
+
+fn incompatible(expected: &str, found: &ModelData) -> Error {
+    Error::ModelFile(ModelFileError::Incompatible {
+        expected: format!("{} checkpoint", expected),
+        found: format!("{} checkpoint", found.kind()),
+    })
+}
+
+fn log_epoch(epoch: usize, epochs: usize, first_batch: usize) {
+    if first_batch > 0 {
+        log::info!(
+            "[Training] Epoch {} of {}, resuming after batch {}",
+            epoch + 1,
+            epochs,
+            first_batch
+        );
+    } else {
+        log::info!("[Training] Epoch {} of {}", epoch + 1, epochs);
+    }
+}
+
+fn record_learning_rate(learning_rate: f64) {
+    log::info!("Learning rate: {}", learning_rate);
+    metrics_logger::set_learning_rate(learning_rate);
+}
+
+fn create_directory(directory: &Path) -> Result<()> {
+    fs::create_dir_all(directory).map_err(|source| Error::Io {
+        path: directory.to_path_buf(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array2, Array3};
+
+    use crate::{
+        activations::{SIGMOID, SOFTMAX},
+        losses::CrossEntropy,
+        model_file::ModelFile,
+        network::dense_layers,
+        optimizers::{Adam, Sgd},
+    };
+
+    use super::*;
+
+    /// Two 1x2 "images", one per class.
+    fn data_set() -> DataSet {
+        let images = Array3::from_shape_vec((2, 1, 2), vec![1.0, 0.0, 0.0, 1.0]).unwrap();
+        let labels = Array2::from_shape_vec((2, 1), vec![0.0, 1.0]).unwrap();
+
+        DataSet {
+            val_data: images.clone(),
+            val_labels: labels.clone(),
+            train_inputs: vec![vec![1.0, 0.0], vec![0.0, 1.0]],
+            train_targets: vec![
+                vec![1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                vec![0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            ],
+            test_data: images,
+            test_labels: labels,
+        }
+    }
+
+    #[test]
+    fn test_train_network_saves_model() {
+        let mut trainer = Trainer::new(2, 2);
+        trainer.output_directory = std::env::temp_dir().join("autoencoder-trainer");
+        trainer.model_extension = String::from("json");
+
+        let mut network = Network::new(
+            vec![2, 3, 10],
+            Box::new(Sgd::new(0.1)),
+            vec![SIGMOID, SOFTMAX],
+            Box::new(CrossEntropy),
+        );
+
+        let file_path = trainer.train_network(&mut network, &data_set()).unwrap();
+        let saved = Network::<f64>::from_file(&file_path).unwrap();
+
+        assert!(file_path.starts_with(&trainer.output_directory));
+        assert_eq!(saved.model(), "2-3-10");
+        assert!(saved.metadata().epochs >= 1);
+    }
+
+    #[test]
+    fn test_schedule_sets_learning_rate() {
+        let mut trainer = Trainer::new(1, 2);
+        trainer.output_directory = std::env::temp_dir().join("autoencoder-trainer-schedule");
+        trainer.model_extension = String::from("json");
+        trainer.schedule = Schedule::Exponential { gamma: 0.5 };
+        trainer.warmup_epochs = 1;
+
+        let mut network = Network::new(
+            vec![2, 3, 10],
+            Box::new(Sgd::new(0.1)),
+            vec![SIGMOID, SOFTMAX],
+            Box::new(CrossEntropy),
+        );
+
+        trainer.train_network(&mut network, &data_set()).unwrap();
+
+        // Half the base rate in the first of two warmup steps
+        assert!((network.optimizer().learning_rate() - 0.05).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_early_stopping_restores_best_epoch() {
+        let mut trainer = Trainer::new(10, 2);
+        trainer.output_directory = std::env::temp_dir().join("autoencoder-trainer-early");
+        trainer.model_extension = String::from("json");
+        // No later epoch can improve the loss by this much
+        trainer.early_stopping = Some(EarlyStopping {
+            metric: Some(Metric::Loss),
+            patience: 1,
+            min_delta: 1000.0,
+            restore_best: true,
+        });
+
+        let mut network = Network::new(
+            vec![2, 3, 10],
+            Box::new(Sgd::new(0.01)),
+            vec![SIGMOID, SOFTMAX],
+            Box::new(CrossEntropy),
+        );
+
+        let file_path = trainer.train_network(&mut network, &data_set()).unwrap();
+        let saved = Network::<f64>::from_file(&file_path).unwrap();
+
+        assert_eq!(network.metadata().epochs, 1);
+        assert_eq!(saved.metadata().epochs, 1);
+    }
+
+    #[test]
+    fn test_config_saved_next_to_model() {
+        let mut config = ExperimentConfig::default().resolve();
+        config.output.directory = std::env::temp_dir().join("autoencoder-trainer-config");
+        config.output.format = String::from("json");
+        let trainer = config.trainer();
+
+        let mut network = Network::new(
+            vec![2, 3, 10],
+            Box::new(Sgd::new(0.1)),
+            vec![SIGMOID, SOFTMAX],
+            Box::new(CrossEntropy),
+        );
+
+        let file_path = trainer.train_network(&mut network, &data_set()).unwrap();
+        let mut config_path = file_path.into_os_string();
+        config_path.push(".config.toml");
+
+        assert_eq!(
+            ExperimentConfig::load(PathBuf::from(config_path)).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn test_resumes_mid_epoch_like_an_uninterrupted_run() {
+        // Dropout draws from its own generator, which the checkpoint restores
+        let network = || {
+            Network::<f64>::from_layers(
+                dense_layers(
+                    &[2, 3, 10],
+                    &[SIGMOID, SOFTMAX],
+                    &[0.3, 0.5],
+                    None,
+                    &mut random::rng(Some(5), Stream::Initialization),
+                ),
+                Box::new(Adam::new(0.01)),
+                Box::new(CrossEntropy),
+            )
+        };
+        let mut trainer = Trainer::new(4, 1);
+        trainer.output_directory = std::env::temp_dir().join("autoencoder-trainer-resume");
+        trainer.model_extension = String::from("json");
+        trainer.seed = Some(5);
+        // The first epoch stays the best, so training stops after the third
+        // and restores the first, unless the resumed run forgets it
+        trainer.early_stopping = Some(EarlyStopping {
+            metric: Some(Metric::Loss),
+            patience: 1,
+            min_delta: 1000.0,
+            restore_best: true,
+        });
+
+        let mut expected = network();
+        trainer.train_network(&mut expected, &data_set()).unwrap();
+        assert_eq!(expected.metadata().epochs, 1);
+
+        // A run that crashes in the second epoch after checkpointing its first
+        // batch
+        let mut interrupted = network();
+        let mut rng = trainer.rng(0);
+        let mut best = trainer.best_epoch(Metric::Accuracy).unwrap();
+        interrupted
+            .resume_training_epoch(&data_set(), 1, &mut rng, 0, |_, _| Ok(()))
+            .unwrap();
+        observe(&mut best, interrupted.metadata(), || {
+            ModelFile::new(
+                ModelData::Network(interrupted.to_data()),
+                interrupted.metadata().clone(),
+            )
+        });
+
+        let epoch_rng = rng.clone();
+        let checkpoint_path = trainer.checkpoint_path(&interrupted.model());
+        fs::create_dir_all(checkpoint_path.parent().unwrap()).unwrap();
+        let result =
+            interrupted.resume_training_epoch(&data_set(), 1, &mut rng, 0, |network, batch| {
+                let checkpoint = trainer.checkpoint(
+                    ModelData::Network(network.to_data()),
+                    network.metadata(),
+                    Position { epoch: 1, batch },
+                    &epoch_rng,
+                    &trainer.learning_rate_schedule(0.01),
+                    Some(&best),
+                );
+                checkpoint.write(&checkpoint_path)?;
+                Err(Error::EmptyResult)
+            });
+        assert!(result.is_err());
+
+        trainer.resume = true;
+        let mut resumed = network();
+        trainer.train_network(&mut resumed, &data_set()).unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&resumed.to_data()).unwrap(),
+            serde_json::to_string(&expected.to_data()).unwrap()
+        );
+        assert_eq!(resumed.metadata().epochs, expected.metadata().epochs);
+        // A finished run leaves no checkpoint to resume
+        assert!(!checkpoint_path.exists());
+    }
+
+    #[test]
+    fn test_shutdown_checkpoints_after_the_current_batch() {
+        let mut trainer = Trainer::new(2, 1);
+        trainer.output_directory = std::env::temp_dir().join("autoencoder-trainer-shutdown");
+        trainer.model_extension = String::from("json");
+        trainer.shutdown.request();
+
+        let mut network = Network::new(
+            vec![2, 3, 10],
+            Box::new(Sgd::new(0.1)),
+            vec![SIGMOID, SOFTMAX],
+            Box::new(CrossEntropy),
+        );
+
+        let Err(Error::Interrupted { checkpoint }) =
+            trainer.train_network(&mut network, &data_set())
+        else {
+            panic!("training should be interrupted");
+        };
+
+        assert_eq!(checkpoint, trainer.checkpoint_path(&network.model()));
+        let position = Checkpoint::read(&checkpoint).unwrap().position;
+        assert_eq!(position, Position { epoch: 0, batch: 1 });
+    }
+
+    #[test]
+    fn test_seeded_runs_are_identical() {
+        let run = || {
+            let mut trainer = Trainer::new(3, 1);
+            trainer.output_directory = std::env::temp_dir().join("autoencoder-trainer-seeded");
+            trainer.model_extension = String::from("json");
+            trainer.seed = Some(11);
+
+            let mut network = Network::<f64>::with_rng(
+                vec![2, 3, 10],
+                Box::new(Adam::new(0.01)),
+                vec![SIGMOID, SOFTMAX],
+                Box::new(CrossEntropy),
+                &mut random::rng(trainer.seed, Stream::Initialization),
+            );
+
+            let file_path = trainer.train_network(&mut network, &data_set()).unwrap();
+            let mut model_file = ModelFile::read(file_path).unwrap();
+            // Only the time of saving may differ
+            model_file.metadata.timestamp = None;
+            serde_json::to_string(&model_file).unwrap()
+        };
+
+        assert_eq!(run(), run());
+    }
+}
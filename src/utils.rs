@@ -1,6 +1,8 @@
 use autometrics::autometrics;
 use rayon::prelude::*;
 
+use super::error::{Error, Result};
+
 #[autometrics]
 pub fn convert_number_to_target_vec(num: usize) -> Vec<f64> {
     let mut v = vec![0.0; 10];
@@ -20,10 +22,12 @@ fn find_max_index(v: Vec<f64>) -> Option<usize> {
 
 #[autometrics]
 pub fn convert_result_vec_to_number(result_vec: Vec<f64>) -> usize {
-    match find_max_index(result_vec) {
-        Some(index) => index,
-        None => panic!("Invalid inputs length"),
-    }
+    try_convert_result_vec_to_number(result_vec).expect("Invalid inputs length")
+}
+
+#[autometrics]
+pub fn try_convert_result_vec_to_number(result_vec: Vec<f64>) -> Result<usize> {
+    find_max_index(result_vec).ok_or(Error::EmptyResult)
 }
 
 #[cfg(test)]
@@ -78,4 +82,12 @@ mod tests {
         let result_vec = vec![];
         convert_result_vec_to_number(result_vec); // This should panic
     }
+
+    #[test]
+    fn test_try_convert_result_vec_to_number_empty() {
+        assert!(matches!(
+            try_convert_result_vec_to_number(vec![]),
+            Err(Error::EmptyResult)
+        ));
+    }
 }